@@ -0,0 +1,386 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Error, Result};
+use flate2::read::GzDecoder;
+use tar_wasi::Archive;
+use zip::ZipArchive;
+
+/// Archive formats serve-d releases may ship in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarXz,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Archive kind serve-d publishes for the given OS
+    pub fn for_os(os_name: &str) -> Self {
+        if os_name == "windows" {
+            ArchiveKind::Zip
+        } else {
+            ArchiveKind::TarXz
+        }
+    }
+
+    /// File extension used in asset names
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveKind::Zip => "zip",
+            ArchiveKind::TarXz => "tar.xz",
+            ArchiveKind::TarGz => "tar.gz",
+        }
+    }
+}
+
+const ZIP_MAGIC: &[u8] = b"PK";
+const XZ_MAGIC: &[u8] = b"\xFD7zXZ";
+const GZIP_MAGIC: &[u8] = b"\x1F\x8B";
+
+/// Sniff the archive kind from the leading magic bytes
+pub fn detect_archive_kind(data: &[u8]) -> Option<ArchiveKind> {
+    if data.starts_with(ZIP_MAGIC) {
+        Some(ArchiveKind::Zip)
+    } else if data.starts_with(XZ_MAGIC) {
+        Some(ArchiveKind::TarXz)
+    } else if data.starts_with(GZIP_MAGIC) {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether the data looks like an HTML page, e.g. a proxy error page
+fn looks_like_html(data: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&data[..data.len().min(512)]).to_ascii_lowercase();
+    let start = start.trim_start();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Check that the downloaded data is the archive kind we expect
+pub fn validate_archive(data: &[u8], expected: ArchiveKind) -> Result<()> {
+    match detect_archive_kind(data) {
+        Some(kind) if kind == expected => Ok(()),
+        Some(kind) => Err(Error::msg(format!(
+            "Downloaded archive is {} but {} was expected",
+            kind.extension(),
+            expected.extension()
+        ))),
+        None if looks_like_html(data) => Err(Error::msg(
+            "Download returned an HTML page instead of an archive, check the download mirror",
+        )),
+        None => Err(Error::msg(format!(
+            "Downloaded file is not a {} archive",
+            expected.extension()
+        ))),
+    }
+}
+
+/// Whether `path` matches `pattern`, where `*` matches within a path
+/// component, `**` any number of components and `?` a single character
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            [b'*', b'*', rest @ ..] => match rest.strip_prefix(b"/") {
+                Some(rest) => {
+                    matches(rest, path)
+                        || (0..path.len()).any(|i| path[i] == b'/' && matches(rest, &path[i + 1..]))
+                }
+                None => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+            },
+            [b'*', rest @ ..] => (0..=path.len())
+                .take_while(|&i| !path[..i].contains(&b'/'))
+                .any(|i| matches(rest, &path[i..])),
+            [b'?', rest @ ..] => match path {
+                [c, path @ ..] => *c != b'/' && matches(rest, path),
+                [] => false,
+            },
+            [c, rest @ ..] => match path {
+                [p, path @ ..] => c == p && matches(rest, path),
+                [] => false,
+            },
+        }
+    }
+
+    matches(pattern.as_bytes(), path.as_bytes())
+}
+
+/// Whether an archive entry gets extracted given the `lsp.extractOnly`
+/// globs, the binary `exec_file` always is
+pub fn keep_entry(path: &Path, only: &[String], exec_file: &str) -> bool {
+    if only.is_empty() || path.file_name() == Some(OsStr::new(exec_file)) {
+        return true;
+    }
+
+    let path = path.to_string_lossy().replace('\\', "/");
+    only.iter().any(|pattern| glob_match(pattern, &path))
+}
+
+/// File extracted from an archive
+pub struct ExtractedFile {
+    /// Path relative to the extraction directory
+    pub path: PathBuf,
+    /// Unix mode stored in the archive
+    pub mode: Option<u32>,
+}
+
+/// Validate and extract the archive entries `keep` takes into `dest`
+pub fn extract_archive(
+    data: Vec<u8>,
+    kind: ArchiveKind,
+    dest: &Path,
+    keep: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<ExtractedFile>> {
+    validate_archive(&data, kind)?;
+
+    // Files are recorded before they're written, so a failed extraction
+    // can remove the partially written one as well
+    let mut files = Vec::new();
+    if let Err(err) = extract_entries(data, kind, dest, keep, &mut files) {
+        for file in &files {
+            let _ = fs::remove_file(dest.join(&file.path));
+        }
+        return Err(err);
+    }
+
+    Ok(files)
+}
+
+fn extract_entries(
+    data: Vec<u8>,
+    kind: ArchiveKind,
+    dest: &Path,
+    keep: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<ExtractedFile>,
+) -> Result<()> {
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(Cursor::new(data))?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                let path = match entry.enclosed_name() {
+                    Some(path) if !entry.is_dir() && keep(path) => path.to_path_buf(),
+                    _ => continue,
+                };
+
+                let dest_path = dest.join(&path);
+                files.push(ExtractedFile {
+                    path,
+                    mode: entry.unix_mode(),
+                });
+                if let Some(parent) = dest_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                io::copy(&mut entry, &mut File::create(dest_path)?)?;
+            }
+        }
+        ArchiveKind::TarXz => {
+            // lzma-rs only decodes a whole xz stream at once
+            let mut tar = Vec::new();
+            lzma_rs::xz_decompress(&mut Cursor::new(data), &mut tar).map_err(|err| {
+                Error::msg(format!("Failed to decompress tar.xz archive: {}", err))
+            })?;
+            extract_tar(Cursor::new(tar), dest, keep, files)?;
+        }
+        ArchiveKind::TarGz => {
+            extract_tar(GzDecoder::new(Cursor::new(data)), dest, keep, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar(
+    tar: impl Read,
+    dest: &Path,
+    keep: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<ExtractedFile>,
+) -> Result<()> {
+    let mut archive = Archive::new(tar);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let is_file = entry.header().entry_type().is_file();
+        if is_file && !keep(&path) {
+            continue;
+        }
+
+        if is_file {
+            files.push(ExtractedFile {
+                path,
+                mode: entry.header().mode().ok(),
+            });
+        }
+        entry.unpack_in(dest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::mock::TestDir;
+
+    #[test]
+    fn detects_archive_kinds_by_magic() {
+        assert_eq!(
+            detect_archive_kind(b"PK\x03\x04rest"),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(
+            detect_archive_kind(b"\xFD7zXZ\x00rest"),
+            Some(ArchiveKind::TarXz)
+        );
+        assert_eq!(
+            detect_archive_kind(b"\x1F\x8B\x08rest"),
+            Some(ArchiveKind::TarGz)
+        );
+    }
+
+    #[test]
+    fn detects_nothing_in_other_data() {
+        assert_eq!(detect_archive_kind(b""), None);
+        assert_eq!(detect_archive_kind(b"P"), None);
+        assert_eq!(detect_archive_kind(b"<!DOCTYPE html>"), None);
+    }
+
+    #[test]
+    fn rejects_html_error_pages() {
+        let err = validate_archive(b"  <html><body>502</body></html>", ArchiveKind::Zip)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("HTML page"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_the_wrong_archive_kind() {
+        assert!(validate_archive(b"PK\x03\x04", ArchiveKind::Zip).is_ok());
+        assert!(validate_archive(b"PK\x03\x04", ArchiveKind::TarXz).is_err());
+    }
+
+    #[test]
+    fn star_matches_within_a_component() {
+        assert!(glob_match("*.so", "libphobos.so"));
+        assert!(glob_match("lib/*.so", "lib/libphobos.so"));
+        assert!(!glob_match("*.so", "lib/libphobos.so"));
+        assert!(!glob_match("*.so", "libphobos.so.1"));
+    }
+
+    #[test]
+    fn double_star_matches_any_components() {
+        assert!(glob_match("**/*.so", "libphobos.so"));
+        assert!(glob_match("**/*.so", "lib/x86_64/libphobos.so"));
+        assert!(glob_match("**", "a/b/c"));
+        assert!(glob_match("lib/**", "lib/a/b"));
+        assert!(!glob_match("lib/**/*.so", "bin/libphobos.so"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("dcd-?erver", "dcd-server"));
+        assert!(!glob_match("dcd-?erver", "dcd-erver"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn keeps_the_binary_and_matching_entries() {
+        let only = vec!["lib/*.so".to_string()];
+        assert!(keep_entry(Path::new("bin/serve-d"), &only, "serve-d"));
+        assert!(keep_entry(Path::new("lib/libdparse.so"), &only, "serve-d"));
+        assert!(keep_entry(Path::new("lib\\libdparse.so"), &only, "serve-d"));
+        assert!(!keep_entry(Path::new("README.md"), &only, "serve-d"));
+        assert!(keep_entry(Path::new("README.md"), &[], "serve-d"));
+    }
+
+    /// Compressed tarball of `files`, by path and mode
+    fn tar_archive(files: &[(&str, &[u8], u32)], kind: ArchiveKind) -> Vec<u8> {
+        let mut builder = tar_wasi::Builder::new(Vec::new());
+        for (path, data, mode) in files {
+            let mut header = tar_wasi::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(*mode);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+
+        match kind {
+            ArchiveKind::TarXz => {
+                let mut xz = Vec::new();
+                lzma_rs::xz_compress(&mut Cursor::new(tar), &mut xz).unwrap();
+                xz
+            }
+            ArchiveKind::TarGz => {
+                let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+                gz.write_all(&tar).unwrap();
+                gz.finish().unwrap()
+            }
+            ArchiveKind::Zip => unreachable!("zip is not a tarball"),
+        }
+    }
+
+    #[test]
+    fn extracts_compressed_tarballs() {
+        for kind in [ArchiveKind::TarXz, ArchiveKind::TarGz] {
+            let dest = TestDir::new("compressed-tarball");
+            let data = tar_archive(&[("bin/serve-d", b"serve-d", 0o755)], kind);
+
+            let files = extract_archive(data, kind, &dest, &|_| true).unwrap();
+            assert_eq!(files.len(), 1);
+            assert_eq!(fs::read(dest.join("bin/serve-d")).unwrap(), b"serve-d");
+        }
+    }
+
+    #[test]
+    fn rejects_a_tarball_that_is_not_compressed() {
+        let dest = TestDir::new("uncompressed-tarball");
+        let mut builder = tar_wasi::Builder::new(Vec::new());
+        let mut header = tar_wasi::Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "serve-d", io::empty())
+            .unwrap();
+
+        let data = builder.into_inner().unwrap();
+        assert!(extract_archive(data, ArchiveKind::TarXz, &dest, &|_| true).is_err());
+        assert!(!dest.join("serve-d").exists());
+    }
+
+    #[test]
+    fn records_the_modes_stored_in_a_tar() {
+        let dest = TestDir::new("tar-modes");
+        let data = tar_archive(
+            &[
+                ("serve-d", b"serve-d", 0o755),
+                ("docs/README.md", b"# serve-d", 0o644),
+            ],
+            ArchiveKind::TarXz,
+        );
+
+        let files = extract_archive(data, ArchiveKind::TarXz, &dest, &|_| true).unwrap();
+        let modes: Vec<(PathBuf, Option<u32>)> = files
+            .into_iter()
+            .map(|file| (file.path, file.mode))
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                (PathBuf::from("serve-d"), Some(0o755)),
+                (PathBuf::from("docs/README.md"), Some(0o644)),
+            ]
+        );
+        assert!(dest.join("docs/README.md").is_file());
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Error, Result};
+
+use crate::{manifest, release::GHReleaseAsset};
+
+/// Checksum file published next to the release archive `archive_name`
+pub fn checksum_asset<'a>(
+    assets: &'a [GHReleaseAsset],
+    archive_name: &str,
+) -> Option<&'a GHReleaseAsset> {
+    let name = format!("{}.sha256", archive_name);
+    assets.iter().find(|candidate| candidate.name == name)
+}
+
+/// Names of a release asset listing the checksums of all the others
+const MANIFEST_ASSET_NAMES: &[&str] = &["checksums.txt", "sha256sums.txt", "SHA256SUMS"];
+
+/// Checksum manifest published with a release, covering all of its assets
+pub fn manifest_asset(assets: &[GHReleaseAsset]) -> Option<&GHReleaseAsset> {
+    assets.iter().find(|asset| {
+        MANIFEST_ASSET_NAMES
+            .iter()
+            .any(|name| asset.name.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Lowercase hash if `word` is a hex SHA-256
+fn sha256_word(word: &str) -> Option<String> {
+    if word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(word.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Hex SHA-256 from a `.sha256` file, which holds either the bare hash or
+/// `sha256sum` output of the asset
+pub fn parse_checksum_file(data: &[u8]) -> Option<String> {
+    sha256_word(String::from_utf8_lossy(data).split_whitespace().next()?)
+}
+
+/// Hex SHA-256 of `asset_name` from `sha256sum` style lines of
+/// `hash  filename`, ignoring any other lines, e.g. of release notes
+///
+/// File names may carry the `*` of binary mode or a leading directory.
+pub fn parse_checksum_manifest(text: &str, asset_name: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        let hash = sha256_word(words.next()?)?;
+        let file_name = words.next()?.trim_start_matches('*');
+        let file_name = file_name.rsplit('/').next().unwrap_or(file_name);
+        (file_name == asset_name && words.next().is_none()).then_some(hash)
+    })
+}
+
+/// Everywhere a release may publish the checksum of an asset
+pub struct ChecksumSources<'a> {
+    /// The `<asset>.sha256` file
+    pub asset_file: Option<&'a [u8]>,
+    /// A `checksums.txt` style manifest asset, see [`manifest_asset`]
+    pub manifest: Option<&'a [u8]>,
+    /// The release notes
+    pub release_body: Option<&'a str>,
+}
+
+/// Published checksum of `asset_name`, from the first source that has it
+pub fn find_checksum(sources: &ChecksumSources, asset_name: &str) -> Option<String> {
+    sources
+        .asset_file
+        .and_then(parse_checksum_file)
+        .or_else(|| {
+            parse_checksum_manifest(&String::from_utf8_lossy(sources.manifest?), asset_name)
+        })
+        .or_else(|| parse_checksum_manifest(sources.release_body?, asset_name))
+}
+
+/// Check downloaded asset `data` against its published checksum
+pub fn verify(asset_name: &str, data: &[u8], expected: &str) -> Result<()> {
+    let actual = manifest::sha256_hex(data);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "{} does not match its published checksum (expected {}, got {})",
+            asset_name, expected, actual
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARCHIVE: &str = "serve-d_0.7.6-linux-x86_64.tar.xz";
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const OTHER_HASH: &str = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752";
+
+    #[test]
+    fn reads_bare_and_sha256sum_checksum_files() {
+        assert_eq!(parse_checksum_file(HASH.as_bytes()), Some(HASH.to_string()));
+        let sha256sum = format!("{}  {}\n", HASH.to_ascii_uppercase(), ARCHIVE);
+        assert_eq!(
+            parse_checksum_file(sha256sum.as_bytes()),
+            Some(HASH.to_string())
+        );
+        assert_eq!(parse_checksum_file(b"not a checksum"), None);
+    }
+
+    #[test]
+    fn finds_the_asset_in_a_manifest() {
+        let manifest = format!(
+            "{}  serve-d_0.7.6-windows-x86_64.zip\n{} *dist/{}\n",
+            OTHER_HASH, HASH, ARCHIVE
+        );
+        assert_eq!(
+            parse_checksum_manifest(&manifest, ARCHIVE),
+            Some(HASH.to_string())
+        );
+        assert_eq!(parse_checksum_manifest(&manifest, "serve-d.zip"), None);
+    }
+
+    #[test]
+    fn takes_the_first_source_with_a_checksum() {
+        let manifest = format!("{}  {}\n", OTHER_HASH, ARCHIVE);
+        let body = format!("## Checksums\n\n{}  {}\n", HASH, ARCHIVE);
+
+        let sources = ChecksumSources {
+            asset_file: None,
+            manifest: Some(manifest.as_bytes()),
+            release_body: Some(&body),
+        };
+        assert_eq!(
+            find_checksum(&sources, ARCHIVE),
+            Some(OTHER_HASH.to_string())
+        );
+
+        let sources = ChecksumSources {
+            asset_file: None,
+            manifest: Some(b"no checksums here"),
+            release_body: Some(&body),
+        };
+        assert_eq!(find_checksum(&sources, ARCHIVE), Some(HASH.to_string()));
+
+        let sources = ChecksumSources {
+            asset_file: Some(HASH.as_bytes()),
+            manifest: Some(manifest.as_bytes()),
+            release_body: None,
+        };
+        assert_eq!(find_checksum(&sources, ARCHIVE), Some(HASH.to_string()));
+    }
+
+    #[test]
+    fn release_notes_mentioning_the_asset_are_not_checksums() {
+        let body = format!("Fixed {} failing to extract on {}\n", ARCHIVE, HASH);
+        let sources = ChecksumSources {
+            asset_file: None,
+            manifest: None,
+            release_body: Some(&body),
+        };
+        assert_eq!(find_checksum(&sources, ARCHIVE), None);
+    }
+}
@@ -0,0 +1,168 @@
+use serde_json::{Map, Value};
+
+use crate::host::Host;
+
+/// D compilers serve-d may take the standard library from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compiler {
+    Dmd,
+    Ldc,
+    Gdc,
+}
+
+/// Compilers probed in order when the user doesn't pick one
+const PROBE_ORDER: &[Compiler] = &[Compiler::Dmd, Compiler::Ldc, Compiler::Gdc];
+
+impl Compiler {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dmd" => Some(Compiler::Dmd),
+            "ldc" | "ldc2" => Some(Compiler::Ldc),
+            "gdc" => Some(Compiler::Gdc),
+            _ => None,
+        }
+    }
+
+    /// Executable name of the compiler
+    pub fn executable(&self) -> &'static str {
+        match self {
+            Compiler::Dmd => "dmd",
+            Compiler::Ldc => "ldc2",
+            Compiler::Gdc => "gdc",
+        }
+    }
+}
+
+/// The compiler named by the user, otherwise the first one found on PATH
+pub fn detect_compiler(host: &dyn Host, preferred: Option<&str>) -> Option<Compiler> {
+    if let Some(preferred) = preferred {
+        return Compiler::parse(preferred);
+    }
+
+    PROBE_ORDER.iter().copied().find(|compiler| {
+        host.execute(compiler.executable(), &["--version"])
+            .map(|output| output.success)
+            .unwrap_or(false)
+    })
+}
+
+/// Import path of GDC's phobos and druntime, from the output of
+/// `gdc -print-file-name=include/d`
+///
+/// GCC echoes the name back unchanged when it can't find the file, so only
+/// absolute paths are taken.
+pub fn gdc_stdlib_path(print_file_name: &str) -> Option<String> {
+    let path = print_file_name.trim();
+    let absolute = path.starts_with('/') || path.get(1..3) == Some(":\\");
+    absolute.then(|| path.to_string())
+}
+
+/// Standard library import paths of the compiler
+///
+/// serve-d finds the dmd and ldc standard libraries next to the compiler on
+/// its own, GDC keeps them inside the GCC installation.
+pub fn stdlib_paths(host: &dyn Host, compiler: Compiler) -> Option<Vec<String>> {
+    match compiler {
+        Compiler::Dmd | Compiler::Ldc => None,
+        Compiler::Gdc => {
+            let output = host
+                .execute(compiler.executable(), &["-print-file-name=include/d"])
+                .ok()?;
+            if !output.success {
+                return None;
+            }
+            gdc_stdlib_path(&String::from_utf8(output.stdout).ok()?).map(|path| vec![path])
+        }
+    }
+}
+
+/// serve-d settings pointing it at the standard library
+///
+/// `overrides` (`lsp.stdlibPaths`) win over the paths detected for the
+/// compiler.
+pub fn stdlib_settings(
+    host: &dyn Host,
+    compiler: Option<&str>,
+    overrides: Option<Vec<String>>,
+) -> Value {
+    let paths = overrides.or_else(|| stdlib_paths(host, detect_compiler(host, compiler)?));
+
+    let mut settings = Map::new();
+    if let Some(paths) = paths {
+        let mut d = Map::new();
+        d.insert(
+            "stdlibPath".to_string(),
+            Value::Array(paths.into_iter().map(Value::String).collect()),
+        );
+        settings.insert("d".to_string(), Value::Object(d));
+    }
+    Value::Object(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::MockHost;
+
+    const GDC_INCLUDE: &str = "/usr/lib/gcc/x86_64-linux-gnu/13/include/d";
+
+    fn host(programs: &[(&str, &str)]) -> MockHost {
+        MockHost {
+            programs: programs
+                .iter()
+                .map(|(command, stdout)| (command.to_string(), stdout.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn takes_only_absolute_gdc_paths() {
+        assert_eq!(
+            gdc_stdlib_path(&format!("{}\n", GDC_INCLUDE)),
+            Some(GDC_INCLUDE.to_string())
+        );
+        assert_eq!(
+            gdc_stdlib_path("C:\\gcc\\include\\d"),
+            Some("C:\\gcc\\include\\d".to_string())
+        );
+        // Echoed back when GCC has no D headers
+        assert_eq!(gdc_stdlib_path("include/d\n"), None);
+    }
+
+    #[test]
+    fn points_serve_d_at_the_gdc_stdlib() {
+        let host = host(&[
+            ("gdc --version", "gdc (GCC) 13.2.0"),
+            ("gdc -print-file-name=include/d", GDC_INCLUDE),
+        ]);
+        assert_eq!(detect_compiler(&host, None), Some(Compiler::Gdc));
+        assert_eq!(
+            stdlib_settings(&host, None, None),
+            json!({ "d": { "stdlibPath": [GDC_INCLUDE] } })
+        );
+        assert_eq!(
+            stdlib_settings(&host, None, Some(vec!["/opt/phobos".to_string()])),
+            json!({ "d": { "stdlibPath": ["/opt/phobos"] } })
+        );
+    }
+
+    #[test]
+    fn leaves_the_dmd_and_ldc_stdlib_to_serve_d() {
+        let host = host(&[
+            ("dmd --version", "DMD64 D Compiler v2.106.0"),
+            ("gdc --version", "gdc (GCC) 13.2.0"),
+            ("gdc -print-file-name=include/d", GDC_INCLUDE),
+        ]);
+        assert_eq!(detect_compiler(&host, None), Some(Compiler::Dmd));
+        assert_eq!(stdlib_settings(&host, None, None), json!({}));
+        assert_eq!(detect_compiler(&host, Some("ldc2")), Some(Compiler::Ldc));
+        assert_eq!(
+            stdlib_settings(&host, Some("gdc"), None),
+            json!({ "d": { "stdlibPath": [GDC_INCLUDE] } })
+        );
+        assert_eq!(detect_compiler(&MockHost::default(), None), None);
+    }
+}
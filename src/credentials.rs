@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+/// Location of the user's netrc file
+///
+/// `$NETRC` wins, otherwise `~/.netrc` (`%USERPROFILE%\_netrc` on Windows).
+pub fn netrc_path(os: &str, env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(netrc) = env("NETRC").filter(|netrc| !netrc.is_empty()) {
+        return Some(PathBuf::from(netrc));
+    }
+
+    match os {
+        "windows" => env("USERPROFILE").map(|home| PathBuf::from(home).join("_netrc")),
+        _ => env("HOME").map(|home| PathBuf::from(home).join(".netrc")),
+    }
+}
+
+/// Password of the first of `machines` with an entry in netrc `contents`,
+/// falling back to the `default` entry
+pub fn parse_netrc(contents: &str, machines: &[&str]) -> Option<String> {
+    let mut tokens = contents.split_whitespace();
+    let mut current: Option<&str> = None;
+    let mut passwords = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => current = tokens.next(),
+            "default" => current = Some(""),
+            "password" => {
+                let password = tokens.next()?;
+                if let Some(name) = current {
+                    passwords.push((name, password));
+                }
+            }
+            // Macros run until an empty line, which whitespace splitting
+            // loses, so stop rather than misread their bodies
+            "macdef" => break,
+            _ => {}
+        }
+    }
+
+    machines
+        .iter()
+        .copied()
+        .chain([""])
+        .find_map(|machine| passwords.iter().find(|(name, _)| *name == machine))
+        .map(|(_, password)| password.to_string())
+}
+
+/// Resolve the GitHub token by precedence: explicit option, then the
+/// `GITHUB_TOKEN` environment variable, then the netrc entry of `web_host`,
+/// e.g. `github.com`, or of `api_host`
+///
+/// An empty option or variable counts as unset.
+pub fn github_token(
+    option: Option<&str>,
+    env: impl Fn(&str) -> Option<String>,
+    netrc: Option<&str>,
+    web_host: &str,
+    api_host: &str,
+) -> Option<String> {
+    option
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .or_else(|| env("GITHUB_TOKEN").filter(|token| !token.is_empty()))
+        .or_else(|| parse_netrc(netrc?, &[web_host, api_host]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "\
+machine api.github.com login octocat password api-token
+default login anonymous password default-token
+machine github.com
+    login octocat
+    password web-token
+";
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn netrc_prefers_the_first_listed_machine() {
+        assert_eq!(
+            parse_netrc(NETRC, &["github.com", "api.github.com"]),
+            Some("web-token".to_string())
+        );
+        assert_eq!(
+            parse_netrc(NETRC, &["api.github.com", "github.com"]),
+            Some("api-token".to_string())
+        );
+        assert_eq!(
+            parse_netrc(NETRC, &["github.example.com"]),
+            Some("default-token".to_string())
+        );
+        assert_eq!(
+            parse_netrc("machine github.com login octocat", &["github.com"]),
+            None
+        );
+    }
+
+    #[test]
+    fn netrc_stops_at_macros() {
+        let netrc = "macdef init\nmachine github.com password macro-token\n";
+        assert_eq!(parse_netrc(netrc, &["github.com"]), None);
+    }
+
+    #[test]
+    fn token_precedence() {
+        let vars = [("GITHUB_TOKEN", "env-token")];
+        let token = |option, vars, netrc| {
+            github_token(option, env(vars), netrc, "github.com", "api.github.com")
+        };
+        assert_eq!(
+            token(Some("option-token"), &vars, Some(NETRC)),
+            Some("option-token".to_string())
+        );
+        assert_eq!(
+            token(Some(""), &vars, Some(NETRC)),
+            Some("env-token".to_string())
+        );
+        assert_eq!(
+            token(None, &[("GITHUB_TOKEN", "")], Some(NETRC)),
+            Some("web-token".to_string())
+        );
+        assert_eq!(token(None, &[], None), None);
+    }
+
+    #[test]
+    fn netrc_location() {
+        let path = |os, vars| netrc_path(os, env(vars));
+        assert_eq!(
+            path("linux", &[("NETRC", "/tmp/netrc"), ("HOME", "/home/me")]),
+            Some(PathBuf::from("/tmp/netrc"))
+        );
+        assert_eq!(
+            path("linux", &[("NETRC", ""), ("HOME", "/home/me")]),
+            Some(PathBuf::from("/home/me/.netrc"))
+        );
+        assert_eq!(
+            path("windows", &[("USERPROFILE", "/users/me")]),
+            Some(PathBuf::from("/users/me/_netrc"))
+        );
+        assert_eq!(path("linux", &[]), None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    http::CROSS_HOST_HEADERS, manifest::InstallManifest, selftest::Report, version::UpdateReason,
+};
+
+const REDACTED: &str = "<redacted>";
+
+/// Whether a setting or environment variable name holds a credential
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [
+        "token",
+        "password",
+        "secret",
+        "authorization",
+        "key",
+        "cookie",
+        "credential",
+    ]
+    .iter()
+    .any(|secret| name.contains(secret))
+}
+
+/// Blank out every credential in `value`, both object entries and the
+/// `[name, value]` pairs environment variables are serialized as
+///
+/// `downloadHeaders` may hold mirror tokens under any name, so only the
+/// generic headers in it are kept.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == "downloadHeaders" {
+                    if let Value::Object(headers) = value {
+                        for (name, value) in headers.iter_mut() {
+                            if !CROSS_HOST_HEADERS
+                                .iter()
+                                .any(|header| name.eq_ignore_ascii_case(header))
+                            {
+                                *value = Value::from(REDACTED);
+                            }
+                        }
+                        continue;
+                    }
+                }
+                if is_secret(key) && !value.is_null() {
+                    *value = Value::from(REDACTED);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let [Value::String(name), value @ Value::String(_)] = items.as_mut_slice() {
+                if is_secret(name) {
+                    *value = Value::from(REDACTED);
+                    return;
+                }
+            }
+            items.iter_mut().for_each(redact);
+        }
+        _ => {}
+    }
+}
+
+/// Everything a maintainer needs to triage an issue, returned by the
+/// `dlang/exportState` request
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateExport {
+    pub plugin_version: &'static str,
+    /// Contents of `version.txt`, if serve-d is installed by the plugin
+    pub installed_version: Option<String>,
+    pub manifest: Option<InstallManifest>,
+    /// Command line and options serve-d was started with
+    pub effective_config: Option<Value>,
+    pub self_test: Report,
+    /// Error the last initialization failed with
+    pub last_error: Option<String>,
+    /// Why serve-d was or wasn't updated on the last initialization
+    pub update_reason: Option<UpdateReason>,
+}
+
+impl StateExport {
+    pub fn new(
+        install_dir: Option<&Path>,
+        effective_config: Option<Value>,
+        self_test: Report,
+        last_error: Option<String>,
+        update_reason: Option<UpdateReason>,
+    ) -> Result<Self> {
+        let (installed_version, manifest) = match install_dir {
+            Some(install_dir) => {
+                let verfile = install_dir.join("version.txt");
+                let installed_version = if verfile.exists() {
+                    Some(fs::read_to_string(verfile)?.trim().to_string())
+                } else {
+                    None
+                };
+                (installed_version, InstallManifest::read(install_dir)?)
+            }
+            None => (None, None),
+        };
+
+        Ok(StateExport {
+            plugin_version: env!("CARGO_PKG_VERSION"),
+            installed_version,
+            manifest,
+            effective_config,
+            self_test,
+            last_error,
+            update_reason,
+        })
+    }
+
+    /// The export as JSON with credentials redacted
+    pub fn to_redacted_json(&self) -> Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        redact(&mut value);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::TestDir;
+
+    #[test]
+    fn redacts_credentials_anywhere_in_the_state() {
+        let mut value = json!({
+            "githubToken": "ghp_secret",
+            "proxyPassword": null,
+            "downloadHeaders": {
+                "Authorization": "Bearer abc",
+                "X-Mirror-Auth": "abc",
+                "Accept": "*/*",
+                "user-agent": "lapce-dlang",
+            },
+            "mirror": { "apiKey": "abc", "cookie": "session=abc", "credentials": "abc" },
+            "env": [["GITHUB_TOKEN", "ghp_secret"], ["AWS_ACCESS_KEY_ID", "AKIA"], ["DFLAGS", "-O"]],
+            "serverArgs": ["--loglevel", "info"],
+        });
+        redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "githubToken": REDACTED,
+                "proxyPassword": null,
+                "downloadHeaders": {
+                    "Authorization": REDACTED,
+                    "X-Mirror-Auth": REDACTED,
+                    "Accept": "*/*",
+                    "user-agent": "lapce-dlang",
+                },
+                "mirror": { "apiKey": REDACTED, "cookie": REDACTED, "credentials": REDACTED },
+                "env": [["GITHUB_TOKEN", REDACTED], ["AWS_ACCESS_KEY_ID", REDACTED], ["DFLAGS", "-O"]],
+                "serverArgs": ["--loglevel", "info"],
+            })
+        );
+    }
+
+    #[test]
+    fn assembles_the_installed_state() {
+        let dir = TestDir::new("export");
+        fs::write(dir.join("version.txt"), "v0.7.6\n").unwrap();
+
+        let export = StateExport::new(
+            Some(&*dir),
+            Some(json!({ "serverArgs": [], "env": [["GH_TOKEN", "ghp_secret"]] })),
+            Report::new(Vec::new()),
+            Some("Fetching serve-d releases failed with error 403".to_string()),
+            Some(UpdateReason::AlreadyLatest),
+        )
+        .unwrap()
+        .to_redacted_json()
+        .unwrap();
+        assert_eq!(export["installedVersion"], "v0.7.6");
+        assert_eq!(export["manifest"], Value::Null);
+        assert_eq!(export["effectiveConfig"]["env"][0][1], REDACTED);
+        assert_eq!(export["selfTest"]["passed"], true);
+        assert_eq!(
+            export["lastError"],
+            "Fetching serve-d releases failed with error 403"
+        );
+        assert_eq!(export["updateReason"], "AlreadyLatest");
+        assert!(!export.to_string().contains("ghp_secret"));
+
+        let export = StateExport::new(None, None, Report::new(Vec::new()), None, None).unwrap();
+        assert_eq!(export.installed_version, None);
+    }
+}
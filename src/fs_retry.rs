@@ -0,0 +1,102 @@
+use std::io::{self, ErrorKind};
+
+use anyhow::Result;
+
+use crate::retry::RetryPolicy;
+
+// WASI errno values, which is what the host reports I/O errors as
+const EBUSY: i32 = 10;
+const EDQUOT: i32 = 19;
+const ENOSPC: i32 = 51;
+const EROFS: i32 = 69;
+const ETXTBSY: i32 = 74;
+
+/// Whether an I/O error may clear up on its own, e.g. a virus scanner or
+/// the search indexer briefly holding a file on Windows
+///
+/// A read-only filesystem reports access denied as well, but never recovers.
+pub fn is_transient(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(EROFS) => return false,
+        Some(EBUSY | ETXTBSY) => return true,
+        _ => {}
+    }
+
+    matches!(
+        err.kind(),
+        ErrorKind::PermissionDenied
+            | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+    )
+}
+
+/// Whether `err` comes from the disk or the user's quota running full
+pub fn is_out_of_space(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| matches!(err.raw_os_error(), Some(ENOSPC | EDQUOT)))
+}
+
+/// Run a filesystem operation, retrying it with backoff while it fails
+/// with transient errors
+pub fn retry<T>(op: impl FnMut() -> io::Result<T>) -> Result<T> {
+    RetryPolicy::FILESYSTEM.run(op, is_transient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_transient_errors() {
+        assert!(is_transient(&io::Error::from(ErrorKind::PermissionDenied)));
+        assert!(is_transient(&io::Error::from_raw_os_error(EBUSY)));
+        assert!(is_transient(&io::Error::from_raw_os_error(ETXTBSY)));
+        assert!(!is_transient(&io::Error::from_raw_os_error(EROFS)));
+        assert!(!is_transient(&io::Error::from(ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn retries_until_the_error_clears() {
+        let mut attempts = 0;
+        let value = retry(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(ErrorKind::PermissionDenied))
+            } else {
+                Ok(attempts)
+            }
+        })
+        .unwrap();
+        assert_eq!(value, 3);
+    }
+
+    #[test]
+    fn gives_up_on_permanent_errors_right_away() {
+        let mut attempts = 0;
+        let err = retry(|| -> io::Result<()> {
+            attempts += 1;
+            Err(io::Error::from_raw_os_error(EROFS))
+        })
+        .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(err.downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    fn recognizes_a_full_disk_through_context() {
+        let err = anyhow::Error::from(io::Error::from_raw_os_error(ENOSPC))
+            .context("Extracting serve-d failed");
+        assert!(is_out_of_space(&err));
+        assert!(is_out_of_space(
+            &io::Error::from_raw_os_error(EDQUOT).into()
+        ));
+        assert!(!is_out_of_space(
+            &io::Error::from_raw_os_error(EROFS).into()
+        ));
+        assert!(!is_out_of_space(&anyhow::Error::msg(
+            "No space left on device"
+        )));
+    }
+}
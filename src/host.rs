@@ -0,0 +1,303 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Error, Result};
+use lapce_plugin::{
+    psp_types::lsp_types::{MessageType, Url},
+    VoltEnvironment, PLUGIN_RPC,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Output of a process run through [`Host::execute`]
+pub struct ProcessOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Everything the plugin needs from Lapce and the system it runs on
+///
+/// The plugin itself runs against [`LapceHost`], the indirection lets the
+/// whole install flow be driven by another implementation.
+pub trait Host {
+    fn operating_system(&self) -> Result<String>;
+    fn architecture(&self) -> Result<String>;
+    fn volt_uri(&self) -> Result<String>;
+    fn env_var(&self, key: &str) -> Option<String>;
+    fn execute(&self, program: &str, args: &[&str]) -> Result<ProcessOutput>;
+    fn log(&self, message: &str);
+    /// Write log lines in `format` from now on, dropping all but errors if
+    /// `quiet` is set
+    fn set_logging(&self, format: LogFormat, quiet: bool);
+    /// Also write log lines to `log_file` from now on
+    fn set_log_file(&self, log_file: LogFile);
+    fn show_message(&self, kind: MessageType, message: String);
+    fn start_lsp(
+        &self,
+        server_path: Url,
+        server_args: Vec<String>,
+        language_id: &str,
+        options: Option<Value>,
+    );
+}
+
+/// How log lines are written
+///
+/// Logs never carry colors, so `NO_COLOR` and plain terminals are always
+/// served by the human format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Human,
+    /// One JSON object per line, for automated diagnosis
+    Json,
+}
+
+impl LogFormat {
+    pub fn format(&self, message: &str) -> String {
+        match self {
+            LogFormat::Human => format!("lapce-dlang: {}", message),
+            LogFormat::Json => {
+                let mut line = Map::new();
+                line.insert("plugin".to_string(), Value::from("lapce-dlang"));
+                line.insert("message".to_string(), Value::from(message));
+                Value::Object(line).to_string()
+            }
+        }
+    }
+}
+
+/// Default size a log file grows to before it's rotated
+pub const DEFAULT_LOG_FILE_MAX_BYTES: u64 = 1024 * 1024;
+/// Default number of rotated log files kept
+pub const DEFAULT_LOG_FILE_KEEP: usize = 3;
+
+/// Log file rotated by size, keeping the last `keep` rotated files as
+/// `<path>.1` (newest) to `<path>.<keep>`
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub keep: usize,
+}
+
+impl LogFile {
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    /// Whether appending `line_len` bytes to a file of `file_len` bytes
+    /// starts a new file, a single overlong line still gets written
+    pub fn needs_rotation(&self, file_len: u64, line_len: u64) -> bool {
+        file_len > 0 && file_len + line_len > self.max_bytes
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        let oldest = self.rotated_path(self.keep);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for index in (1..self.keep).rev() {
+            let rotated = self.rotated_path(index);
+            if rotated.exists() {
+                fs::rename(rotated, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    pub fn append(&self, line: &str) -> io::Result<()> {
+        let file_len = fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+        if self.needs_rotation(file_len, line.len() as u64 + 1) {
+            self.rotate()?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)
+    }
+}
+
+/// Whether output gets through in quiet mode or not, log lines are
+/// `None` and dropped along with every message but errors
+fn passes_quiet(quiet: bool, kind: Option<MessageType>) -> bool {
+    !quiet || kind == Some(MessageType::ERROR)
+}
+
+/// [`Host`] backed by the Lapce plugin API
+pub struct LapceHost {
+    pub log_format: Cell<LogFormat>,
+    /// Drop log lines and messages other than errors
+    pub quiet: Cell<bool>,
+    pub log_file: RefCell<Option<LogFile>>,
+}
+
+impl Host for LapceHost {
+    fn operating_system(&self) -> Result<String> {
+        Ok(VoltEnvironment::operating_system()?)
+    }
+
+    fn architecture(&self) -> Result<String> {
+        Ok(VoltEnvironment::architecture()?)
+    }
+
+    fn volt_uri(&self) -> Result<String> {
+        Ok(VoltEnvironment::uri()?)
+    }
+
+    fn env_var(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+
+    fn execute(&self, program: &str, args: &[&str]) -> Result<ProcessOutput> {
+        let output = PLUGIN_RPC
+            .execute_process(
+                program.to_string(),
+                args.iter().map(|arg| arg.to_string()).collect(),
+            )
+            .map_err(|err| Error::msg(format!("Running {} failed: {:?}", program, err)))?;
+
+        Ok(ProcessOutput {
+            success: output.success,
+            stdout: output.stdout.unwrap_or_default(),
+            stderr: output.stderr.unwrap_or_default(),
+        })
+    }
+
+    fn log(&self, message: &str) {
+        if !passes_quiet(self.quiet.get(), None) {
+            return;
+        }
+
+        let line = self.log_format.get().format(message);
+        PLUGIN_RPC.stderr(&line);
+
+        // Logging must never fail the plugin, a broken log file is left be
+        if let Some(log_file) = self.log_file.borrow().as_ref() {
+            let _ = log_file.append(&line);
+        }
+    }
+
+    fn set_logging(&self, format: LogFormat, quiet: bool) {
+        self.log_format.set(format);
+        self.quiet.set(quiet);
+    }
+
+    fn set_log_file(&self, log_file: LogFile) {
+        *self.log_file.borrow_mut() = Some(log_file);
+    }
+
+    fn show_message(&self, kind: MessageType, message: String) {
+        if !passes_quiet(self.quiet.get(), Some(kind)) {
+            return;
+        }
+
+        PLUGIN_RPC.window_show_message(kind, message);
+    }
+
+    fn start_lsp(
+        &self,
+        server_path: Url,
+        server_args: Vec<String>,
+        language_id: &str,
+        options: Option<Value>,
+    ) {
+        PLUGIN_RPC.start_lsp(server_path, server_args, language_id, options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::mock::TestDir;
+
+    #[test]
+    fn formats_human_and_json_log_lines() {
+        let message = "Downloading \"serve-d\"\nv0.8.0";
+        assert_eq!(
+            LogFormat::Human.format(message),
+            "lapce-dlang: Downloading \"serve-d\"\nv0.8.0"
+        );
+        let line = LogFormat::Json.format(message);
+        assert!(!line.contains('\n'), "{}", line);
+        assert_eq!(
+            serde_json::from_str::<Value>(&line).unwrap(),
+            serde_json::json!({ "plugin": "lapce-dlang", "message": message })
+        );
+        assert_eq!(LogFormat::default(), LogFormat::Human);
+    }
+
+    fn log_file(dir: &Path, max_bytes: u64, keep: usize) -> LogFile {
+        LogFile {
+            path: dir.join("lapce-dlang.log"),
+            max_bytes,
+            keep,
+        }
+    }
+
+    #[test]
+    fn rotates_only_files_that_would_overflow() {
+        let log_file = LogFile {
+            path: PathBuf::from("lapce-dlang.log"),
+            max_bytes: 10,
+            keep: 2,
+        };
+        assert!(!log_file.needs_rotation(0, 20));
+        assert!(!log_file.needs_rotation(5, 5));
+        assert!(log_file.needs_rotation(5, 6));
+        assert_eq!(log_file.rotated_path(2), PathBuf::from("lapce-dlang.log.2"));
+    }
+
+    #[test]
+    fn keeps_the_newest_rotated_files() {
+        let dir = TestDir::new("log-rotation");
+        let log_file = log_file(&dir, 6, 2);
+        for line in ["one", "two", "three", "four"] {
+            log_file.append(line).unwrap();
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(log_file.path.clone()), "four\n");
+        assert_eq!(read(log_file.rotated_path(1)), "three\n");
+        assert_eq!(read(log_file.rotated_path(2)), "two\n");
+        assert!(!log_file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn keeps_no_rotated_files_when_asked() {
+        let dir = TestDir::new("log-rotation-none");
+        let log_file = log_file(&dir, 6, 0);
+        for line in ["one", "two"] {
+            log_file.append(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&log_file.path).unwrap(), "two\n");
+        assert!(!log_file.rotated_path(1).exists());
+    }
+
+    #[test]
+    fn quiet_mode_only_lets_errors_through() {
+        for kind in [None, Some(MessageType::INFO), Some(MessageType::ERROR)] {
+            assert!(passes_quiet(false, kind));
+        }
+        assert!(!passes_quiet(true, None));
+        assert!(!passes_quiet(true, Some(MessageType::INFO)));
+        assert!(!passes_quiet(true, Some(MessageType::WARNING)));
+        assert!(passes_quiet(true, Some(MessageType::ERROR)));
+    }
+}
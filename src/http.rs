@@ -0,0 +1,710 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::IpAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result};
+use http::Method;
+use lapce_plugin::psp_types::lsp_types::Url;
+
+use crate::{host::Host, release, stats};
+
+const USER_AGENT: &str = concat!("lapce-dlang/", env!("CARGO_PKG_VERSION"));
+
+/// Default time limit for GitHub API requests
+pub const DEFAULT_API_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time limit for archive downloads
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Time limits for the two kinds of requests the plugin makes
+///
+/// [`WasiHttp`] can't hold requests to them, see [`WasiHttp::get`], they
+/// bound the waits and retries around requests.
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    pub api: Duration,
+    pub download: Duration,
+}
+
+/// Gate for `lsp.insecureSkipTlsVerify`
+///
+/// Skipping certificate checks is only meant for testing against self-signed
+/// mirrors. The host transport always verifies certificates, and a user who
+/// set the option expects a self-signed mirror to work, so the plugin stops
+/// with an error naming the option instead of failing on the mirror's
+/// certificate. The warning is logged every time the option is set.
+pub fn check_insecure_tls(host: &dyn Host, skip_verify: bool) -> Result<()> {
+    if !skip_verify {
+        return Ok(());
+    }
+
+    host.log(
+        "WARNING: insecureSkipTlsVerify is set, TLS certificate verification must never be \
+         disabled outside of testing",
+    );
+    Err(Error::msg(
+        "insecureSkipTlsVerify is not supported by the Lapce HTTP transport",
+    ))
+}
+
+/// Parse `lsp.dnsOverride`, a table mapping host names to IP addresses
+pub fn parse_dns_override(table: &BTreeMap<String, String>) -> Result<Vec<(String, IpAddr)>> {
+    table
+        .iter()
+        .map(|(name, ip)| {
+            let ip = ip
+                .parse::<IpAddr>()
+                .map_err(|_| Error::msg(format!("dnsOverride.{} is not an IP address", name)))?;
+            Ok((name.to_ascii_lowercase(), ip))
+        })
+        .collect()
+}
+
+/// Gate for `lsp.forceIpv4` and `lsp.dnsOverride`
+///
+/// The host transport resolves names itself and can't be told which
+/// addresses to use. Downloads would then reach whatever the system resolver
+/// returns, which on a network the options work around may be an address
+/// that hangs until the timeout, so they fail early instead. `dnsOverride`
+/// is still parsed, so mistakes in it show up first.
+pub fn check_network_options(
+    force_ipv4: bool,
+    dns_override: Option<&BTreeMap<String, String>>,
+) -> Result<()> {
+    if force_ipv4 {
+        return Err(Error::msg(
+            "forceIpv4 is not supported by the Lapce HTTP transport",
+        ));
+    }
+
+    if let Some(dns_override) = dns_override {
+        parse_dns_override(dns_override)?;
+        return Err(Error::msg(
+            "dnsOverride is not supported by the Lapce HTTP transport",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fully read response of a GET request
+pub struct Response {
+    pub status: u16,
+    /// Header names and values, names in lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Error of a request that may succeed when tried again, like a dropped
+/// connection or a server asking to slow down
+#[derive(Debug)]
+pub struct TransientError(pub String);
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+/// Whether `err` is a [`TransientError`], anything else, like a host
+/// refused by [`AllowedHosts`], fails the same way every time
+pub fn is_transient(err: &Error) -> bool {
+    err.chain().any(|cause| cause.is::<TransientError>())
+}
+
+/// Transport every request of the plugin goes through
+pub trait HttpClient {
+    /// Send a GET request with the plugin's default headers plus `headers`,
+    /// a `User-Agent` in `headers` replaces the default one
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response>;
+}
+
+/// [`HttpClient`] backed by the host's `wasi-experimental-http`
+///
+/// The host owns the connections and offers no transport options, so
+/// HTTP/2 and connection reuse between the metadata and download requests
+/// are up to the host. All requests go through here, so transport settings
+/// belong in this type once the host grows them.
+pub struct WasiHttp;
+
+const MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How long a `Retry-After` header asks to wait, given as seconds or as an
+/// HTTP date like `Wed, 21 Oct 2015 07:28:00 GMT`
+pub fn parse_retry_after(value: &str, now: u64) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut parts = value.split_once(", ")?.1.split(' ');
+    let (day, month, year, time) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let month = MONTHS.iter().position(|name| *name == month)? + 1;
+    let at = release::parse_timestamp(&format!("{}-{:02}-{}T{}Z", year, month, day, time))?;
+    Some(Duration::from_secs(at.saturating_sub(now)))
+}
+
+/// Error of a request that got no response from the host transport
+fn transport_error(url: &str, err: impl fmt::Display) -> Error {
+    Error::new(TransientError(format!(
+        "Request to {} failed: {}",
+        url, err
+    )))
+}
+
+impl WasiHttp {
+    fn send(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response> {
+        let mut builder = http::Request::builder().method(Method::GET).uri(url);
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+        {
+            builder = builder.header("User-Agent", USER_AGENT);
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        let mut resp = wasi_experimental_http::request(builder.body(None)?)
+            .map_err(|err| transport_error(url, err))?;
+        let headers = resp
+            .headers_get_all()
+            .map_err(|err| transport_error(url, err))?
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    name.as_str().to_ascii_lowercase(),
+                    value.to_str().ok()?.to_string(),
+                ))
+            })
+            .collect();
+        let body = resp
+            .body_read_all()
+            .map_err(|err| transport_error(url, err))?;
+
+        Ok(Response {
+            status: resp.status_code.as_u16(),
+            headers,
+            body,
+        })
+    }
+}
+
+impl HttpClient for WasiHttp {
+    /// Sends the request, waiting out a `429 Too Many Requests` once when
+    /// its `Retry-After` fits into the timeout
+    ///
+    /// The host transport can neither abort a request in flight nor be given
+    /// a time limit, so `timeout` only bounds the wait for a retry here. A
+    /// response arriving late is still used, throwing away a finished
+    /// download would only make the next attempt take as long again.
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+        let started = Instant::now();
+        let resp = self.send(url, headers)?;
+        if resp.status != 429 {
+            return Ok(resp);
+        }
+
+        let retry_after = resp
+            .header("retry-after")
+            .and_then(|value| parse_retry_after(value, stats::now()));
+        match retry_after {
+            Some(wait) if started.elapsed() + wait < timeout => {
+                thread::sleep(wait);
+                // The sleep may overshoot, leaving nothing of the timeout
+                if timeout.saturating_sub(started.elapsed()).is_zero() {
+                    return Err(Error::msg(format!(
+                        "Request to {} timed out after {}s",
+                        url,
+                        timeout.as_secs()
+                    )));
+                }
+                self.send(url, headers)
+            }
+            Some(wait) => Err(Error::msg(format!(
+                "Too many requests to {}, try again in {}s",
+                url,
+                wait.as_secs()
+            ))),
+            None => Err(Error::new(TransientError(format!(
+                "Too many requests to {}, try again later",
+                url
+            )))),
+        }
+    }
+}
+
+/// Whether the host of `url` is one of `hosts`
+pub fn host_allowed(url: &str, hosts: &[String]) -> Result<bool> {
+    let url = Url::parse(url)?;
+    Ok(match url.host_str() {
+        Some(host) => hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host)),
+        None => false,
+    })
+}
+
+/// [`HttpClient`] refusing requests to hosts outside an allowlist
+///
+/// The host transport follows redirects on its own without reporting where
+/// they led, so redirects are only checked when a `3xx` with a `Location`
+/// reaches the plugin.
+pub struct AllowedHosts<'a> {
+    pub inner: &'a dyn HttpClient,
+    pub hosts: Vec<String>,
+}
+
+impl<'a> AllowedHosts<'a> {
+    fn check(&self, url: &str) -> Result<()> {
+        if host_allowed(url, &self.hosts)? {
+            Ok(())
+        } else {
+            Err(Error::msg(format!(
+                "Refusing to download from {}, its host is not in allowedHosts",
+                url
+            )))
+        }
+    }
+}
+
+impl<'a> HttpClient for AllowedHosts<'a> {
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+        self.check(url)?;
+        let resp = self.inner.get(url, headers, timeout)?;
+        if (300..400).contains(&resp.status) {
+            if let Some(location) = resp.header("location") {
+                self.check(Url::parse(url)?.join(location)?.as_str())?;
+            }
+        }
+        Ok(resp)
+    }
+}
+
+/// Default limit of redirects followed by [`FollowRedirects`]
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Generic headers that carry no credentials, the only ones kept when a
+/// redirect leads to another host
+pub const CROSS_HOST_HEADERS: &[&str] = &["accept", "user-agent"];
+
+/// [`HttpClient`] following redirects that reach the plugin, up to a limit
+///
+/// A mirror redirecting in a loop fails with the chain of visited URLs
+/// instead of bouncing between them until the timeout.
+pub struct FollowRedirects<'a> {
+    pub inner: &'a dyn HttpClient,
+    pub max_redirects: usize,
+}
+
+impl<'a> HttpClient for FollowRedirects<'a> {
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+        let started = Instant::now();
+        let mut headers = headers.to_vec();
+        let mut chain = vec![url.to_string()];
+        loop {
+            let current = chain.last().unwrap();
+            let resp =
+                self.inner
+                    .get(current, &headers, timeout.saturating_sub(started.elapsed()))?;
+            let location = match resp.header("location") {
+                Some(location) if (300..400).contains(&resp.status) => location,
+                _ => return Ok(resp),
+            };
+
+            let current = Url::parse(current)?;
+            let next = current.join(location)?;
+            // Credentials stay with the host they're meant for, e.g. GitHub
+            // redirects asset downloads to presigned storage urls. That
+            // includes `downloadHeaders`, which may carry mirror tokens under
+            // any name, so only generic headers cross hosts
+            if next.host_str() != current.host_str() {
+                headers.retain(|(name, _)| {
+                    CROSS_HOST_HEADERS
+                        .iter()
+                        .any(|header| name.eq_ignore_ascii_case(header))
+                });
+            }
+            chain.push(next.to_string());
+            if chain.len() > self.max_redirects + 1 {
+                return Err(Error::msg(format!(
+                    "Fetching {} exceeded {} redirects: {}",
+                    url,
+                    self.max_redirects,
+                    chain.join(" -> ")
+                )));
+            }
+        }
+    }
+}
+
+/// Add configured `extra` headers to the `required` ones a request sets
+/// itself, including the plugin's `User-Agent`
+///
+/// Required headers win so a configured header can't silently break e.g.
+/// `Accept` or `Authorization`, unless `override_required` is set.
+pub fn merge_headers<'h>(
+    required: &[(&'h str, &'h str)],
+    extra: &'h [(String, String)],
+    override_required: bool,
+) -> Vec<(&'h str, &'h str)> {
+    let mut merged: Vec<(&str, &str)> = required.to_vec();
+    if !merged
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+    {
+        merged.push(("User-Agent", USER_AGENT));
+    }
+
+    for (name, value) in extra {
+        let existing = merged
+            .iter()
+            .position(|(required, _)| required.eq_ignore_ascii_case(name));
+        match existing {
+            Some(index) if override_required => merged[index] = (name, value),
+            Some(_) => {}
+            None => merged.push((name, value)),
+        }
+    }
+    merged
+}
+
+/// [`HttpClient`] adding configured headers to every request
+pub struct ExtraHeaders<'a> {
+    pub inner: &'a dyn HttpClient,
+    pub headers: Vec<(String, String)>,
+    /// Whether the configured headers replace the ones requests set
+    pub override_required: bool,
+}
+
+impl<'a> HttpClient for ExtraHeaders<'a> {
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+        let merged = merge_headers(headers, &self.headers, self.override_required);
+        self.inner.get(url, &merged, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::mock::MockHost;
+
+    /// Client redirecting requests by `redirects`, recording each request
+    /// with its headers
+    struct Redirecting {
+        redirects: Vec<(&'static str, &'static str)>,
+        requests: RefCell<Vec<(String, Vec<String>)>>,
+    }
+
+    impl Redirecting {
+        fn new(redirects: &[(&'static str, &'static str)]) -> Self {
+            Redirecting {
+                redirects: redirects.to_vec(),
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl HttpClient for Redirecting {
+        fn get(&self, url: &str, headers: &[(&str, &str)], _: Duration) -> Result<Response> {
+            let names = headers.iter().map(|(name, _)| name.to_string()).collect();
+            self.requests.borrow_mut().push((url.to_string(), names));
+            let location = self.redirects.iter().find(|(from, _)| *from == url);
+            Ok(match location {
+                Some((_, to)) => Response {
+                    status: 302,
+                    headers: vec![("location".to_string(), to.to_string())],
+                    body: Vec::new(),
+                },
+                None => Response {
+                    status: 200,
+                    headers: Vec::new(),
+                    body: b"ok".to_vec(),
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn reads_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120", 0), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", 0), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", 0), None);
+    }
+
+    #[test]
+    fn reads_retry_after_dates() {
+        let at = 1445412480;
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(
+            parse_retry_after(date, at - 30),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after(date, at + 30), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Foo 2015 07:28:00 GMT", 0), None);
+    }
+
+    #[test]
+    fn redirects_keep_credentials_on_the_same_host() {
+        let inner = Redirecting::new(&[
+            ("https://example.com/a", "/b"),
+            ("https://example.com/b", "https://objects.example.org/c"),
+        ]);
+        let client = FollowRedirects {
+            inner: &inner,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        };
+        let headers = [
+            ("Authorization", "token secret"),
+            ("X-Mirror-Token", "secret"),
+            ("Accept", "application/octet-stream"),
+            ("User-Agent", USER_AGENT),
+        ];
+        let resp = client
+            .get("https://example.com/a", &headers, DEFAULT_API_TIMEOUT)
+            .unwrap();
+        assert_eq!(resp.body, b"ok");
+
+        let requests = inner.requests.into_inner();
+        let urls: Vec<&str> = requests.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/a",
+                "https://example.com/b",
+                "https://objects.example.org/c",
+            ]
+        );
+        assert_eq!(requests[1].1.len(), 4);
+        assert_eq!(requests[2].1, vec!["Accept", "User-Agent"]);
+    }
+
+    #[test]
+    fn redirect_loops_fail() {
+        let inner = Redirecting::new(&[
+            ("https://example.com/a", "/b"),
+            ("https://example.com/b", "/a"),
+        ]);
+        let client = FollowRedirects {
+            inner: &inner,
+            max_redirects: 3,
+        };
+        let err = client
+            .get("https://example.com/a", &[], DEFAULT_API_TIMEOUT)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Fetching https://example.com/a exceeded 3 redirects: \
+             https://example.com/a -> https://example.com/b -> https://example.com/a \
+             -> https://example.com/b -> https://example.com/a"
+        );
+        assert_eq!(inner.requests.borrow().len(), 4);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        let err = transport_error("https://example.com", "connection reset");
+        assert!(is_transient(&err));
+        assert!(is_transient(&err.context("Downloading serve-d")));
+        assert!(!is_transient(&Error::msg(
+            "Host example.com is not allowed"
+        )));
+    }
+
+    #[test]
+    fn skipping_tls_verification_warns_and_fails() {
+        let host = MockHost::default();
+        check_insecure_tls(&host, false).unwrap();
+        assert!(host.logs.borrow().is_empty());
+
+        let err = check_insecure_tls(&host, true).unwrap_err();
+        assert!(err.to_string().contains("insecureSkipTlsVerify"), "{}", err);
+        let logs = host.logs.borrow();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].starts_with("WARNING"), "{}", logs[0]);
+    }
+
+    #[test]
+    fn allowed_hosts_match_case_insensitively() {
+        let hosts = vec![
+            "github.com".to_string(),
+            "Objects.GitHubUserContent.com".to_string(),
+        ];
+        assert!(host_allowed("https://GitHub.com/Pure-D/serve-d", &hosts).unwrap());
+        assert!(host_allowed("https://objects.githubusercontent.com/x", &hosts).unwrap());
+        assert!(!host_allowed("https://github.com.evil.example/x", &hosts).unwrap());
+        assert!(!host_allowed("https://api.github.com/repos", &hosts).unwrap());
+        assert!(host_allowed("not a url", &hosts).is_err());
+    }
+
+    #[test]
+    fn disallowed_hosts_are_refused_before_and_after_redirects() {
+        let inner = Redirecting::new(&[
+            (
+                "https://github.com/a",
+                "https://objects.githubusercontent.com/a",
+            ),
+            ("https://github.com/b", "https://mirror.example.org/b"),
+        ]);
+        let client = AllowedHosts {
+            inner: &inner,
+            hosts: vec![
+                "github.com".to_string(),
+                "objects.githubusercontent.com".to_string(),
+            ],
+        };
+
+        let resp = client
+            .get("https://github.com/a", &[], DEFAULT_DOWNLOAD_TIMEOUT)
+            .unwrap();
+        assert_eq!(resp.status, 302);
+        let err = client
+            .get("https://github.com/b", &[], DEFAULT_DOWNLOAD_TIMEOUT)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("mirror.example.org"), "{}", err);
+
+        let err = client
+            .get(
+                "https://mirror.example.org/c",
+                &[],
+                DEFAULT_DOWNLOAD_TIMEOUT,
+            )
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not in allowedHosts"), "{}", err);
+        // Refused without sending the request
+        assert_eq!(inner.requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn parses_the_dns_override_table() {
+        let table: BTreeMap<String, String> = [
+            ("GitHub.com".to_string(), "140.82.121.3".to_string()),
+            (
+                "objects.githubusercontent.com".to_string(),
+                "::1".to_string(),
+            ),
+        ]
+        .into();
+        assert_eq!(
+            parse_dns_override(&table).unwrap(),
+            vec![
+                ("github.com".to_string(), "140.82.121.3".parse().unwrap()),
+                (
+                    "objects.githubusercontent.com".to_string(),
+                    "::1".parse().unwrap()
+                ),
+            ]
+        );
+
+        let table: BTreeMap<String, String> =
+            [("github.com".to_string(), "github.io".to_string())].into();
+        assert_eq!(
+            parse_dns_override(&table).unwrap_err().to_string(),
+            "dnsOverride.github.com is not an IP address"
+        );
+    }
+
+    #[test]
+    fn network_options_are_refused_by_the_transport() {
+        assert!(check_network_options(false, None).is_ok());
+        let err = check_network_options(true, None).unwrap_err();
+        assert!(err.to_string().contains("forceIpv4"), "{}", err);
+
+        let table: BTreeMap<String, String> =
+            [("github.com".to_string(), "140.82.121.3".to_string())].into();
+        let err = check_network_options(false, Some(&table)).unwrap_err();
+        assert!(err.to_string().contains("not supported"), "{}", err);
+        // Mistakes in the table are reported first
+        let table: BTreeMap<String, String> =
+            [("github.com".to_string(), "nope".to_string())].into();
+        let err = check_network_options(false, Some(&table)).unwrap_err();
+        assert!(err.to_string().contains("not an IP address"), "{}", err);
+    }
+
+    fn configured(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn required_headers_win_unless_overridden() {
+        let required = [("Accept", "application/octet-stream")];
+        let extra = configured(&[
+            ("accept", "*/*"),
+            ("X-Cache-Key", "serve-d"),
+            ("user-agent", "mirror/1.0"),
+        ]);
+        assert_eq!(
+            merge_headers(&required, &extra, false),
+            vec![
+                ("Accept", "application/octet-stream"),
+                ("User-Agent", USER_AGENT),
+                ("X-Cache-Key", "serve-d"),
+            ]
+        );
+        assert_eq!(
+            merge_headers(&required, &extra, true),
+            vec![
+                ("accept", "*/*"),
+                ("user-agent", "mirror/1.0"),
+                ("X-Cache-Key", "serve-d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_user_agent_a_request_sets() {
+        let required = [("user-agent", "custom")];
+        assert_eq!(
+            merge_headers(&required, &[], false),
+            vec![("user-agent", "custom")]
+        );
+    }
+
+    #[test]
+    fn adds_the_configured_headers_to_every_request() {
+        let inner = crate::mock::MockHttp::release("v0.7.6", &[]);
+        let client = ExtraHeaders {
+            inner: &inner,
+            headers: configured(&[("Authorization", "Bearer mirror")]),
+            override_required: false,
+        };
+        client
+            .get(
+                "https://example.com/serve-d.zip",
+                &[],
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(
+            inner.requests.borrow()[0].1,
+            configured(&[
+                ("User-Agent", USER_AGENT),
+                ("Authorization", "Bearer mirror")
+            ])
+        );
+    }
+}
@@ -0,0 +1,968 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::{self, create_dir_all, OpenOptions},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::Url;
+use serde::Serialize;
+
+use crate::{
+    archive::{self, ArchiveKind, ExtractedFile},
+    checksum, fs_retry,
+    host::Host,
+    http::{self, HttpClient},
+    manifest::{self, InstallManifest, ManifestAsset},
+    release::{self, GHAsset, GHReleaseAsset},
+    retry::RetryPolicy,
+    server, signature,
+    version::Channel,
+};
+
+/// Create the parent directories of every path that don't exist yet
+pub fn ensure_dirs(paths: &[&Path]) -> Result<()> {
+    for path in paths {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs_retry::retry(|| create_dir_all(parent))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Directory created for an install, removed again when dropped before the
+/// install completed
+///
+/// A directory left behind empty would pass for an existing install on the
+/// next start, which then finds no binary in it.
+pub struct CreatedDir(Option<PathBuf>);
+
+impl CreatedDir {
+    /// Create the parent directories of `paths` like [`ensure_dirs`],
+    /// tracking `dir` if it didn't exist before
+    pub fn ensure(dir: &Path, paths: &[&Path]) -> Result<CreatedDir> {
+        let created = !dir.exists();
+        ensure_dirs(paths)?;
+        Ok(CreatedDir(created.then(|| dir.to_path_buf())))
+    }
+
+    /// Keep the directory, the install in it completed
+    pub fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CreatedDir {
+    fn drop(&mut self) {
+        if let Some(dir) = self.0.take() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Whether an existing binary can't be overwritten because a running
+/// serve-d holds it locked
+///
+/// Only Windows locks running executables. Elsewhere the probe fails for
+/// other reasons, like a read-only binary, which mustn't hold back updates.
+pub fn is_binary_locked(os: &str, path: &Path) -> bool {
+    os == "windows" && path.exists() && OpenOptions::new().write(true).open(path).is_err()
+}
+
+/// Find the serve-d binary of an install, relative to `install_dir`
+///
+/// Archives may nest the binary in a directory, so the extracted `files`
+/// are searched first, preferring the shallowest match. Falls back to the
+/// top level of the install directory.
+pub fn locate_binary(install_dir: &Path, exec_file: &str, files: &[PathBuf]) -> Option<PathBuf> {
+    let binary = files
+        .iter()
+        .filter(|file| file.file_name() == Some(OsStr::new(exec_file)))
+        .min_by_key(|file| file.components().count());
+    if let Some(binary) = binary {
+        return Some(binary.clone());
+    }
+
+    if install_dir.join(exec_file).exists() {
+        return Some(PathBuf::from(exec_file));
+    }
+
+    None
+}
+
+/// Relative path as an URL path, to join onto the install directory URL
+pub fn url_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Mode given to the serve-d binary when the archive doesn't store one
+const DEFAULT_BINARY_MODE: u32 = 0o755;
+
+/// Apply the modes stored in the archive through the host's `chmod`, since
+/// the WASI sandbox can't set them itself
+///
+/// The serve-d binary is always made executable. Only permission bits are
+/// applied, setuid, setgid and sticky bits from the archive are dropped.
+/// File timestamps are left at the extraction time.
+fn apply_modes(
+    host: &dyn Host,
+    install_dir: &Path,
+    files: &[ExtractedFile],
+    exec_file: &str,
+) -> Result<()> {
+    if host.operating_system()? == "windows" {
+        return Ok(());
+    }
+
+    // One chmod per distinct mode rather than per file
+    let mut by_mode: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for file in files {
+        let mode = if file.path.file_name() == Some(OsStr::new(exec_file)) {
+            Some(file.mode.unwrap_or(DEFAULT_BINARY_MODE) | 0o111)
+        } else {
+            file.mode
+        };
+        if let Some(mode) = mode {
+            by_mode
+                .entry(mode & 0o777)
+                .or_default()
+                .push(install_dir.join(&file.path).to_string_lossy().into_owned());
+        }
+    }
+
+    for (mode, paths) in by_mode {
+        let mode = format!("{:o}", mode);
+        let mut args = vec![mode.as_str()];
+        args.extend(paths.iter().map(String::as_str));
+        let output = host.execute("chmod", &args)?;
+        if !output.success {
+            return Err(Error::msg(format!(
+                "Setting file modes failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Directory inside the install directory keeping the previous install
+const PREVIOUS_DIR: &str = "previous";
+
+/// Copy `files` relative to `from` over into `to`
+fn copy_files(from: &Path, to: &Path, files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        let dest = to.join(file);
+        ensure_dirs(&[&dest])?;
+        fs_retry::retry(|| fs::copy(from.join(file), &dest))?;
+    }
+
+    Ok(())
+}
+
+/// Files recording an install besides the extracted ones
+fn record_files(install_dir: &Path, verfile: &Path) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(InstallManifest::FILE_NAME)];
+    if let Ok(verfile) = verfile.strip_prefix(install_dir) {
+        files.push(verfile.to_path_buf());
+    }
+    files
+}
+
+/// Copy the current install aside, so it can be restored when the update
+/// turns out not to start
+///
+/// Returns whether there was an install to keep.
+pub fn keep_previous(install_dir: &Path, verfile: &Path) -> Result<bool> {
+    let manifest = match InstallManifest::read(install_dir)? {
+        Some(manifest) => manifest,
+        None => return Ok(false),
+    };
+
+    let previous_dir = install_dir.join(PREVIOUS_DIR);
+    if previous_dir.exists() {
+        fs_retry::retry(|| fs::remove_dir_all(&previous_dir))?;
+    }
+
+    let mut files = manifest.files();
+    files.extend(record_files(install_dir, verfile));
+    files.retain(|file| install_dir.join(file).exists());
+    copy_files(install_dir, &previous_dir, &files)?;
+    Ok(true)
+}
+
+/// Put the install kept by [`keep_previous`] back in place of the current
+/// one, remembering the `failed` release so it isn't installed again
+///
+/// Returns the restored version.
+pub fn restore_previous(
+    install_dir: &Path,
+    verfile: &Path,
+    failed: Option<&str>,
+) -> Result<String> {
+    let previous_dir = install_dir.join(PREVIOUS_DIR);
+    let mut previous = InstallManifest::read(&previous_dir)?
+        .ok_or_else(|| Error::msg("The previous serve-d install is missing"))?;
+
+    if let Some(current) = InstallManifest::read(install_dir)? {
+        for file in current.stale_files(&previous, install_dir) {
+            if file.exists() {
+                fs_retry::retry(|| fs::remove_file(&file))?;
+            }
+        }
+    }
+
+    let mut files = previous.files();
+    files.extend(record_files(install_dir, verfile));
+    files.retain(|file| previous_dir.join(file).exists());
+    copy_files(&previous_dir, install_dir, &files)?;
+
+    if let Some(failed) = failed {
+        previous.rolled_back_from = Some(failed.to_string());
+    }
+    previous.write(install_dir)?;
+    Ok(previous.version)
+}
+
+/// Where serve-d is installed, as reported by the `dlang/installPath`
+/// request
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallPaths {
+    pub install_dir: PathBuf,
+    pub binary: Option<PathBuf>,
+}
+
+impl InstallPaths {
+    pub fn read(install_dir: &Path) -> Result<Self> {
+        let binary = InstallManifest::read(install_dir)?
+            .and_then(|manifest| manifest.binary)
+            .map(|binary| install_dir.join(binary));
+        Ok(InstallPaths {
+            install_dir: install_dir.to_path_buf(),
+            binary,
+        })
+    }
+}
+
+/// Where and from which channel a release gets installed
+pub struct InstallTarget<'a> {
+    pub install_dir: &'a Path,
+    pub verfile: &'a Path,
+    pub kind: ArchiveKind,
+    pub channel: Channel,
+    pub exec_file: &'a str,
+    /// Whether files of identical archives already on disk may be reused
+    pub reuse_unchanged: bool,
+    /// Globs of the archive entries to extract, all of them if empty
+    pub extract_only: &'a [String],
+    /// Extract the serve-d binary only, without the companion tools
+    pub core_only: bool,
+    /// Where archives are extracted before their files are moved into place
+    pub temp_dir: &'a Path,
+    /// Public key the `.asc` signatures of the assets are checked against
+    pub signing_key: Option<&'a Path>,
+    /// Download through the API instead of the browser urls
+    pub asset_api: Option<AssetApi<'a>>,
+    /// Whether warnings of the installed serve-d fail its launch check
+    pub strict_startup: bool,
+}
+
+/// Move a file, copying it when `to` is on another filesystem
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    ensure_dirs(&[to])?;
+    if fs::rename(from, to).is_err() {
+        fs_retry::retry(|| fs::copy(from, to))?;
+        fs_retry::retry(|| fs::remove_file(from))?;
+    }
+    Ok(())
+}
+
+/// Extract an archive into a staging directory in `temp_dir`, then move
+/// its files into `install_dir`
+///
+/// A failed extraction leaves the install directory untouched.
+fn extract_staged(
+    data: Vec<u8>,
+    kind: ArchiveKind,
+    temp_dir: &Path,
+    install_dir: &Path,
+    keep: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<ExtractedFile>> {
+    let staging = temp_dir.join(format!("staging-{}", &manifest::sha256_hex(&data)[..16]));
+    if staging.exists() {
+        fs_retry::retry(|| fs::remove_dir_all(&staging))?;
+    }
+    fs_retry::retry(|| create_dir_all(&staging))?;
+
+    let result = archive::extract_archive(data, kind, &staging, keep).and_then(|extracted| {
+        for file in &extracted {
+            move_file(&staging.join(&file.path), &install_dir.join(&file.path))?;
+        }
+        Ok(extracted)
+    });
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Authenticated access to release assets through the GitHub API, for
+/// private repositories
+pub struct AssetApi<'a> {
+    pub api_base: &'a Url,
+    pub token: &'a str,
+}
+
+/// Download a release asset, again when the connection or the server
+/// failed
+fn download(
+    http: &dyn HttpClient,
+    release_asset: &GHReleaseAsset,
+    asset_api: Option<&AssetApi>,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<Vec<u8>> {
+    let authorization;
+    let (url, headers) = match asset_api {
+        Some(asset_api) => {
+            authorization = format!("Bearer {}", asset_api.token);
+            (
+                release::asset_api_url(asset_api.api_base, release_asset)?.to_string(),
+                vec![
+                    ("Accept", "application/octet-stream"),
+                    ("Authorization", authorization.as_str()),
+                ],
+            )
+        }
+        None => (release_asset.browser_download_url.clone(), Vec::new()),
+    };
+    // Only failures a later attempt may not run into are retried, a missing
+    // asset or a refused host fails right away
+    let resp = retry.run(
+        || {
+            let resp = http.get(&url, &headers, timeout)?;
+            if resp.status >= 500 || resp.status == 429 {
+                return Err(Error::new(http::TransientError(format!(
+                    "Fetching archive {} failed with error {}",
+                    release_asset.name, resp.status
+                ))));
+            }
+            Ok(resp)
+        },
+        http::is_transient,
+    )?;
+    if resp.status != 200 {
+        return Err(Error::msg(format!(
+            "Fetching archive {} failed with error {}",
+            release_asset.name, resp.status
+        )));
+    }
+
+    // Catch mirrors and proxies answering with an error page
+    if let Some(content_type) = resp.header("content-type") {
+        if content_type.starts_with("text/html") {
+            return Err(Error::msg(format!(
+                "Fetching archive {} returned {} instead of an archive",
+                release_asset.name, content_type
+            )));
+        }
+    }
+
+    Ok(resp.body)
+}
+
+/// Download the archive of a release asset, putting split archives back
+/// together from all of their parts
+fn download_archive(
+    http: &dyn HttpClient,
+    release: &GHAsset,
+    release_asset: &GHReleaseAsset,
+    asset_api: Option<&AssetApi>,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<Vec<u8>> {
+    let archive_name = match release::is_split_asset(&release_asset.name) {
+        Some((archive_name, _)) => archive_name,
+        None => return download(http, release_asset, asset_api, timeout, retry),
+    };
+
+    let mut archive = Vec::new();
+    for part in release.split_parts(&archive_name)? {
+        archive.extend(download(http, part, asset_api, timeout, retry)?);
+    }
+    Ok(archive)
+}
+
+/// Tell the user how much space an install needs when it ran out of it,
+/// estimated from the download size of its assets
+pub fn explain_out_of_space(err: Error, needed_bytes: u64) -> Error {
+    if !fs_retry::is_out_of_space(&err) {
+        return err;
+    }
+
+    let needed_mb = (needed_bytes + (1 << 20) - 1) >> 20;
+    Error::msg(format!(
+        "Not enough disk space to install serve-d (need ~{} MB)",
+        needed_mb.max(1)
+    ))
+}
+
+/// Download the release `assets` and check their signatures and published
+/// checksums, without touching the install
+///
+/// Returns the archives in the order of `assets`.
+fn fetch_assets(
+    http: &dyn HttpClient,
+    host: &dyn Host,
+    release: &GHAsset,
+    assets: &[&GHReleaseAsset],
+    target: &InstallTarget,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<Vec<Vec<u8>>> {
+    let asset_api = target.asset_api.as_ref();
+    let checksum_manifest = checksum::manifest_asset(&release.assets)
+        .map(|manifest| download(http, manifest, asset_api, timeout, retry))
+        .transpose()?;
+    let mut archives = Vec::new();
+    for release_asset in assets {
+        let archive_buf =
+            download_archive(http, release, release_asset, asset_api, timeout, retry)?;
+        let archive_name = release_asset.archive_name();
+
+        // Check the detached signature before anything gets extracted
+        let signature = signature::signature_asset(&release.assets, &archive_name);
+        if signature::needs_verification(target.signing_key, release_asset, signature)? {
+            if let (Some(signing_key), Some(signature)) = (target.signing_key, signature) {
+                let signature = download(http, signature, asset_api, timeout, retry)?;
+                signature::verify(host, signing_key, &archive_buf, &signature, target.temp_dir)?;
+                host.log(&format!("Verified the signature of {}", archive_name));
+            }
+        }
+
+        // Releases publish checksums per asset, in a manifest asset or in
+        // their notes
+        let checksum_file = checksum::checksum_asset(&release.assets, &archive_name)
+            .map(|checksum_asset| download(http, checksum_asset, asset_api, timeout, retry))
+            .transpose()?;
+        let sources = checksum::ChecksumSources {
+            asset_file: checksum_file.as_deref(),
+            manifest: checksum_manifest.as_deref(),
+            release_body: release.body.as_deref(),
+        };
+        if let Some(expected) = checksum::find_checksum(&sources, &archive_name) {
+            checksum::verify(&archive_name, &archive_buf, &expected)?;
+            host.log(&format!("Verified the checksum of {}", archive_name));
+        }
+
+        archives.push(archive_buf);
+    }
+
+    Ok(archives)
+}
+
+/// Extract the downloaded `archives` of the release `assets` into the
+/// target directory, recording the install in its manifest and version file
+///
+/// Returns the number of downloaded bytes.
+fn apply_assets(
+    host: &dyn Host,
+    release: &GHAsset,
+    assets: &[&GHReleaseAsset],
+    archives: Vec<Vec<u8>>,
+    target: &InstallTarget,
+) -> Result<u64> {
+    let InstallTarget {
+        install_dir,
+        verfile,
+        kind,
+        channel,
+        exec_file,
+        reuse_unchanged,
+        extract_only,
+        core_only,
+        temp_dir,
+        ..
+    } = *target;
+    let previous = InstallManifest::read(install_dir)?;
+
+    let needed_bytes = assets
+        .iter()
+        .map(|release_asset| release_asset.size.max(0) as u64)
+        .sum();
+    let mut download_bytes = 0;
+    let mut manifest = InstallManifest {
+        version: release.tag_name.clone(),
+        channel: Some(channel),
+        ..Default::default()
+    };
+
+    for (release_asset, archive_buf) in assets.iter().zip(archives) {
+        download_bytes += archive_buf.len() as u64;
+        let sha256 = manifest::sha256_hex(&archive_buf);
+
+        // Releases that only bump the version may ship the same archive,
+        // reuse its files instead of extracting them again
+        let unchanged = previous
+            .as_ref()
+            .filter(|_| reuse_unchanged)
+            .and_then(|previous| previous.unchanged_files(&sha256, install_dir));
+        let files = match unchanged {
+            Some(files) => {
+                host.log(&format!(
+                    "{} is unchanged, skipping extraction",
+                    release_asset.name
+                ));
+                files
+            }
+            // Extract zip or tar archive
+            None => {
+                let extracted = extract_staged(archive_buf, kind, temp_dir, install_dir, &|path| {
+                    if core_only {
+                        path.file_name() == Some(OsStr::new(exec_file))
+                    } else {
+                        archive::keep_entry(path, extract_only, exec_file)
+                    }
+                })
+                .map_err(|err| explain_out_of_space(err, needed_bytes))?;
+                apply_modes(host, install_dir, &extracted, exec_file)?;
+                extracted.into_iter().map(|file| file.path).collect()
+            }
+        };
+
+        manifest.assets.push(ManifestAsset {
+            url: release_asset.browser_download_url.clone(),
+            sha256,
+            files,
+        });
+    }
+
+    // Remove the files of the previous install the new one doesn't have
+    if let Some(previous) = previous {
+        for file in previous.stale_files(&manifest, install_dir) {
+            if file.exists() {
+                fs_retry::retry(|| fs::remove_file(&file))?;
+            }
+        }
+    }
+
+    // Remember the binary checksum to detect corruption later on
+    let files = manifest.files();
+    if let Some(binary) = locate_binary(install_dir, exec_file, &files) {
+        manifest.binary_sha256 = Some(manifest::sha256_hex(&fs::read(install_dir.join(&binary))?));
+        manifest.binary = Some(binary);
+    }
+
+    // Record what and which version we installed
+    manifest
+        .write(install_dir)
+        .map_err(|err| explain_out_of_space(err, needed_bytes))?;
+    fs_retry::retry(|| fs::write(verfile, &release.tag_name))
+        .map_err(|err| explain_out_of_space(err, needed_bytes))?;
+
+    Ok(download_bytes)
+}
+
+/// Download the release `assets` and extract them into the target
+/// directory, recording the install in its manifest and version file
+///
+/// Every asset is downloaded and verified before the first one gets
+/// extracted. Returns the number of downloaded bytes.
+pub fn install_release(
+    http: &dyn HttpClient,
+    host: &dyn Host,
+    release: &GHAsset,
+    assets: &[&GHReleaseAsset],
+    target: &InstallTarget,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<u64> {
+    let archives = fetch_assets(http, host, release, assets, target, timeout, retry)?;
+    apply_assets(host, release, assets, archives, target)
+}
+
+/// Check that the freshly installed serve-d binary runs, warnings only
+/// failing the check if `strict`
+fn check_launches(
+    host: &dyn Host,
+    install_dir: &Path,
+    exec_file: &str,
+    strict: bool,
+) -> Result<()> {
+    let files = InstallManifest::read(install_dir)?
+        .map(|manifest| manifest.files())
+        .unwrap_or_default();
+    let binary = locate_binary(install_dir, exec_file, &files)
+        .ok_or_else(|| Error::msg(format!("{} is missing from the install", exec_file)))?;
+    let output = host.execute(
+        &install_dir.join(binary).display().to_string(),
+        &["--version"],
+    )?;
+    server::StartupOutput::classify(output.success, &String::from_utf8_lossy(&output.stderr))
+        .check(host, strict)
+        .map_err(|log| Error::msg(format!("The installed serve-d does not run: {}", log)))
+}
+
+/// Result of [`install_transaction`]
+pub struct InstallOutcome {
+    pub download_bytes: u64,
+    /// Whether the install that was replaced is kept for
+    /// [`restore_previous`]
+    pub kept_previous: bool,
+}
+
+/// Install a release all-or-nothing
+///
+/// 1. Download every asset and check its signature and checksum, a failure
+///    leaves the install untouched
+/// 2. Keep the current install aside
+/// 3. Extract through the staging directory and move the files into place
+/// 4. Check that the new binary runs
+///
+/// A failure in the last two steps puts the kept install back. A release
+/// whose binary doesn't run is remembered, so it isn't installed again.
+pub fn install_transaction(
+    http: &dyn HttpClient,
+    host: &dyn Host,
+    release: &GHAsset,
+    assets: &[&GHReleaseAsset],
+    target: &InstallTarget,
+    timeout: Duration,
+    retry: &RetryPolicy,
+) -> Result<InstallOutcome> {
+    let archives = fetch_assets(http, host, release, assets, target, timeout, retry)?;
+
+    let kept_previous = keep_previous(target.install_dir, target.verfile)?;
+    let rollback = |err: Error, failed: Option<&str>| -> Error {
+        if !kept_previous {
+            return err;
+        }
+        match restore_previous(target.install_dir, target.verfile, failed) {
+            Ok(restored) => err.context(format!(
+                "Installing serve-d {} failed, rolled back to {}",
+                release.tag_name, restored
+            )),
+            Err(restore_err) => err.context(format!(
+                "Installing serve-d {} failed and rolling back failed too: {}",
+                release.tag_name, restore_err
+            )),
+        }
+    };
+
+    let download_bytes =
+        apply_assets(host, release, assets, archives, target).map_err(|err| rollback(err, None))?;
+    check_launches(
+        host,
+        target.install_dir,
+        target.exec_file,
+        target.strict_startup,
+    )
+    .map_err(|err| rollback(err, Some(&release.tag_name)))?;
+
+    Ok(InstallOutcome {
+        download_bytes,
+        kept_previous,
+    })
+}
+
+/// Fill the `{installDir}` and `{version}` placeholders of a hook argument
+pub fn substitute_placeholders(arg: &str, install_dir: &Path, version: &str) -> String {
+    arg.replace("{installDir}", &install_dir.display().to_string())
+        .replace("{version}", version)
+}
+
+/// Run the `postInstallCommand` hook after an install, logging its output
+///
+/// The hook runs arbitrary commands, so it only runs when `allowed` is set.
+pub fn run_post_install(
+    host: &dyn Host,
+    command: &[String],
+    allowed: bool,
+    install_dir: &Path,
+    version: &str,
+) -> Result<()> {
+    let (program, args) = match command.split_first() {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    if !allowed {
+        return Err(Error::msg(
+            "postInstallCommand is set but allowPostInstallCommand is not, refusing to run it",
+        ));
+    }
+
+    let program = substitute_placeholders(program, install_dir, version);
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| substitute_placeholders(arg, install_dir, version))
+        .collect();
+    let output = host.execute(
+        &program,
+        &args.iter().map(String::as_str).collect::<Vec<_>>(),
+    )?;
+
+    for (stream, data) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        let data = String::from_utf8_lossy(data);
+        if !data.trim().is_empty() {
+            host.log(&format!("postInstallCommand {}: {}", stream, data.trim()));
+        }
+    }
+    if !output.success {
+        return Err(Error::msg(format!("postInstallCommand {} failed", program)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockHost, TestDir};
+
+    #[test]
+    fn creates_missing_parent_directories() {
+        let root = TestDir::new("ensure-dirs");
+        let server = root.join("v0").join("serve-d");
+        let version = root.join("v0").join("meta").join("version");
+        ensure_dirs(&[&server, &version, Path::new("relative")]).unwrap();
+
+        assert!(root.join("v0").is_dir());
+        assert!(root.join("v0").join("meta").is_dir());
+        assert!(!server.exists());
+        // Already existing parents are fine
+        ensure_dirs(&[&server]).unwrap();
+    }
+
+    #[test]
+    fn removes_the_created_dir_unless_kept() {
+        let root = TestDir::new("created-dir");
+        let install_dir = root.join("v0");
+        let server = install_dir.join("serve-d");
+
+        drop(CreatedDir::ensure(&install_dir, &[&server]).unwrap());
+        assert!(!install_dir.exists());
+
+        CreatedDir::ensure(&install_dir, &[&server]).unwrap().keep();
+        assert!(install_dir.is_dir());
+
+        // A directory that was already there is never removed
+        drop(CreatedDir::ensure(&install_dir, &[&server]).unwrap());
+        assert!(install_dir.is_dir());
+    }
+
+    #[test]
+    fn only_windows_binaries_count_as_locked() {
+        let root = TestDir::new("binary-locked");
+        let binary = root.join("serve-d.exe");
+        assert!(!is_binary_locked("windows", &binary));
+
+        fs::write(&binary, b"MZ").unwrap();
+        assert!(!is_binary_locked("windows", &binary));
+
+        // A directory can't be opened for writing either, like a running
+        // binary on Windows
+        let running = root.join("running.exe");
+        fs::create_dir_all(&running).unwrap();
+        assert!(is_binary_locked("windows", &running));
+        assert!(!is_binary_locked("linux", &running));
+    }
+
+    #[test]
+    fn locates_the_shallowest_extracted_binary() {
+        let files = [
+            PathBuf::from("serve-d_0.7.6/tools/serve-d"),
+            PathBuf::from("serve-d_0.7.6/serve-d"),
+            PathBuf::from("serve-d_0.7.6/dcd-server"),
+        ];
+        let binary = locate_binary(Path::new("/opt/serve-d"), "serve-d", &files).unwrap();
+        assert_eq!(binary, PathBuf::from("serve-d_0.7.6/serve-d"));
+        assert_eq!(url_path(&binary), "serve-d_0.7.6/serve-d");
+    }
+
+    #[test]
+    fn falls_back_to_a_binary_at_the_top_level() {
+        let root = TestDir::new("locate-binary");
+        assert_eq!(locate_binary(&root, "serve-d", &[]), None);
+
+        fs::write(root.join("serve-d"), b"").unwrap();
+        assert_eq!(
+            locate_binary(&root, "serve-d", &[PathBuf::from("dcd-server")]),
+            Some(PathBuf::from("serve-d"))
+        );
+    }
+
+    #[test]
+    fn substitutes_hook_placeholders() {
+        assert_eq!(
+            substitute_placeholders(
+                "{installDir}/serve-d-{version}.log",
+                Path::new("/opt/serve-d"),
+                "v0.8.0"
+            ),
+            "/opt/serve-d/serve-d-v0.8.0.log"
+        );
+        assert_eq!(
+            substitute_placeholders("{other}", Path::new("/opt/serve-d"), "v0.8.0"),
+            "{other}"
+        );
+    }
+
+    #[test]
+    fn runs_the_hook_only_when_allowed() {
+        let host = MockHost {
+            programs: [(
+                "notify /opt/serve-d v0.8.0".to_string(),
+                "notified".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let command = vec![
+            "notify".to_string(),
+            "{installDir}".to_string(),
+            "{version}".to_string(),
+        ];
+        let install_dir = Path::new("/opt/serve-d");
+
+        let err = run_post_install(&host, &command, false, install_dir, "v0.8.0").unwrap_err();
+        assert!(
+            err.to_string().contains("allowPostInstallCommand"),
+            "{}",
+            err
+        );
+        assert!(host.logs.borrow().is_empty());
+
+        run_post_install(&host, &command, true, install_dir, "v0.8.0").unwrap();
+        assert_eq!(
+            *host.logs.borrow(),
+            vec!["postInstallCommand stdout: notified".to_string()]
+        );
+        run_post_install(&host, &[], false, install_dir, "v0.8.0").unwrap();
+    }
+
+    #[test]
+    fn applies_the_archive_modes_with_chmod() {
+        let file = |path: &str, mode: Option<u32>| ExtractedFile {
+            path: PathBuf::from(path),
+            mode,
+        };
+        let files = [
+            file("serve-d", Some(0o644)),
+            file("dcd-server", Some(0o4755)),
+            file("dcd-client", Some(0o755)),
+            file("README.md", Some(0o644)),
+            file("LICENSE", None),
+        ];
+        let install_dir = Path::new("/opt/serve-d");
+        // Every chmod other than these fails as not found
+        let host = MockHost {
+            os: Some("linux"),
+            programs: [
+                "chmod 644 /opt/serve-d/README.md",
+                "chmod 755 /opt/serve-d/serve-d /opt/serve-d/dcd-server /opt/serve-d/dcd-client",
+            ]
+            .iter()
+            .map(|command| (command.to_string(), String::new()))
+            .collect(),
+            ..Default::default()
+        };
+        apply_modes(&host, install_dir, &files, "serve-d").unwrap();
+
+        // Windows has no modes to apply
+        let host = MockHost::default();
+        apply_modes(&host, install_dir, &files, "serve-d.exe").unwrap();
+
+        let host = MockHost {
+            os: Some("linux"),
+            failing: ["chmod 755 /opt/serve-d/serve-d".to_string()].into(),
+            ..Default::default()
+        };
+        let err = apply_modes(&host, install_dir, &[file("serve-d", None)], "serve-d").unwrap_err();
+        assert!(
+            err.to_string().contains("Setting file modes failed"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn explains_running_out_of_disk_space() {
+        // ENOSPC as the WASI host reports it
+        let full = || Error::from(std::io::Error::from_raw_os_error(51)).context("Writing failed");
+        assert_eq!(
+            explain_out_of_space(full(), 25 << 20).to_string(),
+            "Not enough disk space to install serve-d (need ~25 MB)"
+        );
+        assert_eq!(
+            explain_out_of_space(full(), (25 << 20) + 1).to_string(),
+            "Not enough disk space to install serve-d (need ~26 MB)"
+        );
+        assert_eq!(
+            explain_out_of_space(full(), 0).to_string(),
+            "Not enough disk space to install serve-d (need ~1 MB)"
+        );
+
+        let other = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let err = explain_out_of_space(other, 25 << 20);
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn downloads_private_assets_through_the_api() {
+        let asset = GHReleaseAsset {
+            id: 4242,
+            name: "serve-d_0.8.0-x86_64-linux.tar.xz".to_string(),
+            size: 7,
+            download_count: 0,
+            browser_download_url: "https://github.com/Pure-D/serve-d/releases/download/v0.8.0/serve-d_0.8.0-x86_64-linux.tar.xz".to_string(),
+            created_at: String::new(),
+        };
+        let api_url = "https://api.github.com/repos/Pure-D/serve-d/releases/assets/4242";
+        let mut http = crate::mock::MockHttp::release("v0.8.0", &[]);
+        http.responses
+            .insert(api_url.to_string(), b"archive".to_vec());
+        http.responses
+            .insert(asset.browser_download_url.clone(), b"public".to_vec());
+        let api_base = release::api_base_url(release::DEFAULT_GITHUB_HOST).unwrap();
+        let asset_api = AssetApi {
+            api_base: &api_base,
+            token: "ghp_private",
+        };
+        let timeout = Duration::from_secs(10);
+
+        let data = download(
+            &http,
+            &asset,
+            Some(&asset_api),
+            timeout,
+            &RetryPolicy::DOWNLOAD,
+        )
+        .unwrap();
+        assert_eq!(data, b"archive");
+        let data = download(&http, &asset, None, timeout, &RetryPolicy::DOWNLOAD).unwrap();
+        assert_eq!(data, b"public");
+
+        let requests = http.requests.borrow();
+        assert_eq!(requests[0].0, api_url);
+        assert_eq!(
+            requests[0].1,
+            vec![
+                ("Accept".to_string(), "application/octet-stream".to_string()),
+                (
+                    "Authorization".to_string(),
+                    "Bearer ghp_private".to_string()
+                ),
+            ]
+        );
+        assert_eq!(requests[1].0, asset.browser_download_url);
+        assert!(requests[1].1.is_empty());
+    }
+}
@@ -1,209 +1,2449 @@
 use std::{
-    fs::{self, create_dir_all},
-    path::PathBuf,
+    cell::{Cell, RefCell},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{Error, Result};
 use lapce_plugin::{
     psp_types::{
-        lsp_types::{request::Initialize, InitializeParams, Url},
+        lsp_types::{request::Initialize, InitializeParams, MessageType, Url},
         Request,
     },
-    register_plugin, LapcePlugin, VoltEnvironment, PLUGIN_RPC,
+    register_plugin, LapcePlugin, PLUGIN_RPC,
 };
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::Value;
-use std::io::Cursor;
-use tar_wasi::Archive;
-use zip::ZipArchive;
+
+use crate::{
+    archive::ArchiveKind,
+    host::{Host, LapceHost, LogFile, LogFormat},
+    http::{HttpClient, WasiHttp},
+    manifest::InstallManifest,
+    options::{Options, UserOnlyOptions},
+    retry::RetryPolicy,
+};
+
+mod archive;
+mod checksum;
+mod compiler;
+mod credentials;
+mod export;
+mod fs_retry;
+mod host;
+mod http;
+mod install;
+mod lock;
+mod manifest;
+#[cfg(test)]
+mod mock;
+mod options;
+mod platform;
+mod release;
+mod retry;
+mod selftest;
+mod server;
+mod signature;
+mod stats;
+mod version;
+mod workspace;
 
 #[derive(Default)]
-struct State {}
+struct State {
+    /// Directory serve-d is installed into, once initialized
+    install_dir: Option<PathBuf>,
+    /// How serve-d was last started
+    launch: Option<Launch>,
+    log_format: LogFormat,
+    quiet: bool,
+    /// File logs are written to besides stderr, once initialized
+    log_file: Option<LogFile>,
+    /// Error the last initialization failed with
+    last_error: Option<String>,
+    /// GitHub API base url, once initialized
+    api_base: Option<Url>,
+    /// Why serve-d was or wasn't updated on the last initialization
+    update_reason: Option<version::UpdateReason>,
+}
+
+impl State {
+    fn host(&self) -> LapceHost {
+        LapceHost {
+            log_format: Cell::new(self.log_format),
+            quiet: Cell::new(self.quiet),
+            log_file: RefCell::new(self.log_file.clone()),
+        }
+    }
+
+    /// Start serve-d again the way it was last started
+    fn restart(&self, host: &dyn Host) -> Result<()> {
+        let launch = self
+            .launch
+            .as_ref()
+            .ok_or_else(|| Error::msg("serve-d has not been started"))?;
+        launch.start(host);
+        Ok(())
+    }
+}
 
 register_plugin!(State);
 
 const LANGUAGE_ID: &str = "dlang";
+const LOG_FILE_NAME: &str = "lapce-dlang.log";
+/// Lock file held while updating an install shared between workspaces
+const INSTALL_LOCK_NAME: &str = "install.lock";
 
-#[derive(Serialize, Deserialize)]
-struct GHAsset {
-    tag_name: String,
-    assets: Vec<GHReleaseAsset>,
+/// Resolved serve-d command and options, kept to start serve-d again
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Launch {
+    server_path: Url,
+    server_args: Vec<String>,
+    options: Option<Value>,
+    /// Environment variables set for serve-d
+    env: Vec<(String, String)>,
+    language_ids: Vec<String>,
+    /// Also run serve-d with its arguments in the health check, to catch it
+    /// exiting right away
+    #[serde(skip)]
+    early_exit_check: bool,
+    /// Fail the health check on warnings of serve-d too
+    #[serde(skip)]
+    strict_startup: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GHReleaseAsset {
-    id: isize,
-    name: String,
-    size: isize,
-    download_count: isize,
-    browser_download_url: String,
-    created_at: String,
+impl Launch {
+    fn start(&self, host: &dyn Host) {
+        let command = if self.env.is_empty() {
+            Ok((self.server_path.clone(), self.server_args.clone()))
+        } else if host.operating_system().ok().as_deref() == Some("windows") {
+            Err(Error::msg(
+                "Setting the serve-d environment is not supported on Windows",
+            ))
+        } else {
+            server::env_command(&self.server_path, &self.server_args, &self.env)
+        };
+        let (server_path, server_args) = command.unwrap_or_else(|err| {
+            host.log(&format!("{}, starting serve-d without it", err));
+            (self.server_path.clone(), self.server_args.clone())
+        });
+
+        // Available language IDs
+        // https://github.com/lapce/lapce/blob/HEAD/lapce-proxy/src/buffer.rs#L173
+        //
+        // The plugin API takes a single language ID per server, so each one
+        // gets its own serve-d
+        for language_id in &self.language_ids {
+            host.start_lsp(
+                server_path.clone(),
+                server_args.clone(),
+                language_id,
+                self.options.clone(),
+            );
+        }
+    }
+
+    /// Check that serve-d runs, the plugin API doesn't report whether the
+    /// started server came up or exited
+    fn check(&self, host: &dyn Host) -> Result<()> {
+        server::health_check(host, &self.server_path, self.strict_startup)?;
+        if self.early_exit_check {
+            server::early_exit_check(
+                host,
+                &self.server_path,
+                &self.server_args,
+                self.strict_startup,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Start serve-d and check that it runs, the plugin API doesn't report
+    /// whether it came up
+    ///
+    /// When the check fails, serve-d from PATH is started instead if
+    /// `fallback_to_path` is set.
+    fn start_checked(self, host: &dyn Host, fallback_to_path: bool) -> Result<Launch> {
+        self.start(host);
+
+        let err = match self.check(host) {
+            Ok(()) => return Ok(self),
+            Err(err) => err,
+        };
+        host.show_message(
+            MessageType::ERROR,
+            format!("serve-d failed its health check: {}", err),
+        );
+
+        let path_server = Url::parse("urn:serve-d")?;
+        if !fallback_to_path || self.server_path == path_server {
+            return Ok(self);
+        }
+
+        host.log("Falling back to serve-d from PATH");
+        let launch = Launch {
+            server_path: path_server,
+            ..self
+        };
+        launch.start(host);
+        Ok(launch)
+    }
 }
 
-fn initialize(params: InitializeParams) -> Result<()> {
-    let mut server_args = vec!["--require".to_string(), "d".to_string()];
-    let mut installed_version = semver::Version::parse("v0.0.0")?;
+/// URL of the installed serve-d binary, where the install actually put it
+///
+/// Archives may nest the binary in a directory.
+fn installed_server_path(install_dir: &Path, install_url: &Url, exec_file: &str) -> Result<Url> {
+    let installed_files = InstallManifest::read(install_dir)?
+        .map(|manifest| manifest.files())
+        .unwrap_or_default();
+    let binary = install::locate_binary(install_dir, exec_file, &installed_files)
+        .ok_or_else(|| Error::msg(format!("{} not found in the install directory", exec_file)))?;
+    Ok(install_url.join(install::url_path(&binary).as_str())?)
+}
+
+fn initialize(
+    state: &mut State,
+    host: &dyn Host,
+    http: &dyn HttpClient,
+    mut params: InitializeParams,
+) -> Result<()> {
+    let (user_only, invalid_user_only) =
+        UserOnlyOptions::parse(params.initialization_options.as_ref());
+
+    // Project config shared through the workspace, taking the
+    // `workspace::CONFIG_KEYS` of the `lsp` block, which overrides it, and
+    // only the `workspace::SERVER_SETTINGS` of its serverSettings
+    // ```json
+    // { "serverSettings": { "d": { "projectImportPaths": ["source"] } } }
+    // ```
+    if let Some(root) = workspace::workspace_root(&params) {
+        let config = workspace::read_config(&root)
+            .and_then(|config| config.map(workspace::allowed_config).transpose());
+        match config {
+            Ok(Some((config, ignored))) => {
+                host.log(&format!(
+                    "Using {}",
+                    root.join(workspace::CONFIG_FILE).display()
+                ));
+                for key in ignored {
+                    let allowed = if key.starts_with("serverSettings") {
+                        workspace::SERVER_SETTINGS
+                    } else {
+                        workspace::CONFIG_KEYS
+                    };
+                    host.log(&format!(
+                        "Ignoring {} in {}, a project config may only set {}",
+                        key,
+                        workspace::CONFIG_FILE,
+                        allowed.join(", ")
+                    ));
+                }
+                params.initialization_options =
+                    workspace::merge_config(params.initialization_options.take(), config);
+            }
+            Ok(None) => {}
+            Err(err) => host.show_message(
+                MessageType::WARNING,
+                format!("Ignoring invalid {}: {}", workspace::CONFIG_FILE, err),
+            ),
+        }
+    }
+
+    let (mut options, invalid) = Options::parse(params.initialization_options.take());
+    // Log lines as JSON objects instead of plain text, and only report
+    // errors
+    // ```
+    // [lapce-plugin-name.lsp]
+    // logFormat = "json" # or "human"
+    // quiet = true
+    // ```
+    state.log_format = options.log_format;
+    state.quiet = options.quiet;
+    host.set_logging(options.log_format, options.quiet);
+    // A mistyped option only loses its own setting, the rest still applies
+    for invalid in invalid_user_only.iter().chain(&invalid) {
+        host.show_message(
+            MessageType::WARNING,
+            format!("Ignoring invalid option {}", invalid),
+        );
+    }
+    // Path options may use environment variables, e.g.
+    // `serverPath = "$HOME/bin/serve-d"` or `%LOCALAPPDATA%` on Windows
+    options.expand_path_vars(
+        host.operating_system().ok().as_deref() == Some("windows"),
+        |key| host.env_var(key),
+    );
+
+    let server_args = server::build_server_args(
+        options.require_features.as_deref(),
+        &options.os_args,
+        &options.server_args,
+        host.operating_system().ok().as_deref(),
+    );
+    let server_settings = server::managed_settings(
+        options.dcd_server_path.as_deref(),
+        options.dcd_client_path.as_deref(),
+        options.core_only,
+        options.use_bundled_dcd,
+    )?;
+    // Settings the plugin derives go over the `serverSettings` passed as is
+    let server_settings = server::deep_merge(
+        Value::Object(options.server_settings.clone()),
+        server_settings,
+    );
+
+    // Point serve-d at the standard library, found inside the GCC
+    // installation for GDC
+    // ```
+    // [lapce-plugin-name.lsp]
+    // compiler = "gdc" # or "dmd", "ldc", probed from PATH by default
+    // stdlibPaths = ["[import path]"]
+    // ```
+    let server_settings = server::deep_merge(
+        server_settings,
+        compiler::stdlib_settings(
+            host,
+            options.compiler.as_deref(),
+            options.stdlib_paths.clone(),
+        ),
+    );
+
+    // DFLAGS for the dub and compiler runs of serve-d, replacing $DFLAGS
+    // ```
+    // [lapce-plugin-name.lsp]
+    // dflags = "-preview=dip1000"
+    // ```
+    let server_env = server::dflags_env(options.dflags.as_deref(), host.env_var("DFLAGS"));
+
+    // Check that serve-d runs after starting it
+    // ```
+    // [lapce-plugin-name.lsp]
+    // healthCheck = true
+    // fallbackToPath = true # also start serve-d from PATH when the check fails
+    // ```
+    let health_check = options.health_check;
+    let fallback_to_path = options.fallback_to_path;
+    // Also catch serve-d exiting right away with its arguments, e.g. on bad
+    // configuration, as part of the health check
+    // ```
+    // [lapce-plugin-name.lsp]
+    // earlyExitCheck = true
+    // ```
+    let early_exit_check = options.early_exit_check;
+    // Let warnings serve-d prints on startup fail the checks as well
+    // ```
+    // [lapce-plugin-name.lsp]
+    // strictStartup = true
+    // ```
+    let strict_startup = options.strict_startup;
+    // Install or update serve-d without starting it, e.g. to provision it
+    // from a setup script
+    // ```
+    // [lapce-plugin-name.lsp]
+    // installOnly = true
+    // ```
+    let install_only = options.install_only;
+    // Import paths of the project, globs expand against the workspace root
+    // ```
+    // [lapce-plugin-name.lsp]
+    // importPaths = ["source", "subprojects/*/source"]
+    // ```
+    let server_settings = server::deep_merge(
+        server_settings,
+        workspace::import_path_settings(
+            workspace::workspace_root(&params).as_deref(),
+            &options.import_paths,
+        ),
+    );
+
+    // Tuning knobs of serve-d for large codebases
+    // ```
+    // [lapce-plugin-name.lsp.limits]
+    // manyProjectsThreshold = 6
+    // softMaxLineLength = 80
+    // maxLineLength = 120
+    // ```
+    let server_settings =
+        server::deep_merge(server_settings, server::limit_settings(&options.limits)?);
+    // Resolve the dependencies of standalone scripts with a dub single-file
+    // recipe header
+    // ```
+    // [lapce-plugin-name.lsp]
+    // dubSingleFile = true
+    // ```
+    let server_settings = server::deep_merge(
+        server_settings,
+        server::dub_single_file_settings(options.dub_single_file),
+    );
+    // Language IDs routed to serve-d, e.g. to also serve dub recipes
+    // ```
+    // [lapce-plugin-name.lsp]
+    // languageIds = ["dlang", "sdl"]
+    // ```
+    let language_ids = if options.language_ids.is_empty() {
+        vec![LANGUAGE_ID.to_string()]
+    } else {
+        options.language_ids.clone()
+    };
+
+    // Connect to serve-d running elsewhere instead of installing it
+    // ```
+    // [lapce-plugin-name.lsp]
+    // remote = { host = "devcontainer", port = 8765 }
+    // ```
+    if let Some(remote) = &options.remote {
+        if install_only {
+            host.log("remote is set, there is nothing to install");
+            return Ok(());
+        }
+        if host.operating_system().ok().as_deref() == Some("windows") {
+            return Err(Error::msg(
+                "Connecting to a remote serve-d is not supported on Windows",
+            ));
+        }
+
+        let (server_path, server_args) = remote.command()?;
+        host.log(&format!(
+            "Connecting to serve-d at {}:{}",
+            remote.host, remote.port
+        ));
+        let launch = Launch {
+            server_path,
+            server_args,
+            options: server::build_server_options(options.raw, server_settings),
+            env: Vec::new(),
+            language_ids,
+            early_exit_check: false,
+            strict_startup,
+        };
+        launch.start(host);
+        state.launch = Some(launch);
+        return Ok(());
+    }
 
     // Check for user specified LSP server path
     // ```
     // [lapce-plugin-name.lsp]
     // serverPath = "[path or filename]"
     // serverArgs = ["--arg1", "--arg2"]
+    // osArgs = { windows = ["--arg"] } # keyed by "linux", "macos", "windows"
+    // requireFeatures = ["d", "dcd"]
+    // dcdServerPath = "[path to dcd-server]"
+    // dcdClientPath = "[path to dcd-client]"
+    //
+    // [lapce-plugin-name.lsp.serverSettings]
+    // d = { ... } # merged into the options passed to serve-d
     // ```
-    if let Some(options) = params.initialization_options.as_ref() {
-        if let Some(lsp) = options.get("lsp") {
-            if let Some(args) = lsp.get("serverArgs") {
-                if let Some(args) = args.as_array() {
-                    for arg in args {
-                        if let Some(arg) = arg.as_str() {
-                            server_args.push(arg.to_string());
-                        }
-                    }
-                }
-            }
+    let server_path = options
+        .server_path
+        .as_deref()
+        .filter(|server_path| !server_path.is_empty());
+    if let Some(server_path) = server_path {
+        if install_only {
+            host.log("serverPath is set, there is nothing to install");
+            return Ok(());
+        }
 
-            // Allow starting specific LSP
-            if let Some(server_path) = lsp.get("serverPath") {
-                if let Some(server_path) = server_path.as_str() {
-                    if !server_path.is_empty() {
-                        PLUGIN_RPC.start_lsp(
-                            Url::parse(&format!("urn:{}", server_path))?,
-                            server_args,
-                            LANGUAGE_ID,
-                            params.initialization_options,
-                        );
-                        return Ok(());
-                    }
-                }
-            }
+        // Catch a copied binary that lost its execute bit, names without a
+        // separator are looked up on PATH
+        let server_path = platform::trim_trailing_separators(server_path);
+        if server_path.contains(['/', '\\']) {
+            platform::ensure_executable(host, &host.operating_system()?, Path::new(server_path))?;
         }
+        let launch = Launch {
+            server_path: Url::parse(&format!("urn:{}", server_path))?,
+            server_args,
+            options: server::build_server_options(options.raw, server_settings),
+            env: server_env,
+            language_ids,
+            early_exit_check,
+            strict_startup,
+        };
+        state.launch = Some(if health_check {
+            launch.start_checked(host, fallback_to_path)?
+        } else {
+            launch.start(host);
+            launch
+        });
+        return Ok(());
     }
 
-    PLUGIN_RPC.stderr("AAAAAAAA");
+    // Platform check, preferring the native asset when running emulated
+    // ```
+    // [lapce-plugin-name.lsp]
+    // forceEmulatedArch = true
+    // fallbackToPath = true # use serve-d from PATH on unsupported platforms
+    // ```
+    let force_emulated_arch = options.force_emulated_arch;
+    let detected = host
+        .operating_system()
+        .and_then(|os| platform::os_name(&os))
+        .and_then(|os_name| {
+            let arch = platform::select_arch(
+                host.architecture()?.as_str(),
+                platform::probe_native_arch(host, os_name).as_deref(),
+                force_emulated_arch,
+            )?;
+            Ok((os_name, arch))
+        });
+    let (os_name, arch) = match detected {
+        Ok(detected) => detected,
+        Err(err) => {
+            if !fallback_to_path || install_only {
+                return Err(err);
+            }
 
-    // Fetch asset from github to check version
-    let asset: GHAsset = serde_json::from_str(
-        String::from_utf8(
-            lapce_plugin::Http::get("https://api.github.com/repos/Pure-D/serve-d/releases/latest")?
-                .body_read_all()?,
-        )?
-        .as_str(),
+            // No release for this platform, serve-d may still have been
+            // built locally
+            host.log(&format!("{}, using serve-d from PATH", err));
+            let launch = Launch {
+                server_path: Url::parse("urn:serve-d")?,
+                server_args,
+                options: server::build_server_options(options.raw, server_settings),
+                env: server_env,
+                language_ids,
+                early_exit_check,
+                strict_startup,
+            };
+            launch.start(host);
+            state.launch = Some(launch);
+            return Ok(());
+        }
+    };
+    host.log(&arch.describe());
+    let arch_name = arch.asset_arch;
+
+    // see http::HttpClient for available API to download files
+
+    // ```
+    // [lapce-plugin-name.lsp]
+    // binaryName = "serve-d" # for forks shipping a renamed executable
+    // ```
+    let exec_file = platform::exec_file_name(os_name, options.binary_name.as_deref());
+
+    // Install into the platform cache directory instead of the plugin
+    // directory, so serve-d survives plugin reinstalls
+    // ```
+    // [lapce-plugin-name.lsp]
+    // useCacheDir = true
+    // ```
+    let use_cache_dir = options.use_cache_dir;
+
+    // Share a single per-user install between all workspaces, in the cache
+    // directory, instead of one per plugin directory
+    // ```
+    // [lapce-plugin-name.lsp]
+    // sharedInstall = false
+    // ```
+    let shared_install = options.shared_install;
+
+    // Install directory, falling back to $LAPCE_DLANG_INSTALL_DIR and then
+    // the plugin directory or the cache directory
+    // ```
+    // [lapce-plugin-name.lsp]
+    // installDir = "[path]"
+    // ```
+    let install_dir_override = platform::install_dir_override(
+        options.install_dir.as_deref(),
+        host.env_var(platform::INSTALL_DIR_ENV),
+    );
+    let install_dir = if let Some(install_dir) = install_dir_override {
+        install_dir
+    } else if use_cache_dir {
+        platform::cache_dir(os_name, |key| host.env_var(key))
+            .ok_or_else(|| Error::msg("Could not resolve the platform cache directory"))?
+    } else if let Some(shared_dir) =
+        platform::cache_dir(os_name, |key| host.env_var(key)).filter(|_| shared_install)
+    {
+        shared_dir
+    } else {
+        platform::volt_dir(host)?
+    };
+    let install_dir = platform::canonical_dir(&install_dir);
+
+    // Keep serve-d major versions apart, so workspaces can each pin one
+    // ```
+    // [lapce-plugin-name.lsp]
+    // majorVersion = 0
+    // ```
+    let major_version = options.major_version;
+    let base_dir = install_dir;
+    let install_dir = match major_version {
+        Some(major) => base_dir.join(version::major_dir(major)),
+        None => base_dir.clone(),
+    };
+    let install_url = Url::from_directory_path(&install_dir)
+        .map_err(|_| Error::msg("Invalid install directory path"))?;
+    let server_file = install_dir.join(exec_file.as_str());
+    let verfile = install_dir.join("version.txt");
+
+    // Create server path if it doesn't already exist, along with any
+    // missing nested directories of the files we write. A directory created
+    // here is removed again if the install fails, the log file goes in only
+    // afterwards so it can't make a new directory pass for an install.
+    let install_dir_exists = install_dir.exists();
+    let created_dir = install::CreatedDir::ensure(
+        if base_dir.exists() {
+            &install_dir
+        } else {
+            &base_dir
+        },
+        &[&server_file, &verfile],
     )?;
 
-    // Architecture check
-    let arch_name = match VoltEnvironment::architecture().as_deref() {
-        Ok("x86_64") => "x86_64",
-        Ok("aarch64") => "arm64",
-        _ => return Err(Error::msg("Unsupported architecture")),
+    // Keep logs in a size-rotated file in the install directory as well
+    // ```
+    // [lapce-plugin-name.lsp]
+    // logFile = true
+    // logFileMaxBytes = 1048576
+    // logFileKeep = 3
+    // ```
+    if options.log_file {
+        install::ensure_dirs(&[&base_dir.join(LOG_FILE_NAME)])?;
+        let log_file = LogFile {
+            path: base_dir.join(LOG_FILE_NAME),
+            max_bytes: options.log_file_max_bytes,
+            keep: options.log_file_keep,
+        };
+        host.set_log_file(log_file.clone());
+        state.log_file = Some(log_file);
+    }
+
+    // Update policy
+    // ```
+    // [lapce-plugin-name.lsp]
+    // autoUpdate = false
+    // minVersion = "0.7.4"
+    // channel = "stable" # or "prerelease"
+    // replacePrerelease = true
+    // onNewerLocal = "keep" # or "downgrade"
+    // allowDowngrade = false # on by default when allowedVersions pins serve-d
+    // ```
+    let policy = version::UpdatePolicy {
+        auto_update: options.auto_update,
+        min_version: options
+            .min_version
+            .as_deref()
+            .map(version::parse_tag)
+            .transpose()?,
+        channel: options
+            .channel
+            .as_deref()
+            .map(version::Channel::parse)
+            .transpose()?
+            .unwrap_or(version::Channel::Stable),
+        replace_prerelease: options.replace_prerelease,
+        on_newer_local: options
+            .on_newer_local
+            .as_deref()
+            .map(version::NewerLocal::parse)
+            .transpose()?
+            .unwrap_or(version::NewerLocal::Keep),
+        pinned: !options.allowed_versions.is_empty(),
+        allow_downgrade: options.allow_downgrade,
+        force_update: options.internal_force_update,
     };
 
-    // OS check
-    let os_name = match VoltEnvironment::operating_system().as_deref() {
-        Ok("macos") => "macos",
-        Ok("linux") => "linux",
-        Ok("windows") => "windows",
-        _ => return Err(Error::msg("Unsupported platform")),
+    // Start an installed serve-d before checking for updates, so serve-d
+    // doesn't wait on the network. The check itself still runs before
+    // initialize returns, and can't be put off for later, see
+    // server::check_update_check_delay. Updates apply on the next start.
+    // ```
+    // [lapce-plugin-name.lsp]
+    // startupPolicy = "startInstalledFirst" # or "blocking"
+    // ```
+    server::check_update_check_delay(options.update_check_delay_secs)?;
+    let startup_policy = options
+        .startup_policy
+        .as_deref()
+        .map(server::StartupPolicy::parse)
+        .transpose()?
+        .unwrap_or(server::StartupPolicy::Blocking);
+    let installed = installed_server_path(&install_dir, &install_url, exec_file.as_str()).ok();
+    let mut started = None;
+    if !install_only && !startup_policy.blocks_on_update(installed.is_some()) {
+        if let Some(server_path) = installed {
+            let launch = Launch {
+                server_path,
+                server_args: server_args.clone(),
+                options: server::build_server_options(options.raw.clone(), server_settings.clone()),
+                env: server_env.clone(),
+                language_ids: language_ids.clone(),
+                early_exit_check,
+                strict_startup,
+            };
+            launch.start(host);
+            started = Some(launch);
+        }
+    }
+
+    // Request time limits, bounding retries and waits around requests since
+    // the host transport doesn't take a limit for requests themselves
+    // ```
+    // [lapce-plugin-name.lsp]
+    // apiTimeoutSecs = 10
+    // downloadTimeoutSecs = 300
+    // ```
+    let timeouts = http::Timeouts {
+        api: Duration::from_secs(options.api_timeout_secs),
+        download: Duration::from_secs(options.download_timeout_secs),
     };
 
-    // see lapce_plugin::Http for available API to download files
+    // Bounds of retrying failed archive downloads
+    // ```
+    // [lapce-plugin-name.lsp]
+    // downloadRetry = { attempts = 3, backoffMs = 1000, budgetSecs = 600 }
+    // ```
+    let download_retry = RetryPolicy::from_options(&options.download_retry, RetryPolicy::DOWNLOAD);
+
+    // ```
+    // [lapce-plugin-name.lsp]
+    // insecureSkipTlsVerify = true # testing only
+    // ```
+    http::check_insecure_tls(host, options.insecure_skip_tls_verify)?;
+
+    // ```
+    // [lapce-plugin-name.lsp]
+    // forceIpv4 = true
+    // dnsOverride = { "api.github.com" = "140.82.121.6" }
+    // ```
+    http::check_network_options(options.force_ipv4, options.dns_override.as_ref())?;
+
+    // GitHub token, falling back to $GITHUB_TOKEN and the netrc file, and
+    // how long a release check is reused by workspaces opened together
+    // ```
+    // [lapce-plugin-name.lsp]
+    // githubToken = "[token]"
+    // releaseCheckReuseSecs = 60
+    // ```
+    let netrc = credentials::netrc_path(os_name, |key| host.env_var(key))
+        .and_then(|netrc| fs::read_to_string(netrc).ok());
 
-    let exec_file = match VoltEnvironment::operating_system().as_deref() {
-        Ok("windows") => "serve-d.exe".to_string(),
-        _ => "serve-d".to_string(),
+    // GitHub Enterprise Server hosting a serve-d mirror
+    // ```
+    // [lapce-plugin-name.lsp]
+    // githubHost = "github.example.com"
+    // ```
+    let api_base = release::api_base_url(&options.github_host)?;
+    state.api_base = Some(api_base.clone());
+    // netrc entries name the host without its port
+    let web_host = options.github_host.split(':').next().unwrap_or_default();
+    // Headers mirrors or artifact proxies require on downloads, and on GitHub
+    // API requests as well if `downloadHeadersOnApi` is set. Headers the
+    // plugin sets itself are kept unless `overrideHeaders` is set.
+    // ```
+    // [lapce-plugin-name.lsp]
+    // downloadHeaders = { "X-Cache-Key" = "serve-d" }
+    // downloadHeadersOnApi = false
+    // overrideHeaders = false
+    // ```
+    let extra_headers = options
+        .download_headers
+        .clone()
+        .map(|headers| headers.into_iter().collect::<Vec<_>>());
+    let api_headers = extra_headers
+        .clone()
+        .filter(|_| options.download_headers_on_api)
+        .map(|headers| http::ExtraHeaders {
+            inner: http,
+            headers,
+            override_required: options.override_headers,
+        });
+    let api_http = match api_headers.as_ref() {
+        Some(api_headers) => api_headers as &dyn HttpClient,
+        None => http,
     };
 
-    // Plugin working directory
-    let volt_uri = VoltEnvironment::uri()?;
-    let server_path = Url::parse(&volt_uri)?.join(exec_file.as_str())?;
-    let verfile = PathBuf::from(format!("{0}/{1}", volt_uri, "version.txt"));
+    // Read only the fields needed from release responses that don't parse,
+    // e.g. after a GitHub API change
+    // ```
+    // [lapce-plugin-name.lsp]
+    // lenientReleaseParse = false
+    // ```
+    let github = release::GitHub {
+        http: api_http,
+        host,
+        token: credentials::github_token(
+            options.github_token.as_deref(),
+            |key| host.env_var(key),
+            netrc.as_deref(),
+            web_host,
+            api_base.host_str().unwrap_or_default(),
+        ),
+        api_base,
+        timeout: timeouts.api,
+        reuse_window: Duration::from_secs(options.release_check_reuse_secs),
+        lenient_parse: options.lenient_release_parse,
+    };
 
-    let mut should_update: bool;
+    // Restrict archive downloads to trusted hosts
+    // ```
+    // [lapce-plugin-name.lsp]
+    // allowedHosts = ["github.com", "objects.githubusercontent.com"]
+    // ```
+    let allowed_hosts = options
+        .allowed_hosts
+        .clone()
+        .map(|hosts| http::AllowedHosts { inner: http, hosts });
+    let allowed_http = match allowed_hosts.as_ref() {
+        Some(allowed_hosts) => allowed_hosts as &dyn HttpClient,
+        None => http,
+    };
 
-    // Create server path if it doesn't already exist
-    if !PathBuf::from(volt_uri.as_str()).exists() {
-        create_dir_all(volt_uri.as_str())?;
-        should_update = true;
+    // Redirects followed per download before giving up on a looping mirror
+    // ```
+    // [lapce-plugin-name.lsp]
+    // maxRedirects = 10
+    // ```
+    let redirects_http = http::FollowRedirects {
+        inner: allowed_http,
+        max_redirects: options.max_redirects,
+    };
+    // The configured headers are added before redirects are followed, so
+    // `FollowRedirects` drops them when a redirect leads to another host
+    let download_headers = extra_headers.map(|headers| http::ExtraHeaders {
+        inner: &redirects_http,
+        headers,
+        override_required: options.override_headers,
+    });
+    let download_http: &dyn HttpClient = match download_headers.as_ref() {
+        Some(download_headers) => download_headers,
+        None => &redirects_http,
+    };
+
+    // How the prerelease channel picks its newest release
+    // ```
+    // [lapce-plugin-name.lsp]
+    // prereleaseOrder = "semver" # or "date"
+    // ```
+    let prerelease_order = options
+        .prerelease_order
+        .as_deref()
+        .map(version::PrereleaseOrder::parse)
+        .transpose()?
+        .unwrap_or(version::PrereleaseOrder::Semver);
+
+    // Fetch asset from github to check version
+    let asset = match major_version {
+        Some(major) => github.fetch_major_release(&install_dir, policy.channel, major)?,
+        None => github.fetch_release(&install_dir, policy.channel, prerelease_order)?,
+    };
+
+    // Releases known to be broken, or the only ones known to work, by
+    // version, requirement or tag
+    // ```
+    // [lapce-plugin-name.lsp]
+    // skipVersions = ["0.7.5"]
+    // allowedVersions = [">=0.7, <0.8"]
+    // ```
+    let version_filter = version::VersionFilter {
+        skip: options.skip_versions.clone(),
+        allowed: options.allowed_versions.clone(),
+    };
 
-        // Create version file (it definitely doesn't exist)
-        fs::write(&verfile, &asset.tag_name)?;
+    // Releases may only ship some platforms or be excluded, go back to the
+    // newest acceptable release built for this one
+    let kind = ArchiveKind::for_os(os_name);
+    let acceptable = |release: &release::GHAsset| {
+        major_version
+            .map(|major| release.has_major(major))
+            .unwrap_or(true)
+            && !release.platform_assets(arch_name, os_name, kind).is_empty()
+            && version_filter.accepts(&release.tag_name)
+    };
+    let asset = if acceptable(&asset) {
+        asset
     } else {
-        if verfile.exists() {
-            // Get version from file if there is one
-            let ver = String::from_utf8(fs::read(&verfile)?)?;
-            installed_version = semver::Version::parse(ver.as_str())?;
+        let compatible = github
+            .find_release(&install_dir, policy.channel, acceptable)?
+            .ok_or_else(|| {
+                Error::msg(format!(
+                    "No recent serve-d release has an asset for {}-{} and passes \
+                     skipVersions/allowedVersions",
+                    arch_name, os_name
+                ))
+            })?;
+        if version_filter.accepts(&asset.tag_name) {
+            host.log(&format!(
+                "serve-d {} has no {}-{} asset, using {}",
+                asset.tag_name, arch_name, os_name, compatible.tag_name
+            ));
+        } else {
+            host.log(&format!(
+                "serve-d {} is excluded by skipVersions/allowedVersions, using {}",
+                asset.tag_name, compatible.tag_name
+            ));
         }
+        compatible
+    };
+    let latest_version = version::parse_tag(asset.tag_name.as_str()).ok();
+    if latest_version.is_none() {
+        host.log(&format!(
+            "Release tag {} is not a semver version, comparing tags instead",
+            asset.tag_name
+        ));
+    }
 
-        // Write the new version we want.
-        fs::write(&verfile, &asset.tag_name)?;
-
-        // Set should_update based on whether the version on git is newer
-        should_update = installed_version > semver::Version::parse(asset.tag_name.as_str())?;
+    // Warn about releases that look abandoned
+    // ```
+    // [lapce-plugin-name.lsp]
+    // staleReleaseDays = 365
+    // ```
+    if let Some(warning) = asset.staleness_warning(stats::now(), options.stale_release_days) {
+        host.show_message(MessageType::WARNING, warning);
     }
 
-    if should_update {
-        let ext = if os_name == "windows" {
-            "zip"
+    let mut update = version::UpdateReason::NotInstalled;
+
+    // Check the installed version if the server path already existed
+    if install_dir_exists {
+        // Get version from file if there is one
+        let installed_tag = if verfile.exists() {
+            Some(String::from_utf8(fs::read(&verfile)?)?)
         } else {
-            "tar.xz"
+            None
         };
+        let mut installed = installed_tag.as_deref().map(version::parse_tag).transpose();
 
-        // Calculate download url
-        let download_url = format!(
-            "https://github.com/Pure-D/serve-d/releases/download/{0}/serve-d_{0}-{1}-{2}.{3}",
-            asset.tag_name.clone(),
-            arch_name,
-            os_name,
-            ext
+        // Trust the binary over a version file that fell out of sync, e.g.
+        // after serve-d got replaced by hand
+        let binary = installed_server_path(&install_dir, &install_url, exec_file.as_str())
+            .and_then(|server_path| server::program(&server_path));
+        if let (Ok(Some(recorded)), Ok(binary)) = (&installed, binary) {
+            if let Ok(probed) = server::probe_installed_version(host, Path::new(&binary)) {
+                if probed != *recorded {
+                    host.log(&format!(
+                        "version.txt says serve-d {} but the binary is {}, correcting it",
+                        recorded, probed
+                    ));
+                    fs_retry::retry(|| fs::write(&verfile, format!("v{}", probed)))?;
+                    installed = Ok(Some(probed));
+                }
+            }
+        }
+
+        match (&latest_version, installed) {
+            (Some(latest_version), Ok(installed_version)) => {
+                // Update when the version on git is newer, or the stable
+                // release replaces a pre-release after switching back
+                let installed_channel =
+                    InstallManifest::read(&install_dir)?.and_then(|manifest| manifest.channel);
+                update =
+                    version::needs_update(installed_version.as_ref(), latest_version, &policy)?;
+                if let Some(installed_version) = &installed_version {
+                    if !update.updates()
+                        && version::switched_to_stable(
+                            installed_channel,
+                            installed_version,
+                            latest_version,
+                            &policy,
+                        )
+                    {
+                        update = version::UpdateReason::SwitchedToStable;
+                    }
+                }
+            }
+            // Tags that aren't semver can only be told apart, not ordered
+            _ => {
+                update =
+                    version::tag_update_reason(installed_tag.as_deref(), &asset.tag_name, &policy);
+            }
+        }
+
+        // A version file without its binary doesn't count as installed
+        if !update.updates()
+            && installed_server_path(&install_dir, &install_url, exec_file.as_str()).is_err()
+        {
+            update = version::UpdateReason::BinaryMissing;
+        }
+    }
+
+    // Reinstall a binary that got corrupted on disk
+    // ```
+    // [lapce-plugin-name.lsp]
+    // verifyIntegrityOnStart = true
+    // ```
+    let mut reuse_unchanged = true;
+    if !update.updates() && options.verify_integrity_on_start {
+        if let Some(manifest) = InstallManifest::read(&install_dir)? {
+            if manifest.binary_intact(&install_dir)? == Some(false) {
+                host.log("serve-d binary does not match its checksum, reinstalling");
+                update = version::UpdateReason::Corrupted;
+                reuse_unchanged = false;
+            }
+        }
+    }
+
+    // Overwriting a running serve-d fails halfway on Windows, leave the
+    // update for the next start instead
+    if update.updates() && install::is_binary_locked(os_name, &server_file) {
+        host.show_message(
+            MessageType::INFO,
+            format!(
+                "serve-d {} is available, it will be installed once serve-d is restarted",
+                asset.tag_name
+            ),
         );
+        update = version::UpdateReason::BinaryLocked;
+    }
+
+    // Don't install a release again that failed to start before
+    let rolled_back_from =
+        InstallManifest::read(&install_dir)?.and_then(|manifest| manifest.rolled_back_from);
+    if update.updates() && rolled_back_from.as_deref() == Some(asset.tag_name.as_str()) {
+        host.log(&format!(
+            "serve-d {} failed to start before, keeping the installed version",
+            asset.tag_name
+        ));
+        update = version::UpdateReason::RolledBack;
+    }
 
-        // Try fetching the archive
-        let mut resp = lapce_plugin::Http::get(download_url.as_str())?;
-        if resp.status_code != 200 {
+    // Workspaces sharing the install take turns updating it. The lock is
+    // held for as long as all download attempts may take
+    let installed_before = fs::read_to_string(&verfile).ok();
+    let mut install_lock = None;
+    if update.updates() && shared_install {
+        install_lock = lock::FileLock::acquire(
+            install_dir.join(INSTALL_LOCK_NAME),
+            download_retry.budget.max(timeouts.download),
+            timeouts.download,
+        );
+        // Extracting alongside the other install would mix up both
+        if install_lock.is_none() {
+            if installed_server_path(&install_dir, &install_url, exec_file.as_str()).is_err() {
+                return Err(Error::msg(format!(
+                    "Timed out waiting for another workspace installing serve-d into {}",
+                    install_dir.display()
+                )));
+            }
+            host.log(&format!(
+                "Another workspace is still installing serve-d into {}, keeping the installed one",
+                install_dir.display()
+            ));
+            update = version::UpdateReason::InstallInProgress;
+        }
+    }
+    // Another workspace may have installed the release while waiting. Only a
+    // version file that changed meanwhile says so, a corrupted or missing
+    // binary of the same version still gets repaired
+    let installed_after = fs::read_to_string(&verfile).ok();
+    if install_lock.is_some()
+        && installed_after != installed_before
+        && installed_after
+            .map(|installed| !version::tag_changed(&installed, &asset.tag_name))
+            .unwrap_or(false)
+    {
+        host.log(&format!(
+            "serve-d {} got installed by another workspace",
+            asset.tag_name
+        ));
+        update = version::UpdateReason::InstalledElsewhere;
+    }
+
+    host.log(&format!(
+        "Update decision for serve-d {}: {:?}",
+        asset.tag_name, update
+    ));
+    state.update_reason = Some(update);
+    let should_update = update.updates();
+
+    // Keep the installed serve-d to roll back to when the update fails its
+    // health check
+    let mut kept_previous = false;
+    if should_update {
+        let release_assets = asset.platform_assets(arch_name, os_name, kind);
+        if release_assets.is_empty() {
             return Err(Error::msg(format!(
-                "Fetching archive failed with error {}",
-                resp.status_code
+                "No serve-d {} asset for {}-{}",
+                asset.tag_name, arch_name, os_name
             )));
         }
 
-        // Archive buffer
-        let archive_buf = resp.body_read_all()?;
-
-        // Extract zip or tar archive
-        match ext {
-            "zip" => {
-                let mut archive =
-                    ZipArchive::new(Cursor::new(archive_buf)).expect("Failed to open zip archive");
-                archive.extract(volt_uri.as_str())?;
+        // Only extract the archive entries matching the globs, the serve-d
+        // binary always is
+        // ```
+        // [lapce-plugin-name.lsp]
+        // extractOnly = ["dcd-*"]
+        // ```
+        let extract_only = &options.extract_only;
+        // Only install serve-d itself, leaving out the bundled DCD so serve-d
+        // uses the one on PATH or at dcdServerPath/dcdClientPath
+        // ```
+        // [lapce-plugin-name.lsp]
+        // coreOnly = true
+        // ```
+        // An external DCD leaves the bundled one out as well
+        // ```
+        // [lapce-plugin-name.lsp]
+        // useBundledDcd = false
+        // ```
+        let core_only = options.core_only || options.use_bundled_dcd == Some(false);
+        // Check the `.asc` signature of every asset against this ASCII
+        // armored public key with gpg, refusing assets without one
+        // ```
+        // [lapce-plugin-name.lsp]
+        // signingKey = "[path]"
+        // ```
+        let signing_key = options.signing_key.as_deref();
+        // Private repositories only serve their assets through the API with
+        // the token, detected unless set explicitly
+        // ```
+        // [lapce-plugin-name.lsp]
+        // privateRepo = true
+        // ```
+        let private_repo = match options.private_repo {
+            Some(private_repo) => private_repo,
+            None => github.token.is_some() && github.is_private(&install_dir).unwrap_or(false),
+        };
+        let asset_token = match (private_repo, github.token.as_ref()) {
+            (false, _) => None,
+            (true, Some(token)) => Some(token.clone()),
+            (true, None) => {
+                return Err(Error::msg(
+                    "Downloading from a private repository requires githubToken",
+                ))
             }
-            "tar.xz" => {
-                let mut archive = Archive::new(Cursor::new(archive_buf));
-                archive.unpack(volt_uri.as_str())?;
+        };
+        // Staging directory for archives, the system temp directory unless
+        // it's unusable
+        // ```
+        // [lapce-plugin-name.lsp]
+        // tempDir = "[path]"
+        // ```
+        let temp_dir = platform::temp_dir(
+            options.temp_dir.as_deref(),
+            os_name,
+            |key| host.env_var(key),
+            &install_dir,
+        )?;
+        let target = install::InstallTarget {
+            install_dir: &install_dir,
+            verfile: &verfile,
+            kind,
+            channel: policy.channel,
+            exec_file: exec_file.as_str(),
+            reuse_unchanged,
+            extract_only,
+            core_only,
+            temp_dir: &temp_dir,
+            signing_key,
+            asset_api: asset_token.as_deref().map(|token| install::AssetApi {
+                api_base: &github.api_base,
+                token,
+            }),
+            strict_startup,
+        };
+        // Install all-or-nothing, going back to the installed serve-d when
+        // the update fails to extract or its binary doesn't run
+        // ```
+        // [lapce-plugin-name.lsp]
+        // transactionalInstall = false
+        // ```
+        let download_bytes = if options.transactional_install {
+            let outcome = install::install_transaction(
+                download_http,
+                host,
+                &asset,
+                &release_assets,
+                &target,
+                timeouts.download,
+                &download_retry,
+            )?;
+            kept_previous = health_check && outcome.kept_previous;
+            outcome.download_bytes
+        } else {
+            kept_previous = health_check && install::keep_previous(&install_dir, &verfile)?;
+            install::install_release(
+                download_http,
+                host,
+                &asset,
+                &release_assets,
+                &target,
+                timeouts.download,
+                &download_retry,
+            )?
+        };
+
+        // Hook run after installing, with `{installDir}` and `{version}`
+        // substituted
+        // ```
+        // [lapce-plugin-name.lsp]
+        // postInstallCommand = ["chmod", "+x", "{installDir}/dcd-server"]
+        // allowPostInstallCommand = true # required, the hook runs anything
+        // ```
+        install::run_post_install(
+            host,
+            &user_only.post_install_command,
+            user_only.allow_post_install_command,
+            &install_dir,
+            &asset.tag_name,
+        )?;
+
+        let mut update_stats = stats::UpdateStats::read(&install_dir)?;
+        update_stats.record_update(download_bytes, stats::now());
+        update_stats.write(&install_dir)?;
+    }
+    drop(install_lock);
+    created_dir.keep();
+
+    // Start the binary where it actually landed
+    let server_path = installed_server_path(&install_dir, &install_url, exec_file.as_str())?;
+
+    // Catch mirrors serving a binary of another architecture before serve-d
+    // fails to start with an obscure error
+    let binary_arch = platform::binary_arch(Path::new(&server::program(&server_path)?))?;
+    if !binary_arch.matches(arch_name) {
+        return Err(Error::msg(format!(
+            "Installed serve-d is built for {:?} but this host needs {}",
+            binary_arch, arch_name
+        )));
+    }
+
+    // Gatekeeper refuses quarantined downloads without saying why
+    // ```
+    // [lapce-plugin-name.lsp]
+    // macRemoveQuarantine = true
+    // ```
+    if os_name == "macos" {
+        let intact = InstallManifest::read(&install_dir)?
+            .map(|manifest| manifest.binary_intact(&install_dir))
+            .transpose()?
+            .flatten()
+            == Some(true);
+        platform::handle_quarantine(
+            host,
+            Path::new(&server::program(&server_path)?),
+            options.mac_remove_quarantine,
+            intact,
+        )?;
+    }
+
+    // Make sure the install is the release it was downloaded as
+    if should_update {
+        let binary = server::program(&server_path)?;
+        match (
+            server::probe_installed_version(host, Path::new(&binary)),
+            latest_version.as_ref(),
+        ) {
+            (Ok(probed), Some(latest_version)) if probed != *latest_version => {
+                host.show_message(
+                    MessageType::WARNING,
+                    format!(
+                        "Installed serve-d {} reports version {}",
+                        asset.tag_name, probed
+                    ),
+                );
             }
-            _ => {}
+            (Ok(_), _) => {}
+            (Err(err), _) => host.log(&format!("Couldn't verify the installed serve-d: {}", err)),
+        }
+    }
+
+    state.install_dir = Some(install_dir.clone());
+
+    if install_only {
+        host.log(&format!("serve-d is installed at {}", server_path));
+        return Ok(());
+    }
+
+    // serve-d already runs, an update takes effect once it's restarted
+    if let Some(launch) = started {
+        if should_update {
+            host.show_message(
+                MessageType::INFO,
+                format!(
+                    "serve-d {} is installed, restart serve-d to use it",
+                    asset.tag_name
+                ),
+            );
         }
+        state.launch = Some(launch);
+        return Ok(());
     }
 
-    // Available language IDs
-    // https://github.com/lapce/lapce/blob/HEAD/lapce-proxy/src/buffer.rs#L173
-    PLUGIN_RPC.start_lsp(
+    let launch = Launch {
         server_path,
         server_args,
-        LANGUAGE_ID,
-        params.initialization_options,
-    );
+        options: server::build_server_options(options.raw, server_settings),
+        env: server_env,
+        language_ids,
+        early_exit_check,
+        strict_startup,
+    };
+    state.launch = Some(if !health_check {
+        launch.start(host);
+        launch
+    } else if !kept_previous {
+        launch.start_checked(host, fallback_to_path)?
+    } else {
+        launch.start(host);
+        match launch.check(host) {
+            Ok(()) => launch,
+            // The update doesn't start, go back to the install that did
+            Err(err) => {
+                let restored =
+                    install::restore_previous(&install_dir, &verfile, Some(&asset.tag_name))?;
+                host.show_message(
+                    MessageType::WARNING,
+                    format!(
+                        "serve-d {} failed to start ({}), rolled back to {}",
+                        asset.tag_name, err, restored
+                    ),
+                );
+                let launch = Launch {
+                    server_path: installed_server_path(
+                        &install_dir,
+                        &install_url,
+                        exec_file.as_str(),
+                    )?,
+                    ..launch
+                };
+                launch.start_checked(host, fallback_to_path)?
+            }
+        }
+    });
 
     Ok(())
 }
 
 impl LapcePlugin for State {
-    fn handle_request(&mut self, _id: u64, method: String, params: Value) {
-        #[allow(clippy::single_match)]
+    fn handle_request(&mut self, id: u64, method: String, params: Value) {
         match method.as_str() {
             Initialize::METHOD => {
                 let params: InitializeParams = serde_json::from_value(params).unwrap();
-                let _ = initialize(params);
+                let host = self.host();
+                self.last_error = None;
+                if let Err(err) = initialize(self, &host, &WasiHttp, params) {
+                    host.show_message(MessageType::ERROR, format!("lapce-dlang: {:#}", err));
+                    self.last_error = Some(format!("{:#}", err));
+                }
+            }
+            "dlang/stats" => match self.install_dir.as_deref().map(stats::UpdateStats::read) {
+                Some(Ok(update_stats)) => PLUGIN_RPC.host_success(id, update_stats),
+                Some(Err(err)) => PLUGIN_RPC.host_error(id, err.to_string()),
+                None => PLUGIN_RPC.host_error(id, "serve-d is not installed by the plugin"),
+            },
+            "dlang/installPath" => {
+                match self.install_dir.as_deref().map(install::InstallPaths::read) {
+                    Some(Ok(paths)) => PLUGIN_RPC.host_success(id, paths),
+                    Some(Err(err)) => PLUGIN_RPC.host_error(id, err.to_string()),
+                    None => PLUGIN_RPC.host_error(id, "serve-d is not installed by the plugin"),
+                }
             }
+            // The plugin API has no way to stop a running server, so serve-d
+            // is only started again with the same configuration
+            "dlang/restart" => match self.restart(&self.host()) {
+                Ok(()) => PLUGIN_RPC.host_success(id, Value::Null),
+                Err(err) => PLUGIN_RPC.host_error(id, err.to_string()),
+            },
+            // Diagnose the environment serve-d runs in
+            "dlang/selfTest" => {
+                let host = self.host();
+                let report = selftest::run(
+                    &host,
+                    &WasiHttp,
+                    self.api_base.as_ref(),
+                    self.install_dir.as_deref(),
+                    self.launch.as_ref().map(|launch| &launch.server_path),
+                );
+                PLUGIN_RPC.host_success(id, report);
+            }
+            // Check a serverPath before configuring it, takes the path as
+            // `{ "path": "[path]" }`
+            "dlang/validateServerPath" => match params.get("path").and_then(|path| path.as_str()) {
+                Some(path) => PLUGIN_RPC.host_success(
+                    id,
+                    selftest::validate_server_path(&self.host(), Path::new(path.trim())),
+                ),
+                None => PLUGIN_RPC.host_error(id, "Missing the path to validate"),
+            },
+            // Everything needed for a bug report, without credentials
+            "dlang/exportState" => {
+                let host = self.host();
+                let self_test = selftest::run(
+                    &host,
+                    &WasiHttp,
+                    self.api_base.as_ref(),
+                    self.install_dir.as_deref(),
+                    self.launch.as_ref().map(|launch| &launch.server_path),
+                );
+                let export = export::StateExport::new(
+                    self.install_dir.as_deref(),
+                    self.launch
+                        .as_ref()
+                        .and_then(|launch| serde_json::to_value(launch).ok()),
+                    self_test,
+                    self.last_error.clone(),
+                    self.update_reason,
+                )
+                .and_then(|export| export.to_redacted_json());
+                match export {
+                    Ok(export) => PLUGIN_RPC.host_success(id, export),
+                    Err(err) => PLUGIN_RPC.host_error(id, err.to_string()),
+                }
+            }
+            // The command line and merged options serve-d was started with
+            "dlang/effectiveConfig" => match self.launch.as_ref() {
+                Some(launch) => PLUGIN_RPC.host_success(id, launch),
+                None => PLUGIN_RPC.host_error(id, "serve-d has not been started"),
+            },
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{
+        crashing_binary, fake_binary, tar_xz_archive, zip_archive, MockHost, MockHttp, TestDir,
+    };
+
+    const RELEASE_URL: &str = "https://api.github.com/repos/Pure-D/serve-d/releases/latest";
+
+    fn asset_name(version: &str) -> String {
+        format!("serve-d_v{}-x86_64-windows.zip", version)
+    }
+
+    fn download_url(version: &str) -> String {
+        format!(
+            "https://github.com/Pure-D/serve-d/releases/download/v{}/{}",
+            version,
+            asset_name(version)
+        )
+    }
+
+    /// Latest release serve-d `version`, with its Windows archive
+    fn release(version: &str) -> MockHttp {
+        let archive = zip_archive(&[("serve-d.exe", &fake_binary(version)[..])]);
+        MockHttp::release(
+            &format!("v{}", version),
+            &[(asset_name(version).as_str(), archive)],
+        )
+    }
+
+    /// Latest release serve-d `version`, with its Linux archive
+    fn linux_release(version: &str) -> MockHttp {
+        let archive = tar_xz_archive(&[("serve-d", &fake_binary(version)[..])]);
+        MockHttp::release(
+            &format!("v{}", version),
+            &[(
+                format!("serve-d_v{}-x86_64-linux.tar.xz", version).as_str(),
+                archive,
+            )],
+        )
+    }
+
+    /// Linux host whose `chmod` and `test` succeed on the serve-d binary in
+    /// `install_dir`
+    fn linux_host(install_dir: &Path) -> MockHost {
+        let binary = install_dir.join("serve-d").display().to_string();
+        MockHost {
+            os: Some("linux"),
+            programs: [
+                format!("chmod 755 {}", binary),
+                format!("test -f {0} -a -x {0}", binary),
+            ]
+            .into_iter()
+            .map(|command| (command, String::new()))
+            .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Serve-d `latest` as the latest release, listed along with `older`
+    fn releases(latest: &str, older: &str) -> MockHttp {
+        let mut http = release(latest);
+        let previous = release(older);
+        let listed: Vec<Value> = [&http, &previous]
+            .iter()
+            .map(|http| serde_json::from_slice(&http.responses[RELEASE_URL]).unwrap())
+            .collect();
+        http.responses.insert(
+            RELEASE_URL.trim_end_matches("/latest").to_string(),
+            serde_json::to_vec(&listed).unwrap(),
+        );
+        http.responses.insert(
+            download_url(older),
+            previous.responses[&download_url(older)].clone(),
+        );
+        http
+    }
+
+    /// Initialize the plugin installing into `install_dir`, with `options`
+    /// set in the `lsp` table
+    fn try_init(
+        install_dir: &Path,
+        options: Value,
+        host: &MockHost,
+        http: &MockHttp,
+    ) -> Result<State> {
+        let lsp = json!({
+            "installDir": install_dir,
+            // Every initialization asks for the latest release
+            "releaseCheckReuseSecs": 0,
+        });
+        let params = InitializeParams {
+            initialization_options: Some(json!({ "lsp": server::deep_merge(lsp, options) })),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        initialize(&mut state, host, http, params)?;
+        Ok(state)
+    }
+
+    fn init(install_dir: &Path, options: Value, host: &MockHost, http: &MockHttp) -> State {
+        try_init(install_dir, options, host, http).unwrap()
+    }
+
+    fn server_path(state: &State) -> Url {
+        let install_dir = state.install_dir.as_ref().unwrap();
+        Url::from_file_path(install_dir.join("serve-d.exe")).unwrap()
+    }
+
+    fn installed_version(state: &State) -> String {
+        let install_dir = state.install_dir.as_ref().unwrap();
+        fs::read_to_string(install_dir.join("version.txt")).unwrap()
+    }
+
+    #[test]
+    fn installs_the_latest_release() {
+        let dir = TestDir::new("fresh-install");
+        let host = MockHost::default();
+        let http = release("0.7.6");
+        let state = init(&dir, json!({}), &host, &http);
+
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::NotInstalled)
+        );
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert!(http.requested(&download_url("0.7.6")));
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(server_path(&state), LANGUAGE_ID.to_string())]
+        );
+    }
+
+    #[test]
+    fn skips_an_up_to_date_install() {
+        let dir = TestDir::new("up-to-date");
+        let host = MockHost::default();
+        init(&dir, json!({}), &host, &release("0.7.6"));
+
+        let http = release("0.7.6");
+        let state = init(&dir, json!({}), &host, &http);
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        assert!(http.requested(RELEASE_URL));
+        assert!(!http.requested(&download_url("0.7.6")));
+        assert_eq!(
+            host.started.borrow().last(),
+            Some(&(server_path(&state), LANGUAGE_ID.to_string()))
+        );
+    }
+
+    #[test]
+    fn updates_to_a_newer_release() {
+        let dir = TestDir::new("update");
+        let host = MockHost::default();
+        let state = init(&dir, json!({}), &host, &release("0.7.5"));
+        assert_eq!(installed_version(&state), "v0.7.5");
+
+        let http = release("0.7.6");
+        let state = init(&dir, json!({}), &host, &http);
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::RemoteNewer)
+        );
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert!(http.requested(&download_url("0.7.6")));
+        let install_dir = state.install_dir.as_ref().unwrap();
+        assert_eq!(
+            fs::read(install_dir.join("serve-d.exe")).unwrap(),
+            fake_binary("0.7.6")
+        );
+    }
+
+    #[test]
+    fn installs_the_latest_release_on_linux() {
+        let dir = TestDir::new("linux-install");
+        let host = linux_host(&dir);
+        let http = linux_release("0.7.6");
+        let state = init(&dir, json!({}), &host, &http);
+
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::NotInstalled)
+        );
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert_eq!(fs::read(dir.join("serve-d")).unwrap(), fake_binary("0.7.6"));
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(
+                Url::from_file_path(dir.join("serve-d")).unwrap(),
+                LANGUAGE_ID.to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn skips_an_up_to_date_install_on_linux() {
+        let dir = TestDir::new("linux-up-to-date");
+        let host = linux_host(&dir);
+        init(&dir, json!({}), &host, &linux_release("0.7.6"));
+
+        let http = linux_release("0.7.6");
+        let state = init(&dir, json!({}), &host, &http);
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        assert!(http.requested(RELEASE_URL));
+        assert!(!http
+            .requests
+            .borrow()
+            .iter()
+            .any(|(url, _)| url.ends_with(".tar.xz")));
+        assert_eq!(host.started.borrow().len(), 2);
+    }
+
+    #[test]
+    fn updates_to_a_newer_release_on_linux() {
+        let dir = TestDir::new("linux-update");
+        let host = linux_host(&dir);
+        init(&dir, json!({}), &host, &linux_release("0.7.5"));
+
+        let state = init(&dir, json!({}), &host, &linux_release("0.7.6"));
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::RemoteNewer)
+        );
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert_eq!(fs::read(dir.join("serve-d")).unwrap(), fake_binary("0.7.6"));
+    }
+
+    #[test]
+    fn release_check_and_download_share_the_configured_client() {
+        let dir = TestDir::new("shared-client");
+        let host = MockHost::default();
+        let http = release("0.7.6");
+        let options = json!({
+            "downloadHeaders": { "X-Cache-Key": "serve-d" },
+            "downloadHeadersOnApi": true,
+        });
+        init(&dir, options, &host, &http);
+
+        let requests = http.requests.borrow();
+        let urls: Vec<&str> = requests.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, vec![RELEASE_URL, download_url("0.7.6").as_str()]);
+        for (url, headers) in requests.iter() {
+            assert!(
+                headers.contains(&("X-Cache-Key".to_string(), "serve-d".to_string())),
+                "{} was requested without the configured headers",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn release_checks_and_downloads_get_their_own_timeout() {
+        let dir = TestDir::new("timeouts");
+        let host = MockHost::default();
+        let http = release("0.7.6");
+        let options = json!({ "apiTimeoutSecs": 7, "downloadTimeoutSecs": 70 });
+        init(&dir, options, &host, &http);
+
+        let timeouts = http.timeouts.borrow();
+        assert_eq!(timeouts.len(), 2);
+        assert_eq!(timeouts[0], Duration::from_secs(7));
+        // Redirects are followed within the download timeout
+        assert!(timeouts[1] > Duration::from_secs(60) && timeouts[1] <= Duration::from_secs(70));
+    }
+
+    #[test]
+    fn starts_a_binary_nested_in_the_archive() {
+        let dir = TestDir::new("nested-archive");
+        let host = MockHost::default();
+        let archive = zip_archive(&[
+            ("serve-d_v0.7.6/serve-d.exe", &fake_binary("0.7.6")[..]),
+            ("serve-d_v0.7.6/LICENSE", &b"BSL-1.0"[..]),
+        ]);
+        let http = MockHttp::release("v0.7.6", &[(asset_name("0.7.6").as_str(), archive)]);
+        let state = init(&dir, json!({}), &host, &http);
+
+        let install_dir = state.install_dir.as_ref().unwrap();
+        let server_path =
+            Url::from_file_path(install_dir.join("serve-d_v0.7.6/serve-d.exe")).unwrap();
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(server_path, LANGUAGE_ID.to_string())]
+        );
+    }
+
+    #[test]
+    fn skips_extracting_an_unchanged_archive() {
+        let dir = TestDir::new("unchanged-archive");
+        let host = MockHost::default();
+        let archive = zip_archive(&[("serve-d.exe", &fake_binary("0.7.5")[..])]);
+        let http = MockHttp::release("v0.7.5", &[(asset_name("0.7.5").as_str(), archive.clone())]);
+        init(&dir, json!({}), &host, &http);
+
+        let http = MockHttp::release("v0.7.6", &[(asset_name("0.7.6").as_str(), archive)]);
+        let state = init(&dir, json!({}), &host, &http);
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert!(host.logs.borrow().contains(&format!(
+            "{} is unchanged, skipping extraction",
+            asset_name("0.7.6")
+        )));
+    }
+
+    #[test]
+    fn unsupported_platforms_fall_back_to_serve_d_from_path() {
+        let root = TestDir::new("unsupported-platform");
+        // Left to the plugin to create, which it mustn't
+        let dir = root.join("serve-d");
+        let host = MockHost {
+            arch: Some("riscv64"),
+            ..Default::default()
+        };
+        let http = release("0.7.6");
+
+        let err = try_init(&dir, json!({}), &host, &http).err().unwrap();
+        assert_eq!(err.to_string(), "Unsupported architecture");
+        assert!(host.started.borrow().is_empty());
+
+        init(&dir, json!({ "fallbackToPath": true }), &host, &http);
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(Url::parse("urn:serve-d").unwrap(), LANGUAGE_ID.to_string())]
+        );
+        assert!(http.requests.borrow().is_empty());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn reinstalls_a_corrupted_binary() {
+        let dir = TestDir::new("corrupted");
+        let host = MockHost::default();
+        let state = init(&dir, json!({}), &host, &release("0.7.6"));
+        let binary = server_path(&state).to_file_path().unwrap();
+        let mut corrupted = fake_binary("0.7.6");
+        corrupted[2] = 0xFF;
+        fs::write(&binary, &corrupted).unwrap();
+
+        // Only checked when asked for
+        let state = init(&dir, json!({}), &host, &release("0.7.6"));
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        assert_eq!(fs::read(&binary).unwrap(), corrupted);
+
+        let options = json!({ "verifyIntegrityOnStart": true });
+        let state = init(&dir, options, &host, &release("0.7.6"));
+        assert_eq!(state.update_reason, Some(version::UpdateReason::Corrupted));
+        assert_eq!(fs::read(&binary).unwrap(), fake_binary("0.7.6"));
+    }
+
+    #[test]
+    fn restarting_starts_serve_d_again_the_same_way() {
+        let dir = TestDir::new("restart");
+        let host = MockHost::default();
+        assert!(State::default().restart(&host).is_err());
+
+        let options = json!({ "serverArgs": ["--loglevel", "trace"] });
+        let state = init(&dir, options, &host, &release("0.7.6"));
+        let started = host.started.borrow().clone();
+        let start_params = host.start_params.borrow().clone();
+        assert_eq!(started.len(), 1);
+        assert!(start_params[0]
+            .0
+            .ends_with(&["--loglevel".to_string(), "trace".to_string()]));
+
+        state.restart(&host).unwrap();
+        assert_eq!(*host.started.borrow(), [started.clone(), started].concat());
+        assert_eq!(
+            *host.start_params.borrow(),
+            [start_params.clone(), start_params].concat()
+        );
+    }
+
+    #[test]
+    fn updates_releases_tagged_without_a_version_by_tag() {
+        let dir = TestDir::new("tag-release");
+        let host = MockHost::default();
+        let tagged = |tag: &str| {
+            let name = format!("serve-d_{}-x86_64-windows.zip", tag);
+            let archive = zip_archive(&[("serve-d.exe", &fake_binary(tag)[..])]);
+            MockHttp::release(tag, &[(name.as_str(), archive)])
+        };
+
+        let state = init(&dir, json!({}), &host, &tagged("nightly-2024-06"));
+        assert_eq!(installed_version(&state), "nightly-2024-06");
+        let state = init(&dir, json!({}), &host, &tagged("nightly-2024-06"));
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        let state = init(&dir, json!({}), &host, &tagged("nightly-2024-07"));
+        assert_eq!(state.update_reason, Some(version::UpdateReason::TagChanged));
+        assert_eq!(installed_version(&state), "nightly-2024-07");
+    }
+
+    #[test]
+    fn failed_health_checks_fall_back_to_serve_d_from_path() {
+        let dir = TestDir::new("health-check");
+        let http = release("0.7.6");
+        let urn = |program: &str| (Url::parse(program).unwrap(), LANGUAGE_ID.to_string());
+
+        let host = MockHost {
+            programs: [(
+                "serve-d-nightly --version".to_string(),
+                "v0.8.0".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let options = json!({
+            "serverPath": "serve-d-nightly",
+            "healthCheck": true,
+            "fallbackToPath": true,
+        });
+        init(&dir, options, &host, &http);
+        assert_eq!(*host.started.borrow(), vec![urn("urn:serve-d-nightly")]);
+        assert!(host.messages.borrow().is_empty());
+
+        // Reported, but kept without the fallback
+        let host = MockHost::default();
+        let options = json!({ "serverPath": "serve-d-nightly", "healthCheck": true });
+        init(&dir, options, &host, &http);
+        assert_eq!(*host.started.borrow(), vec![urn("urn:serve-d-nightly")]);
+        assert_eq!(host.messages.borrow()[0].0, MessageType::ERROR);
+
+        let host = MockHost::default();
+        let options = json!({
+            "serverPath": "serve-d-nightly",
+            "healthCheck": true,
+            "fallbackToPath": true,
+        });
+        init(&dir, options, &host, &http);
+        assert_eq!(
+            *host.started.borrow(),
+            vec![urn("urn:serve-d-nightly"), urn("urn:serve-d")]
+        );
+        assert!(http.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn rolls_back_an_update_that_fails_to_start() {
+        let dir = TestDir::new("rollback");
+        let host = MockHost::default();
+        let options = json!({ "healthCheck": true, "earlyExitCheck": true });
+        let state = init(&dir, options.clone(), &host, &release("0.7.6"));
+        let binary = server_path(&state).to_file_path().unwrap();
+
+        let crashing = |version: &str| {
+            let archive = zip_archive(&[("serve-d.exe", &crashing_binary(version)[..])]);
+            MockHttp::release(
+                &format!("v{}", version),
+                &[(asset_name(version).as_str(), archive)],
+            )
+        };
+        let state = init(&dir, options.clone(), &host, &crashing("0.8.0"));
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert_eq!(fs::read(&binary).unwrap(), fake_binary("0.7.6"));
+        let messages = host.messages.borrow();
+        let (kind, message) = messages.last().unwrap();
+        assert_eq!(*kind, MessageType::WARNING);
+        assert!(message.contains("rolled back to v0.7.6"), "{}", message);
+        drop(messages);
+        // Started once more with the restored install
+        assert_eq!(host.started.borrow().len(), 3);
+
+        // The failed release isn't installed again
+        let http = crashing("0.8.0");
+        let state = init(&dir, options, &host, &http);
+        assert_eq!(state.update_reason, Some(version::UpdateReason::RolledBack));
+        assert!(!http.requested(&download_url("0.8.0")));
+    }
+
+    #[test]
+    fn install_only_never_starts_serve_d() {
+        let dir = TestDir::new("install-only");
+        let host = MockHost::default();
+        let http = release("0.7.6");
+        let state = init(&dir, json!({ "installOnly": true }), &host, &http);
+
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert!(http.requested(&download_url("0.7.6")));
+        assert!(host.started.borrow().is_empty());
+        assert!(state.launch.is_none());
+
+        // Neither when there is nothing to update
+        init(
+            &dir,
+            json!({ "installOnly": true }),
+            &host,
+            &release("0.7.6"),
+        );
+        assert!(host.started.borrow().is_empty());
+    }
+
+    #[test]
+    fn effective_config_reflects_every_option() {
+        let dir = TestDir::new("effective-config");
+        let gdc_include = "/usr/lib/gcc/x86_64-linux-gnu/13/include/d";
+        let host = MockHost {
+            programs: [(
+                "gdc -print-file-name=include/d".to_string(),
+                gdc_include.to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let options = json!({
+            "serverPath": "serve-d",
+            "serverArgs": ["--loglevel", "info"],
+            "dflags": "-g",
+            "compiler": "gdc",
+            "importPaths": ["/opt/dlib/source"],
+            "limits": { "maxLineLength": 120 },
+            "serverSettings": { "dfmt": { "braceStyle": "otbs" } },
+        });
+        let state = init(&dir, options, &host, &release("0.7.6"));
+
+        assert_eq!(
+            serde_json::to_value(state.launch.as_ref().unwrap()).unwrap(),
+            json!({
+                "serverPath": "urn:serve-d",
+                "serverArgs": ["--require", "d", "--loglevel", "info"],
+                "options": {
+                    "d": {
+                        "stdlibPath": [gdc_include],
+                        "projectImportPaths": ["/opt/dlib/source"],
+                    },
+                    "dfmt": { "braceStyle": "otbs", "maxLineLength": 120 },
+                },
+                "env": [["DFLAGS", "-g"]],
+                "languageIds": [LANGUAGE_ID],
+            })
+        );
+    }
+
+    #[test]
+    fn installs_each_major_version_into_its_own_directory() {
+        let dir = TestDir::new("major-version");
+        let host = MockHost::default();
+        let state = init(&dir, json!({ "majorVersion": 0 }), &host, &release("0.7.6"));
+        assert_eq!(state.install_dir, Some(dir.join(version::major_dir(0))));
+        assert_eq!(installed_version(&state), "v0.7.6");
+
+        // No release of that major version to install
+        let http = release("0.7.6");
+        assert!(try_init(&dir, json!({ "majorVersion": 1 }), &host, &http).is_err());
+        assert!(!http.requested(&download_url("0.7.6")));
+        assert!(!dir.join(version::major_dir(1)).join("version.txt").exists());
+    }
+
+    #[test]
+    fn falls_back_to_the_newest_release_with_an_asset() {
+        let dir = TestDir::new("asset-less-latest");
+        let host = MockHost::default();
+        let mut http = release("0.7.5");
+        let previous: Value = serde_json::from_slice(&http.responses[RELEASE_URL]).unwrap();
+        let latest = json!({
+            "tag_name": "v0.8.0",
+            "prerelease": false,
+            "assets": [{
+                "id": 9,
+                "name": "serve-d_v0.8.0-x86_64-linux.tar.xz",
+                "size": 0,
+                "browser_download_url": "https://example.com/serve-d.tar.xz",
+            }],
+        });
+        http.responses.insert(
+            RELEASE_URL.trim_end_matches("/latest").to_string(),
+            json!([latest.clone(), previous]).to_string().into_bytes(),
+        );
+        http.responses
+            .insert(RELEASE_URL.to_string(), latest.to_string().into_bytes());
+
+        let state = init(&dir, json!({}), &host, &http);
+        assert_eq!(installed_version(&state), "v0.7.5");
+        assert!(http.requested(&download_url("0.7.5")));
+        assert!(host
+            .logs
+            .borrow()
+            .contains(&"serve-d v0.8.0 has no x86_64-windows asset, using v0.7.5".to_string()));
+    }
+
+    #[test]
+    fn installs_into_the_directory_from_the_environment() {
+        let dir = TestDir::new("install-dir-env");
+        let host = MockHost {
+            env: [(
+                platform::INSTALL_DIR_ENV.to_string(),
+                dir.display().to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let state = init(
+            &dir,
+            json!({ "installDir": null }),
+            &host,
+            &release("0.7.6"),
+        );
+        assert_eq!(state.install_dir.as_deref(), Some(&*dir));
+        assert_eq!(installed_version(&state), "v0.7.6");
+    }
+
+    #[test]
+    fn starts_the_installed_serve_d_before_checking_for_updates() {
+        let dir = TestDir::new("start-installed-first");
+        let host = MockHost::default();
+        let options = json!({ "startupPolicy": "startInstalledFirst" });
+        // Nothing installed to start yet
+        init(&dir, options.clone(), &host, &release("0.7.6"));
+        assert_eq!(host.started.borrow().len(), 1);
+
+        let state = init(&dir, options, &host, &release("0.8.0"));
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::RemoteNewer)
+        );
+        // Checked and installed before initialize returned, but started once
+        // with the previous version, the update waits for a restart
+        assert_eq!(installed_version(&state), "v0.8.0");
+        assert_eq!(host.started.borrow().len(), 2);
+        assert!(host
+            .messages
+            .borrow()
+            .iter()
+            .any(|(_, message)| message.contains("restart serve-d to use it")));
+    }
+
+    #[test]
+    fn rejects_delaying_the_update_check() {
+        let dir = TestDir::new("update-check-delay");
+        let host = MockHost::default();
+        let error = try_init(
+            &dir,
+            json!({ "updateCheckDelaySecs": 30 }),
+            &host,
+            &release("0.7.6"),
+        )
+        .err()
+        .unwrap();
+        assert!(error
+            .to_string()
+            .contains("updateCheckDelaySecs is not supported"));
+        assert!(host.started.borrow().is_empty());
+        assert!(!dir.join("serve-d.exe").exists());
+    }
+
+    #[test]
+    fn reports_the_install_paths() {
+        let dir = TestDir::new("install-path");
+        let host = MockHost {
+            env: [(
+                platform::INSTALL_DIR_ENV.to_string(),
+                dir.display().to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let state = init(
+            &dir,
+            json!({ "installDir": null }),
+            &host,
+            &release("0.7.6"),
+        );
+
+        let paths = install::InstallPaths::read(state.install_dir.as_ref().unwrap()).unwrap();
+        assert_eq!(paths.install_dir, *dir);
+        assert!(paths.install_dir.is_absolute());
+        assert_eq!(paths.binary, Some(dir.join("serve-d.exe")));
+    }
+
+    #[test]
+    fn a_second_init_reuses_the_release_check_of_the_first() {
+        let dir = TestDir::new("release-check-reuse");
+        let host = MockHost::default();
+        let options = json!({ "releaseCheckReuseSecs": 60 });
+        init(&dir, options.clone(), &host, &release("0.7.6"));
+
+        let http = release("0.8.0");
+        let state = init(&dir, options, &host, &http);
+        assert!(!http.requested(RELEASE_URL));
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        assert_eq!(installed_version(&state), "v0.7.6");
+    }
+
+    #[test]
+    fn forcing_updates_skips_the_version_comparison() {
+        let dir = TestDir::new("force-update");
+        let host = MockHost::default();
+        init(&dir, json!({}), &host, &release("0.7.6"));
+
+        let http = release("0.7.6");
+        let state = init(&dir, json!({ "internalForceUpdate": true }), &host, &http);
+        assert_eq!(state.update_reason, Some(version::UpdateReason::Forced));
+        assert!(http.requested(&download_url("0.7.6")));
+
+        let http = release("0.8.0");
+        let state = init(&dir, json!({ "internalForceUpdate": false }), &host, &http);
+        assert_eq!(state.update_reason, Some(version::UpdateReason::ForcedOff));
+        assert!(!http.requested(&download_url("0.8.0")));
+        assert_eq!(installed_version(&state), "v0.7.6");
+    }
+
+    #[test]
+    fn starts_serve_d_for_each_language_id() {
+        let dir = TestDir::new("language-ids");
+        let host = MockHost::default();
+        let state = init(&dir, json!({ "languageIds": [] }), &host, &release("0.7.6"));
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(server_path(&state), LANGUAGE_ID.to_string())]
+        );
+
+        let host = MockHost::default();
+        let state = init(
+            &dir,
+            json!({ "languageIds": ["dlang", "sdl", "json"] }),
+            &host,
+            &release("0.7.6"),
+        );
+        let server_path = server_path(&state);
+        assert_eq!(
+            *host.started.borrow(),
+            vec![
+                (server_path.clone(), "dlang".to_string()),
+                (server_path.clone(), "sdl".to_string()),
+                (server_path, "json".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn workspaces_share_an_install_in_the_cache_directory() {
+        let local_app_data = TestDir::new("shared-install");
+        let host = MockHost {
+            env: [(
+                "LOCALAPPDATA".to_string(),
+                local_app_data.display().to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let state = init(
+            &local_app_data,
+            json!({ "installDir": null }),
+            &host,
+            &release("0.7.6"),
+        );
+        assert_eq!(state.install_dir, Some(local_app_data.join("lapce-dlang")));
+
+        let http = release("0.7.6");
+        let state = init(&local_app_data, json!({ "installDir": null }), &host, &http);
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::AlreadyLatest)
+        );
+        assert!(!http.requested(&download_url("0.7.6")));
+        assert!(!local_app_data
+            .join("lapce-dlang")
+            .join(INSTALL_LOCK_NAME)
+            .exists());
+
+        // Opted out, the install goes into the plugin directory, which the
+        // mock host has none of
+        let opted_out = json!({ "installDir": null, "sharedInstall": false });
+        assert!(try_init(&local_app_data, opted_out, &host, &release("0.7.6")).is_err());
+    }
+
+    #[test]
+    fn leaves_the_shared_install_to_a_workspace_still_installing() {
+        let local_app_data = TestDir::new("shared-install-busy");
+        let host = MockHost {
+            env: [(
+                "LOCALAPPDATA".to_string(),
+                local_app_data.display().to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        // Don't wait on the lock at all
+        let options = json!({ "installDir": null, "downloadTimeoutSecs": 0 });
+        let lock = local_app_data.join("lapce-dlang").join(INSTALL_LOCK_NAME);
+        let holder = format!("other {}", stats::now() + 600);
+        fs::create_dir_all(lock.parent().unwrap()).unwrap();
+        fs::write(&lock, &holder).unwrap();
+
+        // Nothing is installed to start instead
+        let err = try_init(&local_app_data, options.clone(), &host, &release("0.7.5"))
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("Timed out waiting for another workspace"),
+            "{}",
+            err
+        );
+
+        fs::remove_file(&lock).unwrap();
+        init(&local_app_data, options.clone(), &host, &release("0.7.5"));
+        fs::write(&lock, &holder).unwrap();
+        let http = release("0.7.6");
+        let state = init(&local_app_data, options, &host, &http);
+        assert_eq!(
+            state.update_reason,
+            Some(version::UpdateReason::InstallInProgress)
+        );
+        assert_eq!(installed_version(&state), "v0.7.5");
+        assert!(!http.requested(&download_url("0.7.6")));
+        assert_eq!(fs::read_to_string(&lock).unwrap(), holder);
+    }
+
+    #[test]
+    fn keeps_or_downgrades_a_newer_local_install() {
+        let dir = TestDir::new("newer-local");
+        let host = MockHost::default();
+        init(&dir, json!({}), &host, &release("0.8.0"));
+
+        let state = init(&dir, json!({}), &host, &release("0.7.6"));
+        assert_eq!(state.update_reason, Some(version::UpdateReason::NewerLocal));
+        assert_eq!(installed_version(&state), "v0.8.0");
+        assert!(host
+            .logs
+            .borrow()
+            .contains(&"Update decision for serve-d v0.7.6: NewerLocal".to_string()));
+
+        let options = json!({ "onNewerLocal": "downgrade" });
+        let state = init(&dir, options, &host, &release("0.7.6"));
+        assert_eq!(state.update_reason, Some(version::UpdateReason::Downgrade));
+        assert_eq!(installed_version(&state), "v0.7.6");
+
+        let options = json!({ "onNewerLocal": "upgrade" });
+        assert!(try_init(&dir, options, &host, &release("0.7.6")).is_err());
+    }
+
+    #[test]
+    fn core_only_installs_just_the_serve_d_binary() {
+        let dir = TestDir::new("core-only");
+        let host = MockHost::default();
+        let archive = zip_archive(&[
+            ("serve-d.exe", &fake_binary("0.7.6")[..]),
+            ("dcd-server.exe", &b"dcd-server"[..]),
+            ("dcd-client.exe", &b"dcd-client"[..]),
+        ]);
+        let http = MockHttp::release("v0.7.6", &[(asset_name("0.7.6").as_str(), archive)]);
+        init(&dir, json!({ "coreOnly": true }), &host, &http);
+
+        assert!(dir.join("serve-d.exe").is_file());
+        assert!(!dir.join("dcd-server.exe").exists());
+        assert!(!dir.join("dcd-client.exe").exists());
+        let (_, options) = host.start_params.borrow()[0].clone();
+        let options = options.unwrap();
+        assert_eq!(options["d"]["dcdServerPath"], "dcd-server");
+        assert_eq!(options["d"]["dcdClientPath"], "dcd-client");
+    }
+
+    #[test]
+    fn skips_excluded_versions_for_the_next_acceptable_one() {
+        let dir = TestDir::new("version-filter");
+        let host = MockHost::default();
+        let http = releases("0.8.0", "0.7.5");
+
+        let state = init(&dir, json!({ "skipVersions": ["0.8.0"] }), &host, &http);
+        assert_eq!(installed_version(&state), "v0.7.5");
+        assert!(!http.requested(&download_url("0.8.0")));
+        assert!(host.logs.borrow().contains(
+            &"serve-d v0.8.0 is excluded by skipVersions/allowedVersions, using v0.7.5".to_string()
+        ));
+
+        let options = json!({ "allowedVersions": [">=0.8"] });
+        let state = init(&dir, options, &host, &http);
+        assert_eq!(installed_version(&state), "v0.8.0");
+
+        let options = json!({ "allowedVersions": ["<0.7"] });
+        let err = try_init(&dir, options, &host, &http).err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("passes skipVersions/allowedVersions"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn runs_with_no_initialization_options_at_all() {
+        let local_app_data = TestDir::new("no-options");
+        let host = MockHost {
+            env: [(
+                "LOCALAPPDATA".to_string(),
+                local_app_data.display().to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        initialize(
+            &mut state,
+            &host,
+            &release("0.7.6"),
+            InitializeParams::default(),
+        )
+        .unwrap();
+
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(server_path(&state), LANGUAGE_ID.to_string())]
+        );
+        // Nothing configured for serve-d either
+        assert_eq!(
+            host.start_params.borrow()[0],
+            (vec!["--require".to_string(), "d".to_string()], None)
+        );
+    }
+
+    #[test]
+    fn a_failure_at_any_install_step_keeps_the_previous_install() {
+        let dir = TestDir::new("transaction");
+        let host = MockHost::default();
+        let state = init(&dir, json!({}), &host, &release("0.7.5"));
+        let binary = server_path(&state).to_file_path().unwrap();
+        let assert_kept = |err: Error| {
+            assert_eq!(
+                fs::read_to_string(dir.join("version.txt")).unwrap(),
+                "v0.7.5"
+            );
+            assert_eq!(fs::read(&binary).unwrap(), fake_binary("0.7.5"));
+            err.to_string()
+        };
+
+        // Download
+        let mut http = release("0.7.6");
+        http.responses.remove(&download_url("0.7.6"));
+        let err = try_init(&dir, json!({}), &host, &http).err().unwrap();
+        assert!(assert_kept(err).contains("failed with error 404"));
+
+        // Checksum
+        let archive = zip_archive(&[("serve-d.exe", &fake_binary("0.7.6")[..])]);
+        let checksum_name = format!("{}.sha256", asset_name("0.7.6"));
+        let http = MockHttp::release(
+            "v0.7.6",
+            &[
+                (asset_name("0.7.6").as_str(), archive),
+                (
+                    checksum_name.as_str(),
+                    manifest::sha256_hex(b"other").into_bytes(),
+                ),
+            ],
+        );
+        let err = try_init(&dir, json!({}), &host, &http).err().unwrap();
+        assert!(assert_kept(err).contains("does not match its published checksum"));
+
+        // Extraction
+        let http = MockHttp::release(
+            "v0.7.6",
+            &[(
+                asset_name("0.7.6").as_str(),
+                b"PK\x03\x04truncated".to_vec(),
+            )],
+        );
+        assert_kept(try_init(&dir, json!({}), &host, &http).err().unwrap());
+
+        // Launch
+        let host = MockHost {
+            failing: [format!("{} --version", binary.display())].into(),
+            ..Default::default()
+        };
+        let err = try_init(&dir, json!({}), &host, &release("0.7.6"))
+            .err()
+            .unwrap();
+        assert!(
+            assert_kept(err).contains("Installing serve-d v0.7.6 failed, rolled back to v0.7.5")
+        );
+    }
+
+    #[test]
+    fn a_remote_serve_d_skips_the_install() {
+        let root = TestDir::new("remote");
+        // Left to the plugin to create, which it mustn't
+        let dir = root.join("serve-d");
+        let host = MockHost {
+            os: Some("linux"),
+            ..Default::default()
+        };
+        let http = release("0.7.6");
+        let options = json!({ "remote": { "host": "devcontainer", "port": 8765 } });
+        let state = init(&dir, options.clone(), &host, &http);
+
+        assert!(http.requests.borrow().is_empty());
+        assert!(!dir.exists());
+        assert_eq!(
+            *host.started.borrow(),
+            vec![(Url::parse("urn:nc").unwrap(), LANGUAGE_ID.to_string())]
+        );
+        assert_eq!(
+            host.start_params.borrow()[0].0,
+            vec!["devcontainer", "8765"]
+        );
+        assert!(state.install_dir.is_none());
+
+        // The plugin API can't start nc on Windows
+        let windows = MockHost::default();
+        assert!(try_init(&dir, options, &windows, &http).is_err());
+    }
+
+    #[test]
+    fn pins_downgrade_unless_disallowed() {
+        let dir = TestDir::new("pin-downgrade");
+        let host = MockHost::default();
+        let http = releases("0.8.0", "0.7.6");
+        let pin = json!({ "allowedVersions": ["0.7.6"] });
+
+        init(&dir, json!({}), &host, &http);
+        let options = server::deep_merge(pin.clone(), json!({ "allowDowngrade": false }));
+        let state = init(&dir, options, &host, &http);
+        assert_eq!(state.update_reason, Some(version::UpdateReason::NewerLocal));
+        assert_eq!(installed_version(&state), "v0.8.0");
+
+        let state = init(&dir, pin, &host, &http);
+        assert_eq!(state.update_reason, Some(version::UpdateReason::Downgrade));
+        assert_eq!(installed_version(&state), "v0.7.6");
+        assert_eq!(
+            fs::read(dir.join("serve-d.exe")).unwrap(),
+            fake_binary("0.7.6")
+        );
+    }
+
+    #[test]
+    fn channels_only_downgrade_when_allowed() {
+        let dir = TestDir::new("channel-downgrade");
+        let host = MockHost::default();
+        init(&dir, json!({}), &host, &release("0.8.0"));
+
+        let state = init(
+            &dir,
+            json!({ "allowDowngrade": true }),
+            &host,
+            &release("0.7.6"),
+        );
+        assert_eq!(state.update_reason, Some(version::UpdateReason::Downgrade));
+        assert_eq!(installed_version(&state), "v0.7.6");
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::stats;
+
+/// Poll interval while another plugin instance holds a lock
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// How long a lock is still respected after the time its holder expected to
+/// be done, so a holder that is slow but alive keeps it
+const STALE_AFTER: Duration = Duration::from_secs(600);
+
+/// Lock file held while fetching or installing, so plugin instances of
+/// workspaces opened together don't all do the same work
+///
+/// The file holds the owner token of the holder and until when it expects
+/// to hold the lock.
+pub struct FileLock {
+    path: PathBuf,
+    owner: String,
+}
+
+impl FileLock {
+    /// Take the lock for work taking up to `hold_for`, waiting up to `wait`
+    /// for another holder
+    ///
+    /// A lock still held [`STALE_AFTER`] past the time its holder expected
+    /// to be done is left over from a crashed instance and taken over.
+    /// `None` when waiting timed out.
+    pub fn acquire(path: PathBuf, hold_for: Duration, wait: Duration) -> Option<FileLock> {
+        let owner = owner_token();
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{} {}", owner, stats::now() + hold_for.as_secs());
+                    return Some(FileLock { path, owner });
+                }
+                Err(_) if started.elapsed() >= wait => return None,
+                Err(_) => {
+                    // Take over a lock left over by a crashed instance, unless
+                    // another waiter did so first
+                    if let Ok(holder) = fs::read_to_string(&path) {
+                        let stale = held_until(&path, &holder)
+                            .map(|held_until| stats::now() > held_until + STALE_AFTER.as_secs())
+                            .unwrap_or(false);
+                        if stale && fs::read_to_string(&path).ok() == Some(holder) {
+                            let _ = fs::remove_file(&path);
+                            continue;
+                        }
+                    }
+                    thread::sleep(LOCK_POLL);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    /// Remove the lock file, unless the lock got taken over meanwhile
+    fn drop(&mut self) {
+        let owned = fs::read_to_string(&self.path)
+            .map(|holder| holder.split_whitespace().next() == Some(self.owner.as_str()))
+            .unwrap_or(false);
+        if owned {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Token telling the holders of a lock apart, across instances as well
+fn owner_token() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{}", nanos, NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Until when the holder of the lock at `path` expected to hold it
+///
+/// Locks of older versions only hold the time they were taken, and a lock
+/// whose holder crashed before writing it goes by the time it was created.
+fn held_until(path: &Path, holder: &str) -> Option<u64> {
+    match holder.split_whitespace().last() {
+        Some(held_until) => held_until.parse().ok(),
+        None => fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|modified| modified.as_secs()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::TestDir;
+
+    const HOLD: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn waits_for_the_holder_to_release_the_lock() {
+        let dir = TestDir::new("held-lock");
+        let path = dir.join("held.lock");
+        let lock = FileLock::acquire(path.clone(), HOLD, Duration::from_secs(10)).unwrap();
+        assert!(path.exists());
+        // Held elsewhere and still fresh, so waiting times out
+        assert!(FileLock::acquire(path.clone(), HOLD, Duration::ZERO).is_none());
+
+        let waiter = {
+            let path = path.clone();
+            thread::spawn(move || FileLock::acquire(path, HOLD, Duration::from_secs(10)).is_some())
+        };
+        thread::sleep(LOCK_POLL * 2);
+        drop(lock);
+        assert!(waiter.join().unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn takes_over_a_lock_left_by_a_crashed_instance() {
+        let dir = TestDir::new("stale-lock");
+        let path = dir.join("stale.lock");
+        let long_gone = stats::now() - STALE_AFTER.as_secs() - 60;
+        for holder in [format!("crashed {}", long_gone), long_gone.to_string()] {
+            fs::write(&path, holder).unwrap();
+            let lock = FileLock::acquire(path.clone(), HOLD, Duration::from_secs(1));
+            assert!(lock.is_some());
+            drop(lock);
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn keeps_the_lock_of_a_slow_holder() {
+        let dir = TestDir::new("slow-lock");
+        let path = dir.join("slow.lock");
+        // Past the time it expected to be done, but not by much
+        let holder = format!("slow {}", stats::now() - 60);
+        fs::write(&path, &holder).unwrap();
+
+        let lock = FileLock::acquire(path.clone(), HOLD, LOCK_POLL * 3);
+        assert!(lock.is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap(), holder);
+    }
+
+    #[test]
+    fn leaves_a_lock_taken_over_meanwhile() {
+        let dir = TestDir::new("taken-over-lock");
+        let path = dir.join("taken-over.lock");
+        let lock = FileLock::acquire(path.clone(), HOLD, Duration::ZERO).unwrap();
+        let holder = format!("other {}", stats::now() + 60);
+        fs::write(&path, &holder).unwrap();
+
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), holder);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{fs_retry, version::Channel};
+
+/// Hex encoded SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Release asset an install was extracted from
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestAsset {
+    pub url: String,
+    pub sha256: String,
+    /// Files extracted from the asset, relative to the install directory
+    pub files: Vec<PathBuf>,
+}
+
+/// Record of everything an install put on disk
+///
+/// Written next to the binary so cleanup and integrity checks don't have to
+/// guess which files belong to serve-d.
+#[derive(Serialize, Deserialize, Default)]
+pub struct InstallManifest {
+    pub version: String,
+    /// Release channel the version was installed from
+    #[serde(default)]
+    pub channel: Option<Channel>,
+    pub assets: Vec<ManifestAsset>,
+    /// serve-d binary, relative to the install directory
+    #[serde(default)]
+    pub binary: Option<PathBuf>,
+    #[serde(default)]
+    pub binary_sha256: Option<String>,
+    /// Release that failed to start and got rolled back to this install
+    #[serde(default)]
+    pub rolled_back_from: Option<String>,
+}
+
+impl InstallManifest {
+    pub const FILE_NAME: &'static str = "manifest.json";
+
+    pub fn path(install_dir: &Path) -> PathBuf {
+        install_dir.join(Self::FILE_NAME)
+    }
+
+    /// Read the manifest of an install, if there is one
+    pub fn read(install_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(install_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub fn write(&self, install_dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs_retry::retry(|| fs::write(Self::path(install_dir), &data))?;
+        Ok(())
+    }
+
+    /// Whether the binary still matches its recorded checksum, `None` if no
+    /// checksum was recorded
+    pub fn binary_intact(&self, install_dir: &Path) -> Result<Option<bool>> {
+        let (binary, sha256) = match (&self.binary, &self.binary_sha256) {
+            (Some(binary), Some(sha256)) => (install_dir.join(binary), sha256),
+            _ => return Ok(None),
+        };
+        if !binary.exists() {
+            return Ok(Some(false));
+        }
+
+        Ok(Some(sha256_hex(&fs::read(binary)?) == *sha256))
+    }
+
+    /// Every extracted file, relative to the install directory
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.assets
+            .iter()
+            .flat_map(|asset| asset.files.iter().cloned())
+            .collect()
+    }
+
+    /// Files of an asset with the same archive bytes that are all still
+    /// on disk, so the archive doesn't need to be extracted again
+    pub fn unchanged_files(&self, sha256: &str, install_dir: &Path) -> Option<Vec<PathBuf>> {
+        self.assets
+            .iter()
+            .find(|asset| asset.sha256 == sha256)
+            .filter(|asset| {
+                asset
+                    .files
+                    .iter()
+                    .all(|file| install_dir.join(file).exists())
+            })
+            .map(|asset| asset.files.clone())
+    }
+
+    /// Absolute paths of files in this install that `current` no longer has
+    pub fn stale_files(&self, current: &InstallManifest, install_dir: &Path) -> Vec<PathBuf> {
+        let current_files = current.files();
+        self.files()
+            .into_iter()
+            .filter(|file| !current_files.contains(file))
+            .map(|file| install_dir.join(file))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::TestDir;
+
+    fn manifest(version: &str, files: &[&str]) -> InstallManifest {
+        InstallManifest {
+            version: version.to_string(),
+            channel: Some(Channel::Stable),
+            assets: vec![ManifestAsset {
+                url: format!("https://example.com/serve-d-{}.tar.xz", version),
+                sha256: sha256_hex(version.as_bytes()),
+                files: files.iter().map(PathBuf::from).collect(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_back_the_written_manifest() {
+        let dir = TestDir::new("manifest");
+        assert!(InstallManifest::read(&dir).unwrap().is_none());
+
+        manifest("v0.7.6", &["serve-d", "dcd-server"])
+            .write(&dir)
+            .unwrap();
+        let read = InstallManifest::read(&dir).unwrap().unwrap();
+        assert_eq!(read.version, "v0.7.6");
+        assert_eq!(read.channel, Some(Channel::Stable));
+        assert_eq!(read.assets[0].sha256, sha256_hex(b"v0.7.6"));
+        assert_eq!(
+            read.files(),
+            vec![PathBuf::from("serve-d"), PathBuf::from("dcd-server")]
+        );
+    }
+
+    #[test]
+    fn lists_the_files_a_new_install_no_longer_has() {
+        let dir = Path::new("/opt/serve-d");
+        let previous = manifest("v0.7.5", &["serve-d", "dcd-server", "dcd-client"]);
+        let current = manifest("v0.7.6", &["serve-d", "dcd-server"]);
+        assert_eq!(
+            previous.stale_files(&current, dir),
+            vec![dir.join("dcd-client")]
+        );
+        assert!(current.stale_files(&previous, dir).is_empty());
+    }
+
+    #[test]
+    fn reuses_files_of_an_identical_archive_still_on_disk() {
+        let dir = TestDir::new("manifest-unchanged");
+        let previous = manifest("v0.7.5", &["serve-d", "dcd-server"]);
+        let sha256 = sha256_hex(b"v0.7.5");
+        assert_eq!(previous.unchanged_files(&sha256, &dir), None);
+
+        fs::write(dir.join("serve-d"), b"").unwrap();
+        fs::write(dir.join("dcd-server"), b"").unwrap();
+        assert_eq!(
+            previous.unchanged_files(&sha256, &dir),
+            Some(vec![PathBuf::from("serve-d"), PathBuf::from("dcd-server")])
+        );
+        assert_eq!(previous.unchanged_files(&sha256_hex(b"v0.7.6"), &dir), None);
+    }
+}
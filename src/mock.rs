@@ -0,0 +1,329 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{Cursor, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::{MessageType, Url};
+use serde_json::{json, Value};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    host::{Host, LogFile, LogFormat, ProcessOutput},
+    http::{HttpClient, Response},
+};
+
+/// Windows x86_64 host by default, where serve-d ships as a zip the tests
+/// can build
+#[derive(Default)]
+pub struct MockHost {
+    /// Operating system the host reports, windows when unset
+    pub os: Option<&'static str>,
+    /// Architecture the host reports, x86_64 when unset
+    pub arch: Option<&'static str>,
+    pub env: BTreeMap<String, String>,
+    /// Output of programs other than serve-d, by command line
+    pub programs: BTreeMap<String, String>,
+    /// Command lines of programs that run but fail
+    pub failing: BTreeSet<String>,
+    pub logs: RefCell<Vec<String>>,
+    pub messages: RefCell<Vec<(MessageType, String)>>,
+    /// Server paths and language IDs of every `start_lsp`
+    pub started: RefCell<Vec<(Url, String)>>,
+    /// Arguments and options of every `start_lsp`
+    pub start_params: RefCell<Vec<(Vec<String>, Option<Value>)>>,
+}
+
+impl Host for MockHost {
+    fn operating_system(&self) -> Result<String> {
+        Ok(self.os.unwrap_or("windows").to_string())
+    }
+
+    fn architecture(&self) -> Result<String> {
+        Ok(self.arch.unwrap_or("x86_64").to_string())
+    }
+
+    fn volt_uri(&self) -> Result<String> {
+        Err(Error::msg("The mock host has no plugin directory"))
+    }
+
+    fn env_var(&self, key: &str) -> Option<String> {
+        self.env.get(key).cloned()
+    }
+
+    /// Runs the `programs`, the `failing` ones and binaries made by
+    /// [`fake_binary`], no other program is found
+    fn execute(&self, program: &str, args: &[&str]) -> Result<ProcessOutput> {
+        let command: Vec<&str> = [program].iter().chain(args).copied().collect();
+        let command = command.join(" ");
+        if self.failing.contains(&command) {
+            return Ok(ProcessOutput {
+                success: false,
+                stdout: Vec::new(),
+                stderr: format!("{} failed", program).into_bytes(),
+            });
+        }
+        if let Some(stdout) = self.programs.get(&command) {
+            return Ok(ProcessOutput {
+                success: true,
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            });
+        }
+
+        let binary = match fs::read(program) {
+            Ok(binary) => binary,
+            Err(_) => return Err(Error::msg(format!("{} not found", program))),
+        };
+        let output = String::from_utf8_lossy(&binary);
+        if args != ["--version"] {
+            // Started without a client, serve-d exits right away
+            let crashes = output.lines().any(|line| line == CRASH_MARKER);
+            return Ok(ProcessOutput {
+                success: !crashes,
+                stdout: Vec::new(),
+                stderr: if crashes {
+                    b"Segmentation fault".to_vec()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+        Ok(ProcessOutput {
+            success: true,
+            stdout: output
+                .lines()
+                .last()
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn log(&self, message: &str) {
+        self.logs.borrow_mut().push(message.to_string());
+    }
+
+    fn set_logging(&self, _: LogFormat, _: bool) {}
+
+    fn set_log_file(&self, _: LogFile) {}
+
+    fn show_message(&self, kind: MessageType, message: String) {
+        self.messages.borrow_mut().push((kind, message));
+    }
+
+    fn start_lsp(
+        &self,
+        server_path: Url,
+        server_args: Vec<String>,
+        language_id: &str,
+        options: Option<Value>,
+    ) {
+        self.started
+            .borrow_mut()
+            .push((server_path, language_id.to_string()));
+        self.start_params.borrow_mut().push((server_args, options));
+    }
+}
+
+/// Url and headers of a request
+pub type Request = (String, Vec<(String, String)>);
+
+/// Client serving fixed responses by url, recording every request
+pub struct MockHttp {
+    pub responses: BTreeMap<String, Vec<u8>>,
+    /// ETag sent with every response, requests revalidating it get a
+    /// `304 Not Modified`
+    pub etag: Option<String>,
+    /// Requests made, in order
+    pub requests: RefCell<Vec<Request>>,
+    /// Time limits of the requests, in order
+    pub timeouts: RefCell<Vec<Duration>>,
+}
+
+impl MockHttp {
+    /// Serve a GitHub release of `tag` whose assets are `archives`, by name,
+    /// as the latest and only release
+    pub fn release(tag: &str, archives: &[(&str, Vec<u8>)]) -> Self {
+        let mut responses = BTreeMap::new();
+        let assets: Vec<Value> = archives
+            .iter()
+            .enumerate()
+            .map(|(id, (name, archive))| {
+                let url = format!(
+                    "https://github.com/Pure-D/serve-d/releases/download/{}/{}",
+                    tag, name
+                );
+                responses.insert(url.clone(), archive.clone());
+                json!({
+                    "id": id,
+                    "name": name,
+                    "size": archive.len(),
+                    "browser_download_url": url,
+                })
+            })
+            .collect();
+        let release = json!({ "tag_name": tag, "prerelease": false, "assets": assets });
+        responses.insert(
+            "https://api.github.com/repos/Pure-D/serve-d/releases".to_string(),
+            json!([release]).to_string().into_bytes(),
+        );
+        responses.insert(
+            "https://api.github.com/repos/Pure-D/serve-d/releases/latest".to_string(),
+            release.to_string().into_bytes(),
+        );
+
+        MockHttp {
+            responses,
+            etag: None,
+            requests: RefCell::new(Vec::new()),
+            timeouts: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn requested(&self, url: &str) -> bool {
+        self.requests
+            .borrow()
+            .iter()
+            .any(|(requested, _)| requested == url)
+    }
+}
+
+impl HttpClient for MockHttp {
+    fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+        let headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let revalidated = self.etag.as_ref().filter(|etag| {
+            headers
+                .iter()
+                .any(|(name, value)| name == "If-None-Match" && value == *etag)
+        });
+        self.requests.borrow_mut().push((url.to_string(), headers));
+        self.timeouts.borrow_mut().push(timeout);
+
+        let etag_header = self
+            .etag
+            .iter()
+            .map(|etag| ("etag".to_string(), etag.clone()))
+            .collect();
+        Ok(match (revalidated, self.responses.get(url)) {
+            (Some(_), _) => Response {
+                status: 304,
+                headers: etag_header,
+                body: Vec::new(),
+            },
+            (None, Some(body)) => Response {
+                status: 200,
+                headers: etag_header,
+                body: body.clone(),
+            },
+            (None, None) => Response {
+                status: 404,
+                headers: Vec::new(),
+                body: Vec::new(),
+            },
+        })
+    }
+}
+
+/// Line marking a [`crashing_binary`]
+const CRASH_MARKER: &str = "crash";
+
+fn binary(lines: &[&str]) -> Vec<u8> {
+    let mut binary = b"MZ".to_vec();
+    binary.resize(0x3C, 0);
+    binary.extend(0x40u32.to_le_bytes());
+    binary.extend(b"PE\0\0");
+    binary.extend(0x8664u16.to_le_bytes());
+    for line in lines {
+        binary.extend(format!("\n{}", line).as_bytes());
+    }
+    binary
+}
+
+/// x86_64 Windows executable printing `serve-d v<version>` when run by
+/// [`MockHost`]
+pub fn fake_binary(version: &str) -> Vec<u8> {
+    binary(&[&format!("serve-d v{}", version)])
+}
+
+/// [`fake_binary`] that crashes when started with any other arguments than
+/// `--version`
+pub fn crashing_binary(version: &str) -> Vec<u8> {
+    binary(&[CRASH_MARKER, &format!("serve-d v{}", version)])
+}
+
+/// Zip archive of `files`, by path
+pub fn zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (path, data) in files {
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file(*path, options).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// `.tar.xz` archive of `files`, by path, all stored as executables
+pub fn tar_xz_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = tar_wasi::Builder::new(Vec::new());
+    for (path, data) in files {
+        let mut header = tar_wasi::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        tar.append_data(&mut header, path, *data).unwrap();
+    }
+    let mut xz = Vec::new();
+    lzma_rs::xz_compress(&mut Cursor::new(tar.into_inner().unwrap()), &mut xz).unwrap();
+    xz
+}
+
+/// Empty directory of a single test, removed along with its contents once
+/// dropped, so neither concurrent nor failed runs see each other's files
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lapce-dlang-{}-{}-{}",
+            name,
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        // As the plugin resolves it, e.g. with a symlinked temp dir
+        TestDir(path.canonicalize().unwrap())
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,376 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    host::{self, LogFormat},
+    http, platform, release,
+    retry::RetryOptions,
+    server::RemoteServer,
+};
+
+/// Initialization options, parsed once at the top of `initialize`
+///
+/// Lapce leaves `initialization_options` out when nothing is configured.
+/// That reads the same as an empty `lsp` table here, so every option falls
+/// back to its default the same way whether or not any options were sent.
+/// An option of the wrong type falls back to its default as well, see
+/// [`Options::parse`].
+#[derive(Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Options {
+    /// The initialization options as sent, with serve-d's settings
+    #[serde(skip)]
+    pub raw: Option<Value>,
+
+    pub quiet: bool,
+    pub log_format: LogFormat,
+    pub compiler: Option<String>,
+    pub stdlib_paths: Option<Vec<String>>,
+    pub dflags: Option<String>,
+    pub health_check: bool,
+    pub fallback_to_path: bool,
+    pub early_exit_check: bool,
+    pub strict_startup: bool,
+    pub install_only: bool,
+    pub import_paths: Vec<String>,
+    pub server_settings: Map<String, Value>,
+    pub require_features: Option<Vec<String>>,
+    pub os_args: BTreeMap<String, Vec<String>>,
+    pub server_args: Vec<String>,
+    pub limits: BTreeMap<String, u64>,
+    pub language_ids: Vec<String>,
+    pub dub_single_file: bool,
+    pub server_path: Option<String>,
+    pub remote: Option<RemoteServer>,
+    pub force_emulated_arch: bool,
+    pub binary_name: Option<String>,
+    pub use_cache_dir: bool,
+    pub shared_install: bool,
+    pub install_dir: Option<String>,
+    pub log_file: bool,
+    pub log_file_max_bytes: u64,
+    pub log_file_keep: usize,
+    pub major_version: Option<u64>,
+    pub auto_update: bool,
+    pub min_version: Option<String>,
+    pub channel: Option<String>,
+    pub replace_prerelease: bool,
+    pub on_newer_local: Option<String>,
+    pub allow_downgrade: Option<bool>,
+    /// Internal, for testing the plugin: always (true) or never (false)
+    /// update, regardless of versions
+    pub internal_force_update: Option<bool>,
+    pub update_check_delay_secs: Option<u64>,
+    pub startup_policy: Option<String>,
+    pub api_timeout_secs: u64,
+    pub download_timeout_secs: u64,
+    pub download_retry: RetryOptions,
+    pub insecure_skip_tls_verify: bool,
+    pub force_ipv4: bool,
+    pub dns_override: Option<BTreeMap<String, String>>,
+    pub github_host: String,
+    pub download_headers: Option<BTreeMap<String, String>>,
+    pub download_headers_on_api: bool,
+    pub override_headers: bool,
+    pub github_token: Option<String>,
+    pub release_check_reuse_secs: u64,
+    pub lenient_release_parse: bool,
+    pub allowed_hosts: Option<Vec<String>>,
+    pub max_redirects: usize,
+    pub prerelease_order: Option<String>,
+    pub skip_versions: Vec<String>,
+    pub allowed_versions: Vec<String>,
+    pub stale_release_days: u64,
+    pub verify_integrity_on_start: bool,
+    pub extract_only: Vec<String>,
+    pub dcd_server_path: Option<String>,
+    pub dcd_client_path: Option<String>,
+    pub core_only: bool,
+    pub use_bundled_dcd: Option<bool>,
+    pub transactional_install: bool,
+    pub signing_key: Option<PathBuf>,
+    pub private_repo: Option<bool>,
+    pub temp_dir: Option<String>,
+    pub mac_remove_quarantine: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            raw: None,
+            quiet: false,
+            log_format: LogFormat::Human,
+            compiler: None,
+            stdlib_paths: None,
+            dflags: None,
+            health_check: false,
+            fallback_to_path: false,
+            early_exit_check: false,
+            strict_startup: false,
+            install_only: false,
+            import_paths: Vec::new(),
+            server_settings: Map::new(),
+            require_features: None,
+            os_args: BTreeMap::new(),
+            server_args: Vec::new(),
+            limits: BTreeMap::new(),
+            language_ids: Vec::new(),
+            dub_single_file: false,
+            server_path: None,
+            remote: None,
+            force_emulated_arch: false,
+            binary_name: None,
+            use_cache_dir: false,
+            shared_install: true,
+            install_dir: None,
+            log_file: false,
+            log_file_max_bytes: host::DEFAULT_LOG_FILE_MAX_BYTES,
+            log_file_keep: host::DEFAULT_LOG_FILE_KEEP,
+            major_version: None,
+            auto_update: true,
+            min_version: None,
+            channel: None,
+            replace_prerelease: true,
+            on_newer_local: None,
+            allow_downgrade: None,
+            internal_force_update: None,
+            update_check_delay_secs: None,
+            startup_policy: None,
+            api_timeout_secs: http::DEFAULT_API_TIMEOUT.as_secs(),
+            download_timeout_secs: http::DEFAULT_DOWNLOAD_TIMEOUT.as_secs(),
+            download_retry: RetryOptions::default(),
+            insecure_skip_tls_verify: false,
+            force_ipv4: false,
+            dns_override: None,
+            github_host: release::DEFAULT_GITHUB_HOST.to_string(),
+            download_headers: None,
+            download_headers_on_api: false,
+            override_headers: false,
+            github_token: None,
+            release_check_reuse_secs: release::DEFAULT_REUSE_WINDOW.as_secs(),
+            lenient_release_parse: true,
+            allowed_hosts: None,
+            max_redirects: http::DEFAULT_MAX_REDIRECTS,
+            prerelease_order: None,
+            skip_versions: Vec::new(),
+            allowed_versions: Vec::new(),
+            stale_release_days: release::DEFAULT_STALE_DAYS,
+            verify_integrity_on_start: false,
+            extract_only: Vec::new(),
+            dcd_server_path: None,
+            dcd_client_path: None,
+            core_only: false,
+            use_bundled_dcd: None,
+            transactional_install: true,
+            signing_key: None,
+            private_repo: None,
+            temp_dir: None,
+            mac_remove_quarantine: false,
+        }
+    }
+}
+
+/// Deserialize options from the `lsp` table of the initialization options
+/// `raw`, leaving each option whose value doesn't fit at its default
+///
+/// Also returns why options were left out, so they can be reported while
+/// the rest of the configuration still applies.
+fn parse_lenient<T: DeserializeOwned + Default>(raw: Option<&Value>) -> (T, Vec<String>) {
+    let lsp = match raw.and_then(|raw| raw.get("lsp")) {
+        None | Some(Value::Null) => return (T::default(), Vec::new()),
+        Some(Value::Object(lsp)) => lsp,
+        Some(_) => return (T::default(), vec!["lsp: not a table".to_string()]),
+    };
+
+    // Options are independent of each other, so each is checked on its own
+    // to tell which ones are wrong
+    let mut valid = Map::new();
+    let mut invalid = Vec::new();
+    for (key, value) in lsp {
+        let mut single = Map::new();
+        single.insert(key.clone(), value.clone());
+        match serde_json::from_value::<T>(Value::Object(single)) {
+            Ok(_) => {
+                valid.insert(key.clone(), value.clone());
+            }
+            Err(err) => invalid.push(format!("lsp.{}: {}", key, err)),
+        }
+    }
+
+    match serde_json::from_value(Value::Object(valid)) {
+        Ok(options) => (options, invalid),
+        Err(err) => {
+            invalid.push(format!("lsp: {}", err));
+            (T::default(), invalid)
+        }
+    }
+}
+
+impl Options {
+    /// Parse the initialization options, also returning the invalid options
+    /// left at their default, see [`parse_lenient`]
+    pub fn parse(raw: Option<Value>) -> (Self, Vec<String>) {
+        let (options, invalid) = parse_lenient::<Options>(raw.as_ref());
+        (Options { raw, ..options }, invalid)
+    }
+
+    /// Expand environment variables in the path options, see
+    /// [`platform::expand_vars`]
+    pub fn expand_path_vars(&mut self, windows: bool, env: impl Fn(&str) -> Option<String>) {
+        let expand = |path: &str| platform::expand_vars(path, windows, &env);
+
+        for path in [
+            &mut self.server_path,
+            &mut self.install_dir,
+            &mut self.temp_dir,
+            &mut self.dcd_server_path,
+            &mut self.dcd_client_path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = expand(path);
+        }
+        for path in self
+            .import_paths
+            .iter_mut()
+            .chain(self.stdlib_paths.iter_mut().flatten())
+        {
+            *path = expand(path);
+        }
+        if let Some(signing_key) = &mut self.signing_key {
+            *signing_key = PathBuf::from(expand(&signing_key.to_string_lossy()));
+        }
+    }
+}
+
+/// Options only the user's own config may set, read from the initialization
+/// options before a project config is merged into them
+///
+/// A checked out repository must never get to run commands, whatever keys
+/// its project config holds.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UserOnlyOptions {
+    pub post_install_command: Vec<String>,
+    pub allow_post_install_command: bool,
+}
+
+impl UserOnlyOptions {
+    /// Parse the options like [`Options::parse`]
+    pub fn parse(raw: Option<&Value>) -> (Self, Vec<String>) {
+        parse_lenient(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A few options whose defaults differ from their type's default
+    fn defaults(options: &Options) -> (bool, bool, bool, bool, u64, String) {
+        (
+            options.shared_install,
+            options.auto_update,
+            options.replace_prerelease,
+            options.transactional_install,
+            options.api_timeout_secs,
+            options.github_host.clone(),
+        )
+    }
+
+    #[test]
+    fn missing_options_read_as_defaults() {
+        let expected = defaults(&Options::default());
+        assert_eq!(
+            expected,
+            (
+                true,
+                true,
+                true,
+                true,
+                http::DEFAULT_API_TIMEOUT.as_secs(),
+                "github.com".to_string()
+            )
+        );
+
+        for raw in [
+            None,
+            Some(json!({})),
+            Some(json!({ "lsp": null })),
+            Some(json!({ "lsp": {} })),
+        ] {
+            let (options, invalid) = Options::parse(raw.clone());
+            assert!(invalid.is_empty(), "{:?}", invalid);
+            assert_eq!(defaults(&options), expected);
+            assert!(options.language_ids.is_empty() && options.server_path.is_none());
+            assert_eq!(options.raw, raw);
+        }
+
+        let (user_only, invalid) = UserOnlyOptions::parse(None);
+        assert!(invalid.is_empty());
+        assert!(user_only.post_install_command.is_empty() && !user_only.allow_post_install_command);
+    }
+
+    #[test]
+    fn parses_a_representative_config() {
+        let raw = json!({
+            "lsp": {
+                "serverArgs": ["--loglevel", "info"],
+                "osArgs": { "linux": ["--wait"] },
+                "channel": "prerelease",
+                "autoUpdate": false,
+                "apiTimeoutSecs": 5,
+                "downloadRetry": { "attempts": 5 },
+                "serverSettings": { "d": { "dubPath": "/usr/bin/dub" } },
+                "logFormat": "json",
+            },
+            "d": { "stdlibPath": ["/usr/include/dmd/phobos"] },
+        });
+        let (options, invalid) = Options::parse(Some(raw.clone()));
+        assert!(invalid.is_empty(), "{:?}", invalid);
+        assert_eq!(options.server_args, vec!["--loglevel", "info"]);
+        assert_eq!(options.os_args["linux"], vec!["--wait"]);
+        assert_eq!(options.channel.as_deref(), Some("prerelease"));
+        assert!(!options.auto_update);
+        assert_eq!(options.api_timeout_secs, 5);
+        assert_eq!(options.download_retry.attempts, Some(5));
+        assert_eq!(options.download_retry.backoff_ms, None);
+        assert_eq!(
+            options.server_settings["d"],
+            json!({ "dubPath": "/usr/bin/dub" })
+        );
+        assert_eq!(options.log_format, LogFormat::Json);
+        assert_eq!(options.raw, Some(raw));
+
+        // Everything missing keeps its default
+        assert!(options.shared_install && options.replace_prerelease);
+        assert_eq!(options.github_host, "github.com");
+        assert_eq!(
+            options.download_timeout_secs,
+            http::DEFAULT_DOWNLOAD_TIMEOUT.as_secs()
+        );
+    }
+
+    #[test]
+    fn reports_invalid_options_and_applies_the_rest() {
+        let raw = json!({
+            "lsp": { "healthCheck": "yes", "quiet": true, "languageIds": "dlang" },
+        });
+        let (options, invalid) = Options::parse(Some(raw));
+        assert!(options.quiet);
+        assert!(!options.health_check);
+        assert!(options.language_ids.is_empty());
+        assert_eq!(invalid.len(), 2, "{:?}", invalid);
+        assert!(invalid[0].starts_with("lsp.healthCheck: "), "{:?}", invalid);
+        assert!(invalid[1].starts_with("lsp.languageIds: "), "{:?}", invalid);
+
+        let (options, invalid) = Options::parse(Some(json!({ "lsp": ["quiet"] })));
+        assert_eq!(invalid, vec!["lsp: not a table"]);
+        assert!(!options.quiet);
+    }
+}
@@ -0,0 +1,924 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::{MessageType, Url};
+
+use crate::host::Host;
+
+/// Operating system name as used in serve-d asset file names
+pub fn os_name(os: &str) -> Result<&'static str> {
+    match os {
+        "macos" => Ok("macos"),
+        "linux" => Ok("linux"),
+        "windows" => Ok("windows"),
+        _ => Err(Error::msg("Unsupported platform")),
+    }
+}
+
+/// Default name of the serve-d executable
+const DEFAULT_BINARY_NAME: &str = "serve-d";
+
+/// File name of the serve-d executable on the given OS
+///
+/// Forks may rename the executable, `.exe` is appended on Windows unless the
+/// name already has it.
+pub fn exec_file_name(os: &str, binary_name: Option<&str>) -> String {
+    let name = binary_name
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_BINARY_NAME);
+
+    if os == "windows" && !name.to_ascii_lowercase().ends_with(".exe") {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Architecture picked for the serve-d release asset
+pub struct ArchSelection {
+    /// Architecture name as used in the serve-d asset file names
+    pub asset_arch: &'static str,
+    /// Architecture reported by the volt environment
+    pub reported: String,
+    /// Whether the plugin runs emulated on a host of a different architecture
+    pub emulated: bool,
+}
+
+impl ArchSelection {
+    pub fn describe(&self) -> String {
+        if self.emulated {
+            format!(
+                "running emulated as {}, using native {} serve-d",
+                self.reported, self.asset_arch
+            )
+        } else {
+            format!("using {} serve-d", self.asset_arch)
+        }
+    }
+}
+
+/// Map an architecture name to the one used by serve-d asset names
+fn asset_arch_name(arch: &str) -> Option<&'static str> {
+    match arch {
+        "x86_64" => Some("x86_64"),
+        "aarch64" | "arm64" => Some("arm64"),
+        _ => None,
+    }
+}
+
+/// Pick the asset architecture given the reported architecture and the
+/// probed native one.
+///
+/// When the host is natively a different (supported) architecture than the
+/// one reported, the native asset is preferred unless `force_emulated` is set.
+pub fn select_arch(
+    reported: &str,
+    native: Option<&str>,
+    force_emulated: bool,
+) -> Result<ArchSelection> {
+    let reported_arch =
+        asset_arch_name(reported).ok_or_else(|| Error::msg("Unsupported architecture"))?;
+
+    let native_arch = native.and_then(asset_arch_name);
+    let arch = match native_arch {
+        Some(native_arch) if native_arch != reported_arch && !force_emulated => native_arch,
+        _ => reported_arch,
+    };
+
+    Ok(ArchSelection {
+        asset_arch: arch,
+        reported: reported.to_string(),
+        emulated: arch != reported_arch,
+    })
+}
+
+/// Probe the native architecture of the host, if it can differ from the
+/// architecture the plugin reports.
+///
+/// Only Apple Silicon is probed for now, where an x86_64 Lapce runs under
+/// Rosetta 2 but `hw.optional.arm64` still reports the real hardware.
+pub fn probe_native_arch(host: &dyn Host, os: &str) -> Option<String> {
+    if os != "macos" {
+        return None;
+    }
+
+    let output = host.execute("sysctl", &["-in", "hw.optional.arm64"]).ok()?;
+    if !output.success {
+        return None;
+    }
+
+    match String::from_utf8(output.stdout).ok()?.trim() {
+        "1" => Some("aarch64".to_string()),
+        _ => None,
+    }
+}
+
+/// Extensions Windows runs as programs
+const WINDOWS_EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "com", "bat", "cmd"];
+
+/// Whether `path` is an executable file, without changing its mode
+pub fn is_executable(host: &dyn Host, os: &str, path: &Path) -> Result<bool> {
+    if os == "windows" {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        return Ok(path.is_file()
+            && extension
+                .map(|extension| WINDOWS_EXECUTABLE_EXTENSIONS.contains(&extension.as_str()))
+                .unwrap_or(false));
+    }
+
+    let path = path.to_string_lossy();
+    Ok(host
+        .execute("test", &["-f", &path, "-a", "-x", &path])?
+        .success)
+}
+
+/// Make sure the file at `path` can be executed, adding the execute bit on
+/// Unix hosts
+///
+/// The WASI sandbox can't see file modes, so the host's `test` and `chmod`
+/// do the work.
+pub fn ensure_executable(host: &dyn Host, os: &str, path: &Path) -> Result<()> {
+    if os == "windows" {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        return match extension {
+            Some(extension) if WINDOWS_EXECUTABLE_EXTENSIONS.contains(&extension.as_str()) => {
+                Ok(())
+            }
+            _ => Err(Error::msg(format!(
+                "{} is not an executable",
+                path.display()
+            ))),
+        };
+    }
+
+    if is_executable(host, os, path)? {
+        return Ok(());
+    }
+
+    let path = path.to_string_lossy();
+    let chmod = host.execute("chmod", &["+x", &path])?;
+    if !chmod.success {
+        return Err(Error::msg(format!(
+            "{} is not executable and making it executable failed: {}",
+            path,
+            String::from_utf8_lossy(&chmod.stderr).trim()
+        )));
+    }
+
+    host.log(&format!("Made {} executable", path));
+    Ok(())
+}
+
+/// Extended attribute Gatekeeper checks before running downloaded files
+const QUARANTINE_ATTRIBUTE: &str = "com.apple.quarantine";
+
+/// What to do about a macOS binary Gatekeeper may refuse to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuarantineAction {
+    Nothing,
+    /// Remove the quarantine attribute
+    Remove,
+    /// Tell the user why serve-d may not start
+    Warn,
+}
+
+/// Decide what to do about the quarantine attribute
+///
+/// The attribute is only removed when the user allows it and the binary
+/// still matches the checksum recorded at install.
+pub fn quarantine_action(quarantined: bool, allow_removal: bool, intact: bool) -> QuarantineAction {
+    match (quarantined, allow_removal && intact) {
+        (false, _) => QuarantineAction::Nothing,
+        (true, true) => QuarantineAction::Remove,
+        (true, false) => QuarantineAction::Warn,
+    }
+}
+
+/// Handle the quarantine attribute of the serve-d binary on macOS
+pub fn handle_quarantine(
+    host: &dyn Host,
+    binary: &Path,
+    allow_removal: bool,
+    intact: bool,
+) -> Result<()> {
+    let binary = binary.to_string_lossy();
+    let quarantined = host
+        .execute("xattr", &["-p", QUARANTINE_ATTRIBUTE, &binary])
+        .map(|output| output.success)
+        .unwrap_or(false);
+
+    match quarantine_action(quarantined, allow_removal, intact) {
+        QuarantineAction::Nothing => Ok(()),
+        QuarantineAction::Remove => {
+            let output = host.execute("xattr", &["-d", QUARANTINE_ATTRIBUTE, &binary])?;
+            if !output.success {
+                return Err(Error::msg(format!(
+                    "Removing the quarantine from {} failed: {}",
+                    binary,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            host.log(&format!("Removed the quarantine from {}", binary));
+            Ok(())
+        }
+        QuarantineAction::Warn => {
+            host.show_message(
+                MessageType::WARNING,
+                format!(
+                    "{} is quarantined and Gatekeeper may refuse to run it, set \
+                     macRemoveQuarantine = true or run xattr -d {} on it",
+                    binary, QUARANTINE_ATTRIBUTE
+                ),
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Architecture an executable is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Arm64,
+    /// Mach-O universal binary carrying several architectures
+    Universal,
+}
+
+impl Arch {
+    /// Whether the executable runs as the asset architecture `asset_arch`
+    pub fn matches(&self, asset_arch: &str) -> bool {
+        match self {
+            Arch::X86_64 => asset_arch == "x86_64",
+            Arch::Arm64 => asset_arch == "arm64",
+            Arch::Universal => true,
+        }
+    }
+}
+
+fn u16_at(header: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let bytes = header.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if little_endian {
+        u16::from_le_bytes(bytes)
+    } else {
+        u16::from_be_bytes(bytes)
+    })
+}
+
+fn u32_at(header: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = header.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+/// Architecture of an ELF, Mach-O or PE executable from its leading bytes
+pub fn header_arch(header: &[u8]) -> Option<Arch> {
+    if header.starts_with(b"\x7FELF") {
+        let little_endian = *header.get(5)? == 1;
+        return match u16_at(header, 18, little_endian)? {
+            62 => Some(Arch::X86_64),
+            183 => Some(Arch::Arm64),
+            _ => None,
+        };
+    }
+
+    if header.starts_with(b"MZ") {
+        let pe = u32_at(header, 0x3C, true)? as usize;
+        if header.get(pe..pe + 4)? != b"PE\0\0" {
+            return None;
+        }
+        return match u16_at(header, pe + 4, true)? {
+            0x8664 => Some(Arch::X86_64),
+            0xAA64 => Some(Arch::Arm64),
+            _ => None,
+        };
+    }
+
+    match u32_at(header, 0, false)? {
+        0xCAFEBABE => Some(Arch::Universal),
+        0xCFFAEDFE => match u32_at(header, 4, true)? {
+            0x0100_0007 => Some(Arch::X86_64),
+            0x0100_000C => Some(Arch::Arm64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Architecture the executable at `path` is built for
+pub fn binary_arch(path: &Path) -> Result<Arch> {
+    let mut header = Vec::new();
+    File::open(path)?.take(4096).read_to_end(&mut header)?;
+    header_arch(&header).ok_or_else(|| {
+        Error::msg(format!(
+            "{} is not an executable of a known architecture",
+            path.display()
+        ))
+    })
+}
+
+/// Strip trailing path separators of a configured path, keeping a root
+/// like `/` or `C:\` intact
+pub fn trim_trailing_separators(path: &str) -> &str {
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    if trimmed.is_empty() || trimmed.ends_with(':') {
+        &path[..path.len().min(trimmed.len() + 1)]
+    } else {
+        trimmed
+    }
+}
+
+/// Configured directory as a path, with or without trailing separators
+pub fn normalize_dir(path: &str) -> PathBuf {
+    PathBuf::from(trim_trailing_separators(path))
+}
+
+/// Whether files can be created in `dir`, creating it if needed
+pub fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".write-test");
+    if fs::create_dir_all(dir).is_err() || fs::write(&probe, b"").is_err() {
+        return false;
+    }
+
+    let _ = fs::remove_file(&probe);
+    true
+}
+
+/// Subdirectory of the install directory used when no other temp directory
+/// is usable
+pub const INSTALL_TEMP_DIR: &str = "tmp";
+
+/// Directory downloads get staged in before they're moved into place
+///
+/// The `lsp.tempDir` option has to be writable. Otherwise the system temp
+/// directory is used, falling back to a subdirectory of the install
+/// directory in sandboxes where it's missing or read-only.
+pub fn temp_dir(
+    option: Option<&str>,
+    os: &str,
+    env: impl Fn(&str) -> Option<String>,
+    install_dir: &Path,
+) -> Result<PathBuf> {
+    if let Some(dir) = option.filter(|dir| !dir.is_empty()) {
+        let dir = normalize_dir(dir);
+        if !is_writable(&dir) {
+            return Err(Error::msg(format!(
+                "tempDir {} is not writable",
+                dir.display()
+            )));
+        }
+        return Ok(dir);
+    }
+
+    let system = match os {
+        "windows" => env("TEMP").or_else(|| env("TMP")),
+        _ => env("TMPDIR").or_else(|| Some("/tmp".to_string())),
+    }
+    .filter(|dir| !dir.is_empty())
+    .map(|dir| normalize_dir(&dir).join("lapce-dlang"));
+    match system {
+        Some(dir) if is_writable(&dir) => Ok(dir),
+        _ => Ok(install_dir.join(INSTALL_TEMP_DIR)),
+    }
+}
+
+/// Filesystem path of an URI that may be a `file://` URL or a plain path
+///
+/// URLs are percent-decoded, plain paths are taken as they are since `%`,
+/// `#` or spaces are valid in file names.
+pub fn uri_to_path(uri: &str) -> Result<PathBuf> {
+    if !uri.starts_with("file:") {
+        return Ok(PathBuf::from(uri));
+    }
+
+    Url::parse(uri)?
+        .to_file_path()
+        .map_err(|_| Error::msg(format!("{} is not a local path", uri)))
+}
+
+/// Resolve symlinks in a directory path, so paths built from it compare
+/// consistently
+///
+/// A directory that doesn't exist yet is resolved through its parent, and
+/// the path is kept as is when neither resolves.
+pub fn canonical_dir(dir: &Path) -> PathBuf {
+    if let Ok(dir) = dir.canonicalize() {
+        return dir;
+    }
+
+    match (dir.parent(), dir.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => dir.to_path_buf(),
+        },
+        _ => dir.to_path_buf(),
+    }
+}
+
+/// Expand the environment variables of a path option, `$VAR` and `${VAR}`,
+/// or `%VAR%` on Windows, looking them up through `env`
+///
+/// Undefined variables are left as written, as is everything on a path
+/// without any.
+pub fn expand_vars(input: &str, windows: bool, env: impl Fn(&str) -> Option<String>) -> String {
+    let sigil = if windows { '%' } else { '$' };
+    let mut expanded = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find(sigil) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        // Variable name and how much of `after` the reference spans
+        let (name, len) = if windows {
+            after
+                .find('%')
+                .map_or(("", 0), |end| (&after[..end], end + 1))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced
+                .find('}')
+                .map_or(("", 0), |end| (&braced[..end], end + 2))
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        match Some(name).filter(|name| !name.is_empty()).and_then(&env) {
+            Some(value) => {
+                expanded.push_str(&value);
+                rest = &after[len..];
+            }
+            None => {
+                expanded.push(sigil);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Environment variable naming the install directory
+pub const INSTALL_DIR_ENV: &str = "LAPCE_DLANG_INSTALL_DIR";
+
+/// Install directory the user picked: the `lsp.installDir` option, then
+/// `$LAPCE_DLANG_INSTALL_DIR`
+pub fn install_dir_override(option: Option<&str>, env: Option<String>) -> Option<PathBuf> {
+    option
+        .map(str::to_string)
+        .or(env)
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| normalize_dir(&dir))
+}
+
+/// Directory of the plugin, as a filesystem path
+pub fn volt_dir(host: &dyn Host) -> Result<PathBuf> {
+    uri_to_path(host.volt_uri()?.as_str())
+}
+
+/// Per-user cache directory for the given OS, looking up environment
+/// variables through `env`
+///
+/// - Linux: `$XDG_CACHE_HOME`, falling back to `$HOME/.cache`
+/// - macOS: `$HOME/Library/Caches`
+/// - Windows: `%LOCALAPPDATA%`
+pub fn cache_dir(os: &str, env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let env = |key: &str| env(key).filter(|value| !value.is_empty());
+
+    let base = match os {
+        "linux" => env("XDG_CACHE_HOME")
+            .map(|cache| normalize_dir(&cache))
+            .or_else(|| env("HOME").map(|home| normalize_dir(&home).join(".cache")))?,
+        "macos" => normalize_dir(&env("HOME")?).join("Library").join("Caches"),
+        "windows" => normalize_dir(&env("LOCALAPPDATA")?),
+        _ => return None,
+    };
+
+    Some(base.join("lapce-dlang"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockHost, TestDir};
+
+    fn elf_header(machine: u16) -> Vec<u8> {
+        let mut header = b"\x7FELF\x02\x01".to_vec();
+        header.resize(18, 0);
+        header.extend(machine.to_le_bytes());
+        header
+    }
+
+    fn pe_header(machine: u16) -> Vec<u8> {
+        let mut header = b"MZ".to_vec();
+        header.resize(0x3C, 0);
+        header.extend(0x40u32.to_le_bytes());
+        header.extend(b"PE\0\0");
+        header.extend(machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn reads_elf_architectures() {
+        assert_eq!(header_arch(&elf_header(62)), Some(Arch::X86_64));
+        assert_eq!(header_arch(&elf_header(183)), Some(Arch::Arm64));
+        assert_eq!(header_arch(&elf_header(40)), None);
+    }
+
+    #[test]
+    fn reads_pe_architectures() {
+        assert_eq!(header_arch(&pe_header(0x8664)), Some(Arch::X86_64));
+        assert_eq!(header_arch(&pe_header(0xAA64)), Some(Arch::Arm64));
+        let mut truncated = pe_header(0x8664);
+        truncated.truncate(0x42);
+        assert_eq!(header_arch(&truncated), None);
+    }
+
+    #[test]
+    fn reads_mach_o_architectures() {
+        assert_eq!(
+            header_arch(b"\xCF\xFA\xED\xFE\x07\x00\x00\x01"),
+            Some(Arch::X86_64)
+        );
+        assert_eq!(
+            header_arch(b"\xCF\xFA\xED\xFE\x0C\x00\x00\x01"),
+            Some(Arch::Arm64)
+        );
+        let universal = header_arch(b"\xCA\xFE\xBA\xBE\x00\x00\x00\x02").unwrap();
+        assert!(universal.matches("x86_64") && universal.matches("arm64"));
+    }
+
+    #[test]
+    fn binary_arch_reads_the_file_header() {
+        let dir = TestDir::new("binary-arch");
+        let binary = dir.join("serve-d");
+        fs::write(&binary, elf_header(183)).unwrap();
+        assert_eq!(binary_arch(&binary).unwrap(), Arch::Arm64);
+
+        fs::write(&binary, "#!/bin/sh\nexec serve-d \"$@\"\n").unwrap();
+        assert!(binary_arch(&binary).is_err());
+    }
+
+    fn env(key: &str) -> Option<String> {
+        match key {
+            "HOME" => Some("/home/d".to_string()),
+            "LOCALAPPDATA" => Some("C:\\Users\\d\\AppData\\Local".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_unix_variables() {
+        assert_eq!(
+            expand_vars("$HOME/bin/serve-d", false, env),
+            "/home/d/bin/serve-d"
+        );
+        assert_eq!(expand_vars("${HOME}bin", false, env), "/home/dbin");
+        assert_eq!(expand_vars("%HOME%/bin", false, env), "%HOME%/bin");
+    }
+
+    #[test]
+    fn expands_windows_variables() {
+        assert_eq!(
+            expand_vars("%LOCALAPPDATA%\\serve-d", true, env),
+            "C:\\Users\\d\\AppData\\Local\\serve-d"
+        );
+        assert_eq!(expand_vars("$HOME\\bin", true, env), "$HOME\\bin");
+    }
+
+    #[test]
+    fn leaves_undefined_and_unterminated_variables() {
+        assert_eq!(expand_vars("$NOPE/x", false, env), "$NOPE/x");
+        assert_eq!(expand_vars("${HOME", false, env), "${HOME");
+        assert_eq!(expand_vars("cost$", false, env), "cost$");
+        assert_eq!(expand_vars("100%", true, env), "100%");
+        assert_eq!(expand_vars("%NOPE%%HOME%", true, env), "%NOPE%/home/d");
+    }
+
+    #[test]
+    fn prefers_the_native_arch_when_emulated() {
+        let selection = select_arch("x86_64", Some("aarch64"), false).unwrap();
+        assert_eq!(selection.asset_arch, "arm64");
+        assert!(selection.emulated);
+
+        let selection = select_arch("x86_64", Some("aarch64"), true).unwrap();
+        assert_eq!(selection.asset_arch, "x86_64");
+        assert!(!selection.emulated);
+    }
+
+    #[test]
+    fn keeps_the_reported_arch_without_a_native_probe() {
+        for native in [None, Some("x86_64"), Some("riscv64")] {
+            let selection = select_arch("x86_64", native, false).unwrap();
+            assert_eq!(selection.asset_arch, "x86_64");
+            assert!(!selection.emulated);
+        }
+        assert_eq!(
+            select_arch("aarch64", None, false).unwrap().asset_arch,
+            "arm64"
+        );
+        assert!(select_arch("riscv64", Some("x86_64"), false).is_err());
+    }
+
+    #[test]
+    fn resolves_the_cache_dir_per_os() {
+        assert_eq!(
+            cache_dir("linux", env),
+            Some(PathBuf::from("/home/d/.cache/lapce-dlang"))
+        );
+        let xdg = |key: &str| match key {
+            "XDG_CACHE_HOME" => Some("/var/cache/d/".to_string()),
+            _ => env(key),
+        };
+        assert_eq!(
+            cache_dir("linux", xdg),
+            Some(PathBuf::from("/var/cache/d/lapce-dlang"))
+        );
+        assert_eq!(
+            cache_dir("macos", env),
+            Some(PathBuf::from("/home/d/Library/Caches/lapce-dlang"))
+        );
+        assert_eq!(
+            cache_dir("windows", env),
+            Some(PathBuf::from("C:\\Users\\d\\AppData\\Local").join("lapce-dlang"))
+        );
+    }
+
+    #[test]
+    fn has_no_cache_dir_without_its_variables() {
+        let empty = |key: &str| match key {
+            "XDG_CACHE_HOME" | "HOME" => Some(String::new()),
+            _ => None,
+        };
+        assert_eq!(cache_dir("linux", empty), None);
+        assert_eq!(cache_dir("macos", |_| None), None);
+        assert_eq!(cache_dir("windows", |_| None), None);
+        assert_eq!(cache_dir("freebsd", env), None);
+    }
+
+    #[test]
+    fn binary_names_get_exe_on_windows_only() {
+        assert_eq!(exec_file_name("windows", None), "serve-d.exe");
+        assert_eq!(exec_file_name("linux", None), "serve-d");
+        assert_eq!(exec_file_name("windows", Some("")), "serve-d.exe");
+        assert_eq!(
+            exec_file_name("windows", Some("serve-d-fork")),
+            "serve-d-fork.exe"
+        );
+        assert_eq!(
+            exec_file_name("windows", Some("serve-d-fork.exe")),
+            "serve-d-fork.exe"
+        );
+        assert_eq!(
+            exec_file_name("windows", Some("Serve-D.EXE")),
+            "Serve-D.EXE"
+        );
+        assert_eq!(
+            exec_file_name("macos", Some("serve-d-fork")),
+            "serve-d-fork"
+        );
+    }
+
+    #[test]
+    fn builds_paths_from_file_uris_and_plain_paths() {
+        let dir = uri_to_path("file:///home/user/plugins/lapce-dlang/").unwrap();
+        assert_eq!(
+            dir.join("version.txt").to_str(),
+            Some("/home/user/plugins/lapce-dlang/version.txt")
+        );
+        let dir = uri_to_path("/home/user/plugins/lapce-dlang").unwrap();
+        assert_eq!(
+            dir.join("version.txt").to_str(),
+            Some("/home/user/plugins/lapce-dlang/version.txt")
+        );
+    }
+
+    #[test]
+    fn decodes_special_characters_of_file_uris_only() {
+        assert_eq!(
+            uri_to_path("file:///home/user/My%20Plugins/l%C3%A4pce-dlang").unwrap(),
+            PathBuf::from("/home/user/My Plugins/läpce-dlang")
+        );
+        assert_eq!(
+            uri_to_path("/home/user/My Plugins/100%20").unwrap(),
+            PathBuf::from("/home/user/My Plugins/100%20")
+        );
+        assert!(uri_to_path("file://example.com/plugins").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_symlinked_dirs() {
+        let base = TestDir::new("symlinked");
+        let real = base.join("real");
+        fs::create_dir_all(&real).unwrap();
+        let real = real.canonicalize().unwrap();
+        let link = base.join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        assert_eq!(canonical_dir(&link), real);
+        // Not created yet, resolved through its parent
+        assert_eq!(canonical_dir(&link.join("serve-d")), real.join("serve-d"));
+        let missing = Path::new("/lapce-dlang-missing/serve-d");
+        assert_eq!(canonical_dir(missing), missing);
+    }
+
+    #[test]
+    fn trims_trailing_separators_but_keeps_roots() {
+        assert_eq!(trim_trailing_separators("/opt/serve-d/"), "/opt/serve-d");
+        assert_eq!(trim_trailing_separators("/opt/serve-d//"), "/opt/serve-d");
+        assert_eq!(trim_trailing_separators("C:\\serve-d\\"), "C:\\serve-d");
+        assert_eq!(trim_trailing_separators("/opt/serve-d"), "/opt/serve-d");
+        assert_eq!(trim_trailing_separators("/"), "/");
+        assert_eq!(trim_trailing_separators("//"), "/");
+        assert_eq!(trim_trailing_separators("C:\\"), "C:\\");
+        assert_eq!(trim_trailing_separators("C:\\\\"), "C:\\");
+        assert_eq!(trim_trailing_separators(""), "");
+    }
+
+    #[test]
+    fn joins_onto_dirs_with_and_without_trailing_separators() {
+        for dir in ["/opt/serve-d", "/opt/serve-d/", "/opt/serve-d///"] {
+            assert_eq!(
+                normalize_dir(dir).join("version.txt").to_str(),
+                Some("/opt/serve-d/version.txt")
+            );
+        }
+        let env = |key: &str| (key == "XDG_CACHE_HOME").then(|| "/home/user/.cache/".to_string());
+        assert_eq!(
+            cache_dir("linux", env),
+            Some(PathBuf::from("/home/user/.cache/lapce-dlang"))
+        );
+    }
+
+    #[test]
+    fn install_dir_option_wins_over_the_environment() {
+        let env = Some("/srv/serve-d/".to_string());
+        assert_eq!(
+            install_dir_override(Some("/opt/serve-d"), env.clone()),
+            Some(PathBuf::from("/opt/serve-d"))
+        );
+        assert_eq!(
+            install_dir_override(None, env),
+            Some(PathBuf::from("/srv/serve-d"))
+        );
+        assert_eq!(install_dir_override(None, Some(String::new())), None);
+        assert_eq!(install_dir_override(None, None), None);
+    }
+
+    #[test]
+    fn windows_executables_go_by_extension() {
+        let dir = TestDir::new("windows-executable");
+        for name in ["serve-d.EXE", "serve-d"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let host = MockHost::default();
+
+        assert!(is_executable(&host, "windows", &dir.join("serve-d.EXE")).unwrap());
+        assert!(!is_executable(&host, "windows", &dir.join("serve-d")).unwrap());
+        assert!(!is_executable(&host, "windows", &dir.join("dcd-server.exe")).unwrap());
+        assert!(ensure_executable(&host, "windows", &dir.join("serve-d.EXE")).is_ok());
+        assert!(ensure_executable(&host, "windows", &dir.join("serve-d")).is_err());
+    }
+
+    #[test]
+    fn adds_a_missing_execute_bit() {
+        let test = "test -f /opt/serve-d -a -x /opt/serve-d".to_string();
+        let chmod = "chmod +x /opt/serve-d".to_string();
+        let path = Path::new("/opt/serve-d");
+
+        let host = MockHost {
+            programs: [(test.clone(), String::new())].into(),
+            ..Default::default()
+        };
+        assert!(is_executable(&host, "linux", path).unwrap());
+        ensure_executable(&host, "linux", path).unwrap();
+        assert!(host.logs.borrow().is_empty());
+
+        let host = MockHost {
+            programs: [(chmod.clone(), String::new())].into(),
+            failing: [test.clone()].into(),
+            ..Default::default()
+        };
+        assert!(!is_executable(&host, "linux", path).unwrap());
+        ensure_executable(&host, "linux", path).unwrap();
+        assert_eq!(
+            *host.logs.borrow(),
+            vec!["Made /opt/serve-d executable".to_string()]
+        );
+
+        let host = MockHost {
+            failing: [test, chmod].into(),
+            ..Default::default()
+        };
+        let err = ensure_executable(&host, "linux", path).unwrap_err();
+        assert!(
+            err.to_string().contains("making it executable failed"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn removes_the_quarantine_only_when_allowed_and_intact() {
+        use QuarantineAction::*;
+        assert_eq!(quarantine_action(false, true, true), Nothing);
+        assert_eq!(quarantine_action(false, false, false), Nothing);
+        assert_eq!(quarantine_action(true, true, true), Remove);
+        assert_eq!(quarantine_action(true, true, false), Warn);
+        assert_eq!(quarantine_action(true, false, true), Warn);
+    }
+
+    #[test]
+    fn handles_a_quarantined_binary() {
+        let binary = Path::new("/Applications/serve-d");
+        let quarantined = |allowed: &[&str]| MockHost {
+            os: Some("macos"),
+            programs: ["xattr -p com.apple.quarantine /Applications/serve-d"]
+                .iter()
+                .chain(allowed)
+                .map(|command| (command.to_string(), String::new()))
+                .collect(),
+            ..Default::default()
+        };
+
+        let host = quarantined(&["xattr -d com.apple.quarantine /Applications/serve-d"]);
+        handle_quarantine(&host, binary, true, true).unwrap();
+        assert!(host.messages.borrow().is_empty());
+        assert_eq!(
+            *host.logs.borrow(),
+            vec!["Removed the quarantine from /Applications/serve-d"]
+        );
+
+        let host = quarantined(&[]);
+        handle_quarantine(&host, binary, false, true).unwrap();
+        assert_eq!(host.messages.borrow()[0].0, MessageType::WARNING);
+
+        // Not quarantined, xattr -p fails
+        let host = MockHost::default();
+        handle_quarantine(&host, binary, true, true).unwrap();
+        assert!(host.messages.borrow().is_empty() && host.logs.borrow().is_empty());
+    }
+
+    #[test]
+    fn selects_a_writable_temp_dir() {
+        let root = TestDir::new("temp-dir");
+        // Nothing can be created below a file
+        let blocked = root.join("file");
+        fs::write(&blocked, b"").unwrap();
+        let install_dir = root.join("install");
+        let env = |key: &str| match key {
+            "TMPDIR" | "TEMP" => Some(root.display().to_string()),
+            _ => None,
+        };
+
+        let configured = root.join("configured");
+        assert_eq!(
+            temp_dir(
+                Some(&format!("{}/", configured.display())),
+                "linux",
+                env,
+                &install_dir
+            )
+            .unwrap(),
+            configured
+        );
+        let err = temp_dir(
+            Some(&blocked.join("tmp").display().to_string()),
+            "linux",
+            env,
+            &install_dir,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is not writable"), "{}", err);
+
+        assert_eq!(
+            temp_dir(None, "linux", env, &install_dir).unwrap(),
+            root.join("lapce-dlang")
+        );
+        assert_eq!(
+            temp_dir(Some(""), "windows", env, &install_dir).unwrap(),
+            root.join("lapce-dlang")
+        );
+
+        let unusable = |_: &str| Some(blocked.display().to_string());
+        assert_eq!(
+            temp_dir(None, "linux", unusable, &install_dir).unwrap(),
+            install_dir.join(INSTALL_TEMP_DIR)
+        );
+        assert_eq!(
+            temp_dir(None, "windows", |_| None, &install_dir).unwrap(),
+            install_dir.join(INSTALL_TEMP_DIR)
+        );
+    }
+}
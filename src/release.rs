@@ -0,0 +1,1080 @@
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    archive::ArchiveKind,
+    host::Host,
+    http::HttpClient,
+    lock::FileLock,
+    manifest, stats,
+    version::{self, Channel, PrereleaseOrder},
+};
+
+/// Name prefix of the main serve-d asset
+const MAIN_ASSET_PREFIX: &str = "serve-d";
+
+#[derive(Serialize, Deserialize)]
+pub struct GHAsset {
+    pub tag_name: String,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub published_at: Option<String>,
+    /// Release notes, which may list the checksums of the assets
+    #[serde(default)]
+    pub body: Option<String>,
+    pub assets: Vec<GHReleaseAsset>,
+}
+
+/// Release asset as returned by the GitHub API
+///
+/// Only the name and download url are required, other fields default so
+/// that shape changes between API versions don't fail the parse. Unknown
+/// fields are ignored.
+#[derive(Serialize, Deserialize)]
+pub struct GHReleaseAsset {
+    #[serde(default)]
+    pub id: isize,
+    pub name: String,
+    #[serde(default)]
+    pub size: isize,
+    #[serde(default)]
+    pub download_count: isize,
+    pub browser_download_url: String,
+    #[serde(default)]
+    pub created_at: String,
+}
+
+impl GHReleaseAsset {
+    fn is_main(&self) -> bool {
+        self.name.starts_with(MAIN_ASSET_PREFIX)
+    }
+
+    /// Name of the archive the asset holds, or a part of for split archives
+    pub fn archive_name(&self) -> String {
+        is_split_asset(&self.name)
+            .map(|(archive_name, _)| archive_name)
+            .unwrap_or_else(|| self.name.clone())
+    }
+}
+
+/// Archive name and part number of a split archive part, e.g. `2` for
+/// `serve-d_0.8.0-x86_64-linux.tar.xz.002`
+///
+/// Parts carry a numeric extension of at least three digits, counting from
+/// `.001`.
+pub fn is_split_asset(name: &str) -> Option<(String, u32)> {
+    let (archive_name, part) = name.rsplit_once('.')?;
+    if archive_name.is_empty() || part.len() < 3 || !part.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let part = part.parse::<u32>().ok().filter(|part| *part > 0)?;
+    Some((archive_name.to_string(), part))
+}
+
+/// A number the API may also send as a numeric string
+fn lenient_number(value: Option<&Value>) -> isize {
+    value
+        .and_then(|value| value.as_i64().or_else(|| value.as_str()?.parse().ok()))
+        .unwrap_or_default() as isize
+}
+
+/// Read a release the strict parse rejected, taking only the tag and the
+/// name, download url and size of each asset by path
+///
+/// Assets without a name or download url are left out, nothing else about
+/// the response has to match.
+pub fn parse_release_lenient(release: &Value) -> Option<GHAsset> {
+    let tag_name = release.get("tag_name")?.as_str()?.to_string();
+    let assets = release
+        .get("assets")?
+        .as_array()?
+        .iter()
+        .filter_map(|asset| {
+            Some(GHReleaseAsset {
+                id: lenient_number(asset.get("id")),
+                name: asset.get("name")?.as_str()?.to_string(),
+                size: lenient_number(asset.get("size")),
+                download_count: lenient_number(asset.get("download_count")),
+                browser_download_url: asset.get("browser_download_url")?.as_str()?.to_string(),
+                created_at: asset
+                    .get("created_at")
+                    .and_then(|created_at| created_at.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+        })
+        .collect();
+
+    Some(GHAsset {
+        tag_name,
+        prerelease: release
+            .get("prerelease")
+            .and_then(|prerelease| prerelease.as_bool())
+            .unwrap_or(false),
+        published_at: release
+            .get("published_at")
+            .and_then(|published_at| published_at.as_str())
+            .map(str::to_string),
+        body: release
+            .get("body")
+            .and_then(|body| body.as_str())
+            .map(str::to_string),
+        assets,
+    })
+}
+
+/// Parse a GitHub `YYYY-MM-DDTHH:MM:SSZ` timestamp into unix seconds
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim_end_matches('Z').split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since the unix epoch of a proleptic Gregorian date
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Default age after which the latest release is considered suspicious
+pub const DEFAULT_STALE_DAYS: u64 = 365;
+
+/// Pick the newest of `releases` by `order`
+///
+/// Ties, and tags that aren't versions when ordering by semver, keep the
+/// order GitHub listed them in.
+pub fn newest(releases: Vec<GHAsset>, order: PrereleaseOrder) -> Option<GHAsset> {
+    // Scanning in reverse lets `max_by_key` settle ties on the first listed
+    let releases = releases.into_iter().rev();
+    match order {
+        PrereleaseOrder::Semver => {
+            releases.max_by_key(|release| version::parse_tag(&release.tag_name).ok())
+        }
+        PrereleaseOrder::Date => releases.max_by_key(|release| {
+            release
+                .published_at
+                .as_deref()
+                .and_then(parse_timestamp)
+                .or_else(|| release.released_at())
+        }),
+    }
+}
+
+impl GHAsset {
+    /// When the release was published, going by its newest asset
+    pub fn released_at(&self) -> Option<u64> {
+        self.assets
+            .iter()
+            .filter_map(|asset| parse_timestamp(&asset.created_at))
+            .max()
+    }
+
+    /// Warning to show when the release is older than `threshold_days`,
+    /// e.g. because the repository is archived or a stale fork
+    pub fn staleness_warning(&self, now: u64, threshold_days: u64) -> Option<String> {
+        let age_days = now.saturating_sub(self.released_at()?) / 86400;
+        if age_days <= threshold_days {
+            return None;
+        }
+
+        Some(format!(
+            "The latest serve-d release {} is {} days old, check that the release source is correct",
+            self.tag_name, age_days
+        ))
+    }
+
+    /// Whether the release tag is a version of the major version
+    pub fn has_major(&self, major: u64) -> bool {
+        version::parse_tag(&self.tag_name)
+            .map(|version| version.major == major)
+            .unwrap_or(false)
+    }
+
+    /// Parts of the split archive `archive_name`, in order
+    ///
+    /// Fails when a part is missing, the archive couldn't be put together.
+    pub fn split_parts(&self, archive_name: &str) -> Result<Vec<&GHReleaseAsset>> {
+        let mut parts: Vec<(u32, &GHReleaseAsset)> = self
+            .assets
+            .iter()
+            .filter_map(|asset| match is_split_asset(&asset.name) {
+                Some((name, part)) if name == archive_name => Some((part, asset)),
+                _ => None,
+            })
+            .collect();
+        parts.sort_by_key(|(part, _)| *part);
+
+        for (expected, (part, _)) in (1..).zip(&parts) {
+            if *part != expected {
+                return Err(Error::msg(format!(
+                    "Part {} of the split archive {} is missing",
+                    expected, archive_name
+                )));
+            }
+        }
+        Ok(parts.into_iter().map(|(_, asset)| asset).collect())
+    }
+
+    /// All release assets built for the platform, in extraction order
+    ///
+    /// The main serve-d asset comes first, followed by companion tools
+    /// (e.g. dcd) sorted by name, so extraction is deterministic.
+    pub fn platform_assets(
+        &self,
+        arch_name: &str,
+        os_name: &str,
+        kind: ArchiveKind,
+    ) -> Vec<&GHReleaseAsset> {
+        let suffix = format!("-{}-{}.{}", arch_name, os_name, kind.extension());
+
+        // A split archive stands in as its first part
+        let mut assets: Vec<&GHReleaseAsset> = self
+            .assets
+            .iter()
+            .filter(|asset| match is_split_asset(&asset.name) {
+                Some((archive_name, part)) => part == 1 && archive_name.ends_with(&suffix),
+                None => asset.name.ends_with(&suffix),
+            })
+            .collect();
+        assets.sort_by(|a, b| b.is_main().cmp(&a.is_main()).then(a.name.cmp(&b.name)));
+        assets
+    }
+}
+
+/// GitHub REST API version the response types are written against
+const GITHUB_API_VERSION: &str = "2022-11-28";
+
+const REPOSITORY: &[&str] = &["Pure-D", "serve-d"];
+
+/// GitHub host serve-d releases come from unless `lsp.githubHost` is set
+pub const DEFAULT_GITHUB_HOST: &str = "github.com";
+
+/// Check that `lsp.githubHost` is a bare host name, optionally with a port,
+/// rather than a url
+pub fn validate_github_host(host: &str) -> Result<()> {
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    let valid_name = !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    let valid_port = port.map(|port| port.parse::<u16>().is_ok()).unwrap_or(true);
+    if valid_name && valid_port {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "githubHost must be a host name like github.example.com, not {:?}",
+            host
+        )))
+    }
+}
+
+/// Base url of the API of a GitHub host
+///
+/// github.com serves its API from `api.github.com`, GitHub Enterprise
+/// Server from `/api/v3` on the host itself.
+pub fn api_base_url(github_host: &str) -> Result<Url> {
+    validate_github_host(github_host)?;
+    let url = if github_host.eq_ignore_ascii_case(DEFAULT_GITHUB_HOST) {
+        "https://api.github.com/".to_string()
+    } else {
+        format!("https://{}/api/v3/", github_host)
+    };
+    Ok(Url::parse(&url)?)
+}
+
+/// Build a serve-d repository API url from path segments
+///
+/// Segments are percent-encoded, so e.g. release tags of forks may contain
+/// any character.
+pub fn repo_api_url(api_base: &Url, segments: &[&str]) -> Result<Url> {
+    let mut url = api_base.clone();
+    url.path_segments_mut()
+        .map_err(|_| Error::msg("Invalid GitHub API url"))?
+        .pop_if_empty()
+        .push("repos")
+        .extend(REPOSITORY)
+        .extend(segments);
+    Ok(url)
+}
+
+/// API url downloading a release asset, which private repositories require
+/// instead of its `browser_download_url`
+pub fn asset_api_url(api_base: &Url, asset: &GHReleaseAsset) -> Result<Url> {
+    repo_api_url(api_base, &["releases", "assets", &asset.id.to_string()])
+}
+
+/// Default time a fetched response is reused without asking GitHub again
+pub const DEFAULT_REUSE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Access to the GitHub API for release metadata
+pub struct GitHub<'a> {
+    pub http: &'a dyn HttpClient,
+    pub host: &'a dyn Host,
+    /// API base url, see [`api_base_url`]
+    pub api_base: Url,
+    /// Token sent as bearer authorization, raising the rate limit
+    pub token: Option<String>,
+    pub timeout: Duration,
+    /// How long a fetched response is reused as is
+    pub reuse_window: Duration,
+    /// Fall back to [`parse_release_lenient`] when a release doesn't parse
+    pub lenient_parse: bool,
+}
+
+impl<'a> GitHub<'a> {
+    /// GET a GitHub API url, revalidating the response cached in
+    /// `cache_dir` under `cache_name` with its ETag
+    ///
+    /// A `304 Not Modified` answer means the cached response is still
+    /// current, so no new metadata is downloaded.
+    /// A response fetched within the reuse window, e.g. by the plugin
+    /// instance of another workspace opened at the same time, is taken
+    /// without a request. Responses without an ETag are cached for that
+    /// window too, they just can't be revalidated after it.
+    ///
+    /// The cache files carry a hash of the full url, so a changed
+    /// `githubHost` never gets the responses of another host.
+    fn get_cached(&self, url: &Url, cache_dir: &Path, cache_name: &str) -> Result<Vec<u8>> {
+        let cache_name = format!(
+            "{}-{}",
+            cache_name,
+            &manifest::sha256_hex(url.as_str().as_bytes())[..16]
+        );
+        let etag_file = cache_dir.join(format!("{}.etag", cache_name));
+        let body_file = cache_dir.join(format!("{}.json", cache_name));
+        let fetched_file = cache_dir.join(format!("{}.fetched", cache_name));
+
+        if let Some(body) = self.recent_response(&body_file, &fetched_file) {
+            return Ok(body);
+        }
+        let lock = FileLock::acquire(
+            cache_dir.join(format!("{}.lock", cache_name)),
+            self.timeout,
+            self.timeout,
+        );
+        if lock.is_none() {
+            // The holder is still asking GitHub, the response it got last
+            // time beats asking alongside it
+            if let Ok(body) = fs::read(&body_file) {
+                self.host.log(
+                    "Timed out waiting for another workspace checking for serve-d releases, \
+                     using the previous check",
+                );
+                return Ok(body);
+            }
+            self.host.log(
+                "Timed out waiting for another workspace checking for serve-d releases, \
+                 checking as well",
+            );
+        }
+        // Another instance may have fetched it while waiting for the lock
+        if let Some(body) = self.recent_response(&body_file, &fetched_file) {
+            return Ok(body);
+        }
+
+        let etag = if body_file.exists() {
+            fs::read_to_string(&etag_file).ok()
+        } else {
+            None
+        };
+
+        let authorization = self.token.as_ref().map(|token| format!("Bearer {}", token));
+        let mut headers = vec![
+            ("Accept", "application/vnd.github+json"),
+            ("X-GitHub-Api-Version", GITHUB_API_VERSION),
+        ];
+        if let Some(authorization) = authorization.as_deref() {
+            headers.push(("Authorization", authorization));
+        }
+        if let Some(etag) = etag.as_deref() {
+            headers.push(("If-None-Match", etag.trim()));
+        }
+
+        let resp = self.http.get(url.as_str(), &headers, self.timeout)?;
+        match resp.status {
+            304 => {
+                fs::write(&fetched_file, stats::now().to_string())?;
+                Ok(fs::read(&body_file)?)
+            }
+            200 => {
+                fs::write(&body_file, &resp.body)?;
+                match resp.header("etag") {
+                    Some(etag) => fs::write(&etag_file, etag)?,
+                    None if etag_file.exists() => fs::remove_file(&etag_file)?,
+                    None => {}
+                }
+                fs::write(&fetched_file, stats::now().to_string())?;
+
+                Ok(resp.body)
+            }
+            status => Err(Error::msg(format!(
+                "Fetching serve-d releases failed with error {}",
+                status
+            ))),
+        }
+    }
+
+    /// Cached response body if it was fetched within the reuse window
+    fn recent_response(&self, body_file: &Path, fetched_file: &Path) -> Option<Vec<u8>> {
+        let fetched_at: u64 = fs::read_to_string(fetched_file).ok()?.trim().parse().ok()?;
+        if stats::now().saturating_sub(fetched_at) >= self.reuse_window.as_secs() {
+            return None;
+        }
+        fs::read(body_file).ok()
+    }
+
+    /// Parse a release response, leniently if the strict parse fails and
+    /// that's enabled
+    fn parse_release(&self, body: &[u8]) -> Result<GHAsset> {
+        let err = match serde_json::from_slice(body) {
+            Ok(release) => return Ok(release),
+            Err(err) => err,
+        };
+        let lenient = serde_json::from_slice::<Value>(body)
+            .ok()
+            .filter(|_| self.lenient_parse)
+            .and_then(|release| parse_release_lenient(&release));
+        match lenient {
+            Some(release) => {
+                self.log_lenient(&err);
+                Ok(release)
+            }
+            None => Err(err.into()),
+        }
+    }
+
+    fn log_lenient(&self, err: &serde_json::Error) {
+        self.host.log(&format!(
+            "Unexpected GitHub release response ({}), reading only the fields needed",
+            err
+        ));
+    }
+
+    /// Fetch the most recent serve-d releases, newest first
+    fn fetch_releases(&self, cache_dir: &Path) -> Result<Vec<GHAsset>> {
+        let body = self.get_cached(
+            &repo_api_url(&self.api_base, &["releases"])?,
+            cache_dir,
+            "releases",
+        )?;
+        let err = match serde_json::from_slice(&body) {
+            Ok(releases) => return Ok(releases),
+            Err(err) => err,
+        };
+        let lenient = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .filter(|_| self.lenient_parse)
+            .and_then(|releases| {
+                releases
+                    .as_array()
+                    .map(|releases| releases.iter().filter_map(parse_release_lenient).collect())
+            });
+        match lenient {
+            Some(releases) => {
+                self.log_lenient(&err);
+                Ok(releases)
+            }
+            None => Err(err.into()),
+        }
+    }
+
+    /// Fetch the newest serve-d release of the channel
+    ///
+    /// The stable channel uses GitHub's latest release, which never is a
+    /// pre-release. The prerelease channel takes the newest of all releases
+    /// by `order`.
+    pub fn fetch_release(
+        &self,
+        cache_dir: &Path,
+        channel: Channel,
+        order: PrereleaseOrder,
+    ) -> Result<GHAsset> {
+        match channel {
+            Channel::Stable => self.parse_release(&self.get_cached(
+                &repo_api_url(&self.api_base, &["releases", "latest"])?,
+                cache_dir,
+                "release",
+            )?),
+            Channel::Prerelease => newest(self.fetch_releases(cache_dir)?, order)
+                .ok_or_else(|| Error::msg("serve-d has no releases")),
+        }
+    }
+
+    /// Walk back from the newest serve-d release of the channel to the first
+    /// one `accept` takes
+    pub fn find_release(
+        &self,
+        cache_dir: &Path,
+        channel: Channel,
+        accept: impl Fn(&GHAsset) -> bool,
+    ) -> Result<Option<GHAsset>> {
+        Ok(self
+            .fetch_releases(cache_dir)?
+            .into_iter()
+            .filter(|release| channel == Channel::Prerelease || !release.prerelease)
+            .find(|release| accept(release)))
+    }
+
+    /// Whether the serve-d repository is private, so its release assets
+    /// have to be downloaded through the API
+    pub fn is_private(&self, cache_dir: &Path) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct Repository {
+            #[serde(default)]
+            private: bool,
+        }
+
+        let repository: Repository = serde_json::from_slice(&self.get_cached(
+            &repo_api_url(&self.api_base, &[])?,
+            cache_dir,
+            "repository",
+        )?)?;
+        Ok(repository.private)
+    }
+
+    /// Fetch the newest serve-d release of the channel with the given major
+    /// version
+    pub fn fetch_major_release(
+        &self,
+        cache_dir: &Path,
+        channel: Channel,
+        major: u64,
+    ) -> Result<GHAsset> {
+        self.find_release(cache_dir, channel, |release| release.has_major(major))?
+            .ok_or_else(|| Error::msg(format!("serve-d has no {}.x releases", major)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{MockHost, MockHttp, TestDir};
+
+    fn asset(name: &str) -> GHReleaseAsset {
+        GHReleaseAsset {
+            id: 0,
+            name: name.to_string(),
+            size: 0,
+            download_count: 0,
+            browser_download_url: format!("https://example.com/{}", name),
+            created_at: String::new(),
+        }
+    }
+
+    fn release(names: &[&str]) -> GHAsset {
+        GHAsset {
+            tag_name: "v0.8.0".to_string(),
+            prerelease: false,
+            published_at: None,
+            body: None,
+            assets: names.iter().map(|name| asset(name)).collect(),
+        }
+    }
+
+    fn names(assets: Vec<&GHReleaseAsset>) -> Vec<&str> {
+        assets.iter().map(|asset| asset.name.as_str()).collect()
+    }
+
+    const ARCHIVE: &str = "serve-d_0.8.0-x86_64-linux.tar.xz";
+
+    #[test]
+    fn recognizes_split_asset_parts() {
+        assert_eq!(
+            is_split_asset(&format!("{}.002", ARCHIVE)),
+            Some((ARCHIVE.to_string(), 2))
+        );
+        assert_eq!(
+            is_split_asset(&format!("{}.0010", ARCHIVE)),
+            Some((ARCHIVE.to_string(), 10))
+        );
+        assert_eq!(is_split_asset(ARCHIVE), None);
+        assert_eq!(is_split_asset(&format!("{}.01", ARCHIVE)), None);
+        assert_eq!(is_split_asset(&format!("{}.000", ARCHIVE)), None);
+        assert_eq!(is_split_asset(".001"), None);
+    }
+
+    #[test]
+    fn orders_the_parts_of_a_split_archive() {
+        let release = release(&[
+            &format!("{}.002", ARCHIVE),
+            &format!("{}.001", ARCHIVE),
+            "serve-d_0.8.0-x86_64-windows.zip.001",
+            &format!("{}.003", ARCHIVE),
+        ]);
+        assert_eq!(
+            names(release.split_parts(ARCHIVE).unwrap()),
+            vec![
+                format!("{}.001", ARCHIVE),
+                format!("{}.002", ARCHIVE),
+                format!("{}.003", ARCHIVE),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_a_missing_part() {
+        let release = release(&[&format!("{}.001", ARCHIVE), &format!("{}.003", ARCHIVE)]);
+        let err = release.split_parts(ARCHIVE).err().unwrap().to_string();
+        assert!(err.contains("Part 2"), "{}", err);
+    }
+
+    #[test]
+    fn split_archives_stand_in_as_their_first_part() {
+        let release = release(&[
+            "dcd_0.16.0-x86_64-linux.tar.xz",
+            &format!("{}.002", ARCHIVE),
+            &format!("{}.001", ARCHIVE),
+            "serve-d_0.8.0-x86_64-windows.zip",
+        ]);
+        assert_eq!(
+            names(release.platform_assets("x86_64", "linux", ArchiveKind::TarXz)),
+            vec![
+                format!("{}.001", ARCHIVE),
+                "dcd_0.16.0-x86_64-linux.tar.xz".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn selects_every_platform_asset_main_first() {
+        let release = release(&[
+            "dcd_0.16.0-x86_64-linux.tar.xz",
+            "serve-d_0.8.0-x86_64-windows.zip",
+            "dfmt_0.15.0-x86_64-linux.tar.xz",
+            "serve-d_0.8.0-arm64-linux.tar.xz",
+            "serve-d_0.8.0-x86_64-linux.tar.xz",
+            "serve-d_0.8.0-x86_64-linux.tar.xz.sha256",
+        ]);
+        assert_eq!(
+            names(release.platform_assets("x86_64", "linux", ArchiveKind::TarXz)),
+            vec![
+                "serve-d_0.8.0-x86_64-linux.tar.xz",
+                "dcd_0.16.0-x86_64-linux.tar.xz",
+                "dfmt_0.15.0-x86_64-linux.tar.xz",
+            ]
+        );
+        assert!(release
+            .platform_assets("x86_64", "macos", ArchiveKind::TarXz)
+            .is_empty());
+    }
+
+    fn github<'a>(http: &'a MockHttp, host: &'a MockHost) -> GitHub<'a> {
+        GitHub {
+            http,
+            host,
+            api_base: api_base_url(DEFAULT_GITHUB_HOST).unwrap(),
+            token: None,
+            timeout: Duration::from_secs(10),
+            reuse_window: Duration::ZERO,
+            lenient_parse: true,
+        }
+    }
+
+    fn latest(http: &MockHttp, host: &MockHost, cache_dir: &Path) -> String {
+        github(http, host)
+            .fetch_release(cache_dir, Channel::Stable, PrereleaseOrder::Semver)
+            .unwrap()
+            .tag_name
+    }
+
+    fn sent_etag(http: &MockHttp, request: usize) -> Option<String> {
+        http.requests.borrow()[request]
+            .1
+            .iter()
+            .find(|(name, _)| name == "If-None-Match")
+            .map(|(_, etag)| etag.clone())
+    }
+
+    #[test]
+    fn not_modified_uses_the_cached_release() {
+        let dir = TestDir::new("etag-not-modified");
+        let host = MockHost::default();
+        let mut http = MockHttp::release("v0.7.6", &[]);
+        http.etag = Some("\"v0.7.6\"".to_string());
+
+        assert_eq!(latest(&http, &host, &dir), "v0.7.6");
+        assert_eq!(latest(&http, &host, &dir), "v0.7.6");
+        assert_eq!(sent_etag(&http, 0), None);
+        assert_eq!(sent_etag(&http, 1), Some("\"v0.7.6\"".to_string()));
+    }
+
+    #[test]
+    fn a_new_release_replaces_the_cached_one_and_its_etag() {
+        let dir = TestDir::new("etag-modified");
+        let host = MockHost::default();
+        let mut old = MockHttp::release("v0.7.5", &[]);
+        old.etag = Some("\"v0.7.5\"".to_string());
+        assert_eq!(latest(&old, &host, &dir), "v0.7.5");
+
+        let mut new = MockHttp::release("v0.7.6", &[]);
+        new.etag = Some("\"v0.7.6\"".to_string());
+        assert_eq!(latest(&new, &host, &dir), "v0.7.6");
+        assert_eq!(latest(&new, &host, &dir), "v0.7.6");
+        assert_eq!(sent_etag(&new, 0), Some("\"v0.7.5\"".to_string()));
+        assert_eq!(sent_etag(&new, 1), Some("\"v0.7.6\"".to_string()));
+    }
+
+    #[test]
+    fn a_timed_out_fetch_lock_uses_the_previous_check() {
+        let dir = TestDir::new("fetch-lock-timeout");
+        let host = MockHost::default();
+        let http = MockHttp::release("v0.7.6", &[]);
+        assert_eq!(latest(&http, &host, &dir), "v0.7.6");
+
+        let body = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .unwrap();
+        let held = format!("other {}", crate::stats::now() + 600);
+        fs::write(body.with_extension("lock"), &held).unwrap();
+        let mut github = github(&http, &host);
+        github.timeout = Duration::ZERO;
+        let release = github
+            .fetch_release(&dir, Channel::Stable, PrereleaseOrder::Semver)
+            .unwrap();
+        assert_eq!(release.tag_name, "v0.7.6");
+        assert_eq!(http.requests.borrow().len(), 1);
+        assert!(host
+            .logs
+            .borrow()
+            .iter()
+            .any(|log| log.contains("using the previous check")));
+        assert_eq!(
+            fs::read_to_string(body.with_extension("lock")).unwrap(),
+            held
+        );
+    }
+
+    #[test]
+    fn parses_github_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2024-02-29T12:00:00Z"), Some(1709208000));
+        assert_eq!(parse_timestamp("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn warns_about_releases_past_the_threshold() {
+        let mut release = release(&[ARCHIVE, "serve-d_0.8.0-x86_64-windows.zip"]);
+        release.assets[0].created_at = "2024-02-29T12:00:00Z".to_string();
+        release.assets[1].created_at = "2024-01-01T00:00:00Z".to_string();
+        let released = 1709208000;
+        assert_eq!(release.released_at(), Some(released));
+
+        assert_eq!(release.staleness_warning(released + 365 * 86400, 365), None);
+        let warning = release
+            .staleness_warning(released + 366 * 86400, 365)
+            .unwrap();
+        assert!(warning.contains("v0.8.0 is 366 days old"), "{}", warning);
+        // A clock behind the release date is never stale
+        assert_eq!(release.staleness_warning(0, 365), None);
+    }
+
+    #[test]
+    fn releases_without_dates_are_never_stale() {
+        let release = release(&[ARCHIVE]);
+        assert_eq!(release.released_at(), None);
+        assert_eq!(release.staleness_warning(u64::MAX, 0), None);
+    }
+
+    #[test]
+    fn encodes_tags_in_api_urls() {
+        let api_base = api_base_url(DEFAULT_GITHUB_HOST).unwrap();
+        assert_eq!(
+            repo_api_url(&api_base, &["releases", "tags", "v1.0 beta/fork#2?"])
+                .unwrap()
+                .as_str(),
+            "https://api.github.com/repos/Pure-D/serve-d/releases/tags/v1.0%20beta%2Ffork%232%3F"
+        );
+        let api_base = api_base_url("github.example.com").unwrap();
+        assert_eq!(
+            repo_api_url(&api_base, &["releases", "tags", "v0.8.0"])
+                .unwrap()
+                .as_str(),
+            "https://github.example.com/api/v3/repos/Pure-D/serve-d/releases/tags/v0.8.0"
+        );
+    }
+
+    #[test]
+    fn tells_the_major_version_of_a_release() {
+        let release = release(&[ARCHIVE]);
+        assert!(release.has_major(0));
+        assert!(!release.has_major(1));
+        let nightly = GHAsset {
+            tag_name: "nightly".to_string(),
+            ..release
+        };
+        assert!(!nightly.has_major(0));
+    }
+
+    fn published(tag: &str, published_at: Option<&str>) -> GHAsset {
+        GHAsset {
+            tag_name: tag.to_string(),
+            prerelease: true,
+            published_at: published_at.map(str::to_string),
+            ..release(&[])
+        }
+    }
+
+    fn newest_tag(releases: Vec<GHAsset>, order: PrereleaseOrder) -> Option<String> {
+        newest(releases, order).map(|release| release.tag_name)
+    }
+
+    #[test]
+    fn orders_prereleases_by_semver_or_date() {
+        let releases = || {
+            vec![
+                published("v0.8.0-beta.1", Some("2024-03-01T00:00:00Z")),
+                published("v0.8.0-beta.2", Some("2024-02-01T00:00:00Z")),
+                published("nightly", Some("2024-01-01T00:00:00Z")),
+            ]
+        };
+        assert_eq!(
+            newest_tag(releases(), PrereleaseOrder::Semver).as_deref(),
+            Some("v0.8.0-beta.2")
+        );
+        assert_eq!(
+            newest_tag(releases(), PrereleaseOrder::Date).as_deref(),
+            Some("v0.8.0-beta.1")
+        );
+        assert_eq!(newest_tag(Vec::new(), PrereleaseOrder::Semver), None);
+    }
+
+    #[test]
+    fn ties_keep_the_listed_order() {
+        let releases = || {
+            vec![
+                published("v0.8.0-beta.1", None),
+                published("0.8.0-beta.1", None),
+            ]
+        };
+        assert_eq!(
+            newest_tag(releases(), PrereleaseOrder::Semver).as_deref(),
+            Some("v0.8.0-beta.1")
+        );
+        assert_eq!(
+            newest_tag(releases(), PrereleaseOrder::Date).as_deref(),
+            Some("v0.8.0-beta.1")
+        );
+    }
+
+    #[test]
+    fn dates_fall_back_to_the_asset_dates() {
+        let mut with_assets = published("v0.8.0-beta.1", None);
+        with_assets.assets = vec![GHReleaseAsset {
+            created_at: "2024-03-01T00:00:00Z".to_string(),
+            ..asset(ARCHIVE)
+        }];
+        let releases = vec![
+            published("v0.8.0-beta.2", Some("2024-02-01T00:00:00Z")),
+            with_assets,
+        ];
+        assert_eq!(
+            newest_tag(releases, PrereleaseOrder::Date).as_deref(),
+            Some("v0.8.0-beta.1")
+        );
+    }
+
+    #[test]
+    fn parses_older_and_newer_shaped_releases() {
+        // Before download counts and asset dates were returned
+        let older = json!({
+            "tag_name": "v0.7.6",
+            "assets": [{ "name": ARCHIVE, "browser_download_url": "https://example.com/a" }],
+        });
+        let older: GHAsset = serde_json::from_value(older).unwrap();
+        assert_eq!(older.tag_name, "v0.7.6");
+        assert!(!older.prerelease);
+        assert_eq!(older.assets[0].download_count, 0);
+        assert_eq!(older.assets[0].created_at, "");
+
+        let newer = json!({
+            "tag_name": "v0.8.0",
+            "prerelease": true,
+            "immutable": true,
+            "reactions": { "total_count": 3 },
+            "assets": [{
+                "id": 7,
+                "name": ARCHIVE,
+                "size": 1024,
+                "download_count": 12,
+                "digest": "sha256:0000",
+                "browser_download_url": "https://example.com/b",
+                "created_at": "2024-03-01T00:00:00Z",
+            }],
+        });
+        let newer: GHAsset = serde_json::from_value(newer).unwrap();
+        assert!(newer.prerelease);
+        assert_eq!(newer.assets[0].size, 1024);
+        assert_eq!(newer.assets[0].download_count, 12);
+    }
+
+    #[test]
+    fn pins_the_api_version() {
+        let dir = TestDir::new("api-version");
+        let host = MockHost::default();
+        let http = MockHttp::release("v0.7.6", &[]);
+        latest(&http, &host, &dir);
+
+        let requests = http.requests.borrow();
+        assert!(requests[0].1.contains(&(
+            "X-GitHub-Api-Version".to_string(),
+            GITHUB_API_VERSION.to_string()
+        )));
+        drop(requests);
+    }
+
+    #[test]
+    fn reuses_a_response_fetched_within_the_window() {
+        let dir = TestDir::new("reuse-window");
+        let host = MockHost::default();
+        let first = MockHttp::release("v0.7.6", &[]);
+        let second = MockHttp::release("v0.8.0", &[]);
+        let fetch = |http: &MockHttp, reuse_window: Duration| {
+            GitHub {
+                reuse_window,
+                ..github(http, &host)
+            }
+            .fetch_release(&dir, Channel::Stable, PrereleaseOrder::Semver)
+            .unwrap()
+            .tag_name
+        };
+
+        assert_eq!(fetch(&first, Duration::from_secs(60)), "v0.7.6");
+        assert_eq!(fetch(&second, Duration::from_secs(60)), "v0.7.6");
+        assert!(second.requests.borrow().is_empty());
+        // Past the window the release is checked again
+        assert_eq!(fetch(&second, Duration::ZERO), "v0.8.0");
+        assert_eq!(second.requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn builds_api_urls_of_enterprise_hosts() {
+        assert_eq!(
+            api_base_url("GitHub.com").unwrap().as_str(),
+            "https://api.github.com/"
+        );
+        assert_eq!(
+            api_base_url("github.example.com").unwrap().as_str(),
+            "https://github.example.com/api/v3/"
+        );
+        let api_base = api_base_url("ghe.internal:8443").unwrap();
+        assert_eq!(
+            repo_api_url(&api_base, &["releases", "latest"])
+                .unwrap()
+                .as_str(),
+            "https://ghe.internal:8443/api/v3/repos/Pure-D/serve-d/releases/latest"
+        );
+    }
+
+    #[test]
+    fn rejects_github_hosts_that_are_not_host_names() {
+        for host in [
+            "",
+            "https://github.example.com",
+            "github.example.com/",
+            "github..example.com",
+            "-github.example.com",
+            "github.example.com:http",
+            "github.example.com:70000",
+            "github example.com",
+        ] {
+            let err = api_base_url(host).unwrap_err().to_string();
+            assert!(err.starts_with("githubHost must be a host name"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn builds_asset_api_urls_by_id() {
+        let asset = GHReleaseAsset {
+            id: 4242,
+            ..asset(ARCHIVE)
+        };
+        let api_base = api_base_url(DEFAULT_GITHUB_HOST).unwrap();
+        assert_eq!(
+            asset_api_url(&api_base, &asset).unwrap().as_str(),
+            "https://api.github.com/repos/Pure-D/serve-d/releases/assets/4242"
+        );
+        let api_base = api_base_url("github.example.com").unwrap();
+        assert_eq!(
+            asset_api_url(&api_base, &asset).unwrap().as_str(),
+            "https://github.example.com/api/v3/repos/Pure-D/serve-d/releases/assets/4242"
+        );
+    }
+
+    /// Release the strict parse rejects: the size is a string, the
+    /// prerelease flag is missing its value and one asset has no url
+    fn off_shape_release() -> Value {
+        json!({
+            "tag_name": "v0.8.0",
+            "prerelease": null,
+            "body": 7,
+            "assets": [
+                {
+                    "id": "12",
+                    "name": ARCHIVE,
+                    "size": "1024",
+                    "browser_download_url": "https://example.com/serve-d.tar.xz",
+                },
+                { "name": "serve-d_0.8.0-x86_64-windows.zip" },
+            ],
+        })
+    }
+
+    #[test]
+    fn reads_only_the_needed_fields_of_an_off_shape_release() {
+        assert!(serde_json::from_value::<GHAsset>(off_shape_release()).is_err());
+
+        let release = parse_release_lenient(&off_shape_release()).unwrap();
+        assert_eq!(release.tag_name, "v0.8.0");
+        assert!(!release.prerelease);
+        assert_eq!(release.body, None);
+        assert_eq!(names(release.assets.iter().collect()), vec![ARCHIVE]);
+        assert_eq!(release.assets[0].id, 12);
+        assert_eq!(release.assets[0].size, 1024);
+
+        assert!(parse_release_lenient(&json!({ "assets": [] })).is_none());
+        assert!(parse_release_lenient(&json!({ "tag_name": "v0.8.0" })).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_the_lenient_parse_only_when_enabled() {
+        let host = MockHost::default();
+        let http = MockHttp::release("v0.8.0", &[]);
+        let body = off_shape_release().to_string().into_bytes();
+
+        let release = github(&http, &host).parse_release(&body).unwrap();
+        assert_eq!(release.tag_name, "v0.8.0");
+        assert!(host.logs.borrow()[0].starts_with("Unexpected GitHub release response"));
+
+        let strict = GitHub {
+            lenient_parse: false,
+            ..github(&http, &host)
+        };
+        assert!(strict.parse_release(&body).is_err());
+        assert!(github(&http, &host).parse_release(b"not json").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt::Display,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result};
+use serde::Deserialize;
+
+/// Retry options table like
+/// `{ attempts = 3, backoffMs = 1000, budgetSecs = 600 }`, see
+/// [`RetryPolicy::from_options`]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RetryOptions {
+    pub attempts: Option<u64>,
+    pub backoff_ms: Option<u64>,
+    pub budget_secs: Option<u64>,
+}
+
+/// Bounds of an operation that is retried while it fails
+///
+/// Every retry site goes through here, so none of them can loop forever:
+/// retrying stops after `attempts` tries or once the next wait would run
+/// past `budget`, whichever comes first.
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries in total, including the first one
+    pub attempts: u32,
+    /// Wait before the first retry, growing with each attempt
+    pub backoff: Duration,
+    /// Wall-clock time all attempts together may take
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    /// Filesystem operations held up by e.g. a virus scanner
+    pub const FILESYSTEM: RetryPolicy = RetryPolicy {
+        attempts: 5,
+        backoff: Duration::from_millis(100),
+        budget: Duration::from_secs(5),
+    };
+
+    /// Archive downloads interrupted by the network or the server
+    pub const DOWNLOAD: RetryPolicy = RetryPolicy {
+        attempts: 3,
+        backoff: Duration::from_secs(1),
+        budget: Duration::from_secs(600),
+    };
+
+    /// Build a policy from configured `options`, taking missing ones from
+    /// `default`
+    pub fn from_options(options: &RetryOptions, default: RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            attempts: options
+                .attempts
+                .map(|attempts| attempts.clamp(1, u32::MAX as u64) as u32)
+                .unwrap_or(default.attempts),
+            backoff: options
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.backoff),
+            budget: options
+                .budget_secs
+                .map(Duration::from_secs)
+                .unwrap_or(default.budget),
+        }
+    }
+
+    /// Run `op` until it succeeds, fails with an error `retryable` rejects
+    /// or the policy is exhausted
+    ///
+    /// Giving up on a retryable error says how many attempts were made.
+    pub fn run<T, E>(
+        &self,
+        mut op: impl FnMut() -> Result<T, E>,
+        retryable: impl Fn(&E) -> bool,
+    ) -> Result<T>
+    where
+        E: Into<Error> + Display,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let err = match op() {
+                Ok(value) => return Ok(value),
+                Err(err) if retryable(&err) => err,
+                Err(err) => return Err(err.into()),
+            };
+
+            let wait = self.backoff * attempt;
+            if attempt >= self.attempts || started.elapsed() + wait > self.budget {
+                return Err(err.into().context(format!(
+                    "Gave up after {} attempt{} in {}s",
+                    attempt,
+                    if attempt == 1 { "" } else { "s" },
+                    started.elapsed().as_secs()
+                )));
+            }
+
+            thread::sleep(wait);
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(attempts: u32, backoff_ms: u64, budget_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            backoff: Duration::from_millis(backoff_ms),
+            budget: Duration::from_millis(budget_ms),
+        }
+    }
+
+    /// Run `policy` on an operation that always fails retryably, returning
+    /// how often it ran and the error
+    fn run_failing(policy: RetryPolicy) -> (u32, String) {
+        let mut runs = 0;
+        let err = policy
+            .run(
+                || -> Result<(), Error> {
+                    runs += 1;
+                    Err(Error::msg("connection reset"))
+                },
+                |_| true,
+            )
+            .unwrap_err();
+        (runs, format!("{:#}", err))
+    }
+
+    #[test]
+    fn stops_after_the_attempts() {
+        let (runs, err) = run_failing(policy(3, 1, 60_000));
+        assert_eq!(runs, 3);
+        assert!(err.starts_with("Gave up after 3 attempts"), "{}", err);
+        assert!(err.ends_with("connection reset"), "{}", err);
+
+        let (runs, err) = run_failing(policy(1, 1, 60_000));
+        assert_eq!(runs, 1);
+        assert!(err.starts_with("Gave up after 1 attempt in"), "{}", err);
+    }
+
+    #[test]
+    fn stops_before_a_wait_past_the_budget() {
+        // The second wait of 100ms would end past the budget
+        let (runs, _) = run_failing(policy(10, 100, 250));
+        assert_eq!(runs, 2);
+        let (runs, _) = run_failing(policy(10, 1_000, 500));
+        assert_eq!(runs, 1);
+    }
+
+    #[test]
+    fn returns_the_first_success_or_unretryable_error() {
+        let mut runs = 0;
+        let value = policy(5, 1, 60_000)
+            .run(
+                || {
+                    runs += 1;
+                    if runs < 3 {
+                        Err(Error::msg("busy"))
+                    } else {
+                        Ok(runs)
+                    }
+                },
+                |_| true,
+            )
+            .unwrap();
+        assert_eq!(value, 3);
+
+        let mut runs = 0;
+        let err = policy(5, 1, 60_000)
+            .run(
+                || -> Result<(), Error> {
+                    runs += 1;
+                    Err(Error::msg("not found"))
+                },
+                |err| err.to_string() != "not found",
+            )
+            .unwrap_err();
+        assert_eq!(runs, 1);
+        assert_eq!(err.to_string(), "not found");
+    }
+
+    #[test]
+    fn options_override_the_default_policy() {
+        let options = RetryOptions {
+            attempts: Some(0),
+            backoff_ms: Some(50),
+            budget_secs: None,
+        };
+        let policy = RetryPolicy::from_options(&options, RetryPolicy::DOWNLOAD);
+        assert_eq!(policy.attempts, 1);
+        assert_eq!(policy.backoff, Duration::from_millis(50));
+        assert_eq!(policy.budget, RetryPolicy::DOWNLOAD.budget);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{fs, path::Path};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::Url;
+use serde::Serialize;
+
+use crate::{
+    host::Host,
+    http::{self, HttpClient},
+    manifest::InstallManifest,
+    platform, release, server,
+};
+
+/// Outcome of a single self-test check
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Check {
+                name,
+                passed: true,
+                detail,
+            },
+            Err(err) => Check {
+                name,
+                passed: false,
+                detail: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Report returned by the `dlang/selfTest` request
+#[derive(Serialize)]
+pub struct Report {
+    /// Whether every check passed
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(checks: Vec<Check>) -> Self {
+        Report {
+            passed: checks.iter().all(|check| check.passed),
+            checks,
+        }
+    }
+}
+
+fn check_platform(host: &dyn Host) -> Result<String> {
+    let os = platform::os_name(&host.operating_system()?)?;
+    let arch = platform::select_arch(
+        host.architecture()?.as_str(),
+        platform::probe_native_arch(host, os).as_deref(),
+        false,
+    )?;
+    Ok(format!("{}, {}", os, arch.describe()))
+}
+
+fn installed_dir(install_dir: Option<&Path>) -> Result<&Path> {
+    install_dir.ok_or_else(|| Error::msg("serve-d is not installed by the plugin"))
+}
+
+fn check_write_access(install_dir: Option<&Path>) -> Result<String> {
+    let install_dir = installed_dir(install_dir)?;
+    let probe = install_dir.join(".write-test");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    Ok(install_dir.display().to_string())
+}
+
+fn check_network(http: &dyn HttpClient, api_base: Option<&Url>) -> Result<String> {
+    let url = match api_base {
+        Some(api_base) => release::repo_api_url(api_base, &[])?,
+        None => release::repo_api_url(&release::api_base_url(release::DEFAULT_GITHUB_HOST)?, &[])?,
+    };
+    let resp = http.get(url.as_str(), &[], http::DEFAULT_API_TIMEOUT)?;
+    if resp.status != 200 {
+        return Err(Error::msg(format!("{} answered {}", url, resp.status)));
+    }
+    Ok(format!("{} is reachable", url))
+}
+
+fn check_binary(install_dir: Option<&Path>) -> Result<String> {
+    let install_dir = installed_dir(install_dir)?;
+    let manifest = InstallManifest::read(install_dir)?
+        .ok_or_else(|| Error::msg("The install has no manifest"))?;
+    match manifest.binary_intact(install_dir)? {
+        Some(true) => Ok(format!("serve-d {} matches its checksum", manifest.version)),
+        Some(false) => Err(Error::msg(format!(
+            "serve-d {} does not match its checksum",
+            manifest.version
+        ))),
+        None => Ok(format!(
+            "serve-d {} has no recorded checksum",
+            manifest.version
+        )),
+    }
+}
+
+fn check_version(host: &dyn Host, server_path: Option<&Url>) -> Result<String> {
+    let server_path = server_path.ok_or_else(|| Error::msg("serve-d has not been started"))?;
+    server::health_check(host, server_path, false)?;
+    Ok(format!("{} --version runs", server::program(server_path)?))
+}
+
+/// Check everything serve-d depends on, for bug reports
+pub fn run(
+    host: &dyn Host,
+    http: &dyn HttpClient,
+    api_base: Option<&Url>,
+    install_dir: Option<&Path>,
+    server_path: Option<&Url>,
+) -> Report {
+    Report::new(vec![
+        Check::new("platform", check_platform(host)),
+        Check::new("writeAccess", check_write_access(install_dir)),
+        Check::new("network", check_network(http, api_base)),
+        Check::new("binary", check_binary(install_dir)),
+        Check::new("version", check_version(host, server_path)),
+    ])
+}
+
+fn check_exists(path: &Path) -> Result<String> {
+    if path.is_file() {
+        Ok(format!("{} exists", path.display()))
+    } else {
+        Err(Error::msg(format!("{} is not a file", path.display())))
+    }
+}
+
+fn check_executable(host: &dyn Host, path: &Path) -> Result<String> {
+    let os = platform::os_name(&host.operating_system()?)?;
+    if platform::is_executable(host, os, path)? {
+        Ok(format!("{} is executable", path.display()))
+    } else {
+        Err(Error::msg(format!("{} is not executable", path.display())))
+    }
+}
+
+fn check_architecture(host: &dyn Host, path: &Path) -> Result<String> {
+    let os = platform::os_name(&host.operating_system()?)?;
+    let arch = platform::select_arch(
+        host.architecture()?.as_str(),
+        platform::probe_native_arch(host, os).as_deref(),
+        false,
+    )?;
+    let binary_arch = platform::binary_arch(path)?;
+    if binary_arch.matches(arch.asset_arch) {
+        Ok(format!("Built for {:?}", binary_arch))
+    } else {
+        Err(Error::msg(format!(
+            "Built for {:?} but this host needs {}",
+            binary_arch, arch.asset_arch
+        )))
+    }
+}
+
+/// Check a candidate `serverPath` without starting serve-d, for the
+/// `dlang/validateServerPath` request
+pub fn validate_server_path(host: &dyn Host, path: &Path) -> Report {
+    Report::new(vec![
+        Check::new("exists", check_exists(path)),
+        Check::new("executable", check_executable(host, path)),
+        Check::new("architecture", check_architecture(host, path)),
+        Check::new(
+            "version",
+            server::probe_installed_version(host, path)
+                .map(|version| format!("serve-d {}", version)),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{fake_binary, MockHost, MockHttp, TestDir};
+
+    fn results(report: &Report) -> Vec<(&str, bool)> {
+        report
+            .checks
+            .iter()
+            .map(|check| (check.name, check.passed))
+            .collect()
+    }
+
+    #[test]
+    fn reports_each_check_on_its_own() {
+        let host = MockHost {
+            programs: [(
+                "serve-d --version".to_string(),
+                "serve-d v0.7.6".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let mut http = MockHttp::release("v0.7.6", &[]);
+        let server_path = Url::parse("urn:serve-d").unwrap();
+
+        let report = run(&host, &http, None, None, Some(&server_path));
+        assert!(!report.passed);
+        assert_eq!(
+            results(&report),
+            vec![
+                ("platform", true),
+                ("writeAccess", false),
+                ("network", false),
+                ("binary", false),
+                ("version", true),
+            ]
+        );
+        assert_eq!(
+            report.checks[2].detail,
+            "https://api.github.com/repos/Pure-D/serve-d answered 404"
+        );
+
+        http.responses.insert(
+            "https://api.github.com/repos/Pure-D/serve-d".to_string(),
+            b"{}".to_vec(),
+        );
+        let report = run(&host, &http, None, None, None);
+        assert_eq!(
+            results(&report),
+            vec![
+                ("platform", true),
+                ("writeAccess", false),
+                ("network", true),
+                ("binary", false),
+                ("version", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_only_when_every_check_does() {
+        let check = |passed: bool| {
+            Check::new(
+                "check",
+                if passed {
+                    Ok(String::new())
+                } else {
+                    Err(Error::msg("failed"))
+                },
+            )
+        };
+        assert!(Report::new(vec![check(true), check(true)]).passed);
+        assert!(!Report::new(vec![check(true), check(false)]).passed);
+        assert!(Report::new(Vec::new()).passed);
+    }
+
+    #[test]
+    fn validates_a_candidate_server_path() {
+        let dir = TestDir::new("validate-server-path");
+        let binary = dir.join("serve-d.exe");
+        fs::write(&binary, fake_binary("0.7.6")).unwrap();
+
+        let host = MockHost::default();
+        let report = validate_server_path(&host, &binary);
+        assert!(report.passed);
+        assert_eq!(report.checks[2].detail, "Built for X86_64");
+        assert_eq!(report.checks[3].detail, "serve-d 0.7.6");
+
+        let report = validate_server_path(&host, &dir.join("missing.exe"));
+        assert_eq!(
+            results(&report),
+            vec![
+                ("exists", false),
+                ("executable", false),
+                ("architecture", false),
+                ("version", false),
+            ]
+        );
+
+        let arm64 = MockHost {
+            arch: Some("aarch64"),
+            ..Default::default()
+        };
+        let report = validate_server_path(&arm64, &binary);
+        assert_eq!(
+            results(&report),
+            vec![
+                ("exists", true),
+                ("executable", true),
+                ("architecture", false),
+                ("version", true),
+            ]
+        );
+        assert_eq!(
+            report.checks[2].detail,
+            "Built for X86_64 but this host needs arm64"
+        );
+    }
+}
@@ -0,0 +1,979 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::Url;
+use semver::Version;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{host::Host, version};
+
+/// Feature sets required from serve-d when the user doesn't configure any
+const DEFAULT_REQUIRE_FEATURES: &[&str] = &["d"];
+
+/// Build the serve-d command line from the `lsp` options
+///
+/// `require_features` replaces the default `--require d`, an empty list
+/// omits `--require` entirely. The `os_args` entry of the `os` follows, then
+/// `server_args` are appended as is.
+pub fn build_server_args(
+    require_features: Option<&[String]>,
+    os_args: &BTreeMap<String, Vec<String>>,
+    server_args: &[String],
+    os: Option<&str>,
+) -> Vec<String> {
+    let features = match require_features {
+        Some(features) => features.to_vec(),
+        None => DEFAULT_REQUIRE_FEATURES
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect(),
+    };
+
+    let mut args = Vec::new();
+    for feature in features {
+        args.push("--require".to_string());
+        args.push(feature);
+    }
+
+    if let Some(os_args) = os.and_then(|os| os_args.get(os)) {
+        args.extend(os_args.iter().cloned());
+    }
+
+    args.extend(server_args.iter().cloned());
+    args
+}
+
+/// Environment variables serve-d needs on top of the inherited ones
+///
+/// serve-d inherits `$DFLAGS` from Lapce, so only an `lsp.dflags` option
+/// replacing it has to be set.
+pub fn dflags_env(option: Option<&str>, env: Option<String>) -> Vec<(String, String)> {
+    match option {
+        Some(dflags) if env.as_deref() != Some(dflags) => {
+            vec![("DFLAGS".to_string(), dflags.to_string())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Decode the `%XX` escapes of an URL path, leaving malformed ones as they
+/// are
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|hex| bytes[index] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Program to execute for a serve-d path, a `urn:` names a program on PATH
+///
+/// The `urn:` path is percent-encoded like any URL path, e.g. a program
+/// name with a space in it reads `urn:serve%20d`.
+pub fn program(server_path: &Url) -> Result<String> {
+    match server_path.scheme() {
+        "urn" => Ok(percent_decode(server_path.path())),
+        _ => Ok(server_path
+            .to_file_path()
+            .map_err(|_| Error::msg(format!("{} is not a local path", server_path)))?
+            .display()
+            .to_string()),
+    }
+}
+
+/// Lowercase markers of a warning line in serve-d's output
+const WARNING_MARKERS: &[&str] = &["warning", "warn:", "deprecat"];
+
+/// What serve-d's output says about whether it can run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartupOutput {
+    Clean,
+    /// Warnings only, serve-d may well work regardless, even when it exited
+    /// with an error status because of them
+    Warnings(Vec<String>),
+    /// serve-d failed, with its output
+    Fatal(String),
+}
+
+impl StartupOutput {
+    /// Classify the output of a serve-d run by its exit status and the lines
+    /// it printed
+    ///
+    /// A failed run only counts as fatal if some line isn't a warning or
+    /// there's no output to tell.
+    pub fn classify(success: bool, output: &str) -> Self {
+        let lines: Vec<&str> = output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let (warnings, others): (Vec<&str>, Vec<&str>) = lines.iter().partition(|line| {
+            let line = line.to_ascii_lowercase();
+            WARNING_MARKERS.iter().any(|marker| line.contains(marker))
+        });
+
+        if !success && (warnings.is_empty() || !others.is_empty()) {
+            StartupOutput::Fatal(lines.join("\n"))
+        } else if warnings.is_empty() {
+            StartupOutput::Clean
+        } else {
+            StartupOutput::Warnings(warnings.into_iter().map(str::to_string).collect())
+        }
+    }
+
+    /// Fail on fatal output, and on warnings as well if `strict`, logging
+    /// the warnings that are let through
+    pub fn check(self, host: &dyn Host, strict: bool) -> Result<(), String> {
+        match self {
+            StartupOutput::Clean => Ok(()),
+            StartupOutput::Warnings(warnings) if strict => Err(warnings.join("\n")),
+            StartupOutput::Warnings(warnings) => {
+                for warning in warnings {
+                    host.log(&format!("serve-d warning: {}", warning));
+                }
+                Ok(())
+            }
+            StartupOutput::Fatal(output) => Err(output),
+        }
+    }
+}
+
+/// Check that serve-d runs by asking it for its version
+///
+/// The plugin API doesn't report whether the language server came up, nor
+/// can it wait on it with a timeout, so this is the closest probe available.
+/// Warnings only fail the check if `strict`.
+pub fn health_check(host: &dyn Host, server_path: &Url, strict: bool) -> Result<()> {
+    let output = host.execute(&program(server_path)?, &["--version"])?;
+    StartupOutput::classify(output.success, &String::from_utf8_lossy(&output.stderr))
+        .check(host, strict)
+        .map_err(|log| Error::msg(format!("serve-d --version failed: {}", log)))
+}
+
+/// Run serve-d with the arguments it gets started with, but without a
+/// client on stdin, to catch it exiting right away with an error
+///
+/// The plugin API can't watch the started server, so this is the closest
+/// way to see its exit and output. A serve-d that comes up exits cleanly
+/// once stdin is closed. Warnings only fail the check if `strict`.
+pub fn early_exit_check(
+    host: &dyn Host,
+    server_path: &Url,
+    server_args: &[String],
+    strict: bool,
+) -> Result<()> {
+    let args: Vec<&str> = server_args.iter().map(String::as_str).collect();
+    let output = host.execute(&program(server_path)?, &args)?;
+
+    let mut log = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if log.is_empty() {
+        log = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    }
+    StartupOutput::classify(output.success, &log)
+        .check(host, strict)
+        .map_err(|log| {
+            Error::msg(if log.is_empty() {
+                "serve-d exited right after starting without any output".to_string()
+            } else {
+                format!("serve-d exited right after starting: {}", log)
+            })
+        })
+}
+
+/// Ask the serve-d `binary` which version it is, since `version.txt` only
+/// records what the plugin last installed
+pub fn probe_installed_version(host: &dyn Host, binary: &Path) -> Result<Version> {
+    let output = host.execute(&binary.display().to_string(), &["--version"])?;
+    if !output.success {
+        return Err(Error::msg(format!(
+            "serve-d --version failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    version::parse_version_output(&stdout).ok_or_else(|| {
+        Error::msg(format!(
+            "No version in the serve-d --version output: {}",
+            stdout.trim()
+        ))
+    })
+}
+
+/// Run the serve-d command through `env` to set environment variables,
+/// which the plugin API can't pass to serve-d
+pub fn env_command(
+    server_path: &Url,
+    server_args: &[String],
+    env: &[(String, String)],
+) -> Result<(Url, Vec<String>)> {
+    let program = program(server_path)?;
+
+    let mut args: Vec<String> = env
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    args.push(program);
+    args.extend(server_args.iter().cloned());
+    Ok((Url::parse("urn:env")?, args))
+}
+
+/// serve-d running on another host, e.g. in a container, listening on TCP
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RemoteServer {
+    pub host: String,
+    pub port: u16,
+}
+
+impl RemoteServer {
+    /// Command connecting to the remote serve-d through `nc`, since the
+    /// plugin API only starts servers as processes talking over stdio
+    pub fn command(&self) -> Result<(Url, Vec<String>)> {
+        if self.host.trim().is_empty() || self.port == 0 {
+            return Err(Error::msg("remote needs a host and a non-zero port"));
+        }
+        Ok((
+            Url::parse("urn:nc")?,
+            vec![self.host.trim().to_string(), self.port.to_string()],
+        ))
+    }
+}
+
+/// Whether startup waits on the update check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupPolicy {
+    /// Check for updates before starting serve-d
+    Blocking,
+    /// Start an installed serve-d first, then check for updates
+    ///
+    /// The check still runs before initialize returns, it only no longer
+    /// holds up serve-d. An update installed by it applies on the next start.
+    StartInstalledFirst,
+}
+
+impl StartupPolicy {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "blocking" => Ok(StartupPolicy::Blocking),
+            "startInstalledFirst" => Ok(StartupPolicy::StartInstalledFirst),
+            _ => Err(Error::msg(format!("Unknown startup policy {}", name))),
+        }
+    }
+
+    /// Whether starting serve-d has to wait for the update check
+    pub fn blocks_on_update(&self, installed: bool) -> bool {
+        match self {
+            StartupPolicy::Blocking => true,
+            StartupPolicy::StartInstalledFirst => !installed,
+        }
+    }
+}
+
+/// Gate for `lsp.updateCheckDelaySecs`
+///
+/// The plugin runs on the host's single thread and has no timers to come
+/// back on, so delaying the check could only sleep inside the initialize
+/// handler, holding up every other message to the plugin for the delay.
+/// Setting it fails instead of blocking; 0 is the same as leaving it unset.
+pub fn check_update_check_delay(delay_secs: Option<u64>) -> Result<()> {
+    match delay_secs {
+        Some(secs) if secs > 0 => Err(Error::msg(
+            "updateCheckDelaySecs is not supported, the plugin can't check for updates later",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Recursively merge `overlay` into `base`
+///
+/// Objects are merged key by key; any other overlay value replaces the base.
+pub fn deep_merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Object(mut base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let merged = match base.remove(&key) {
+                    Some(base_value) => deep_merge(base_value, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+/// Companion tools `lsp.coreOnly` or `lsp.useBundledDcd = false` leave out
+/// of the install, found on PATH instead unless their path is configured
+const CORE_ONLY_TOOLS: &[(&str, &str)] = &[
+    ("dcdServerPath", "dcd-server"),
+    ("dcdClientPath", "dcd-client"),
+];
+
+/// serve-d settings derived from the plugin's own options
+///
+/// External `dcd-server`/`dcd-client` binaries are checked to exist, since
+/// serve-d only reports a broken path once completion is first used.
+/// `useBundledDcd` picks between the DCD shipped with serve-d and an
+/// external one, and can't be forced on along with external paths.
+pub fn managed_settings(
+    dcd_server_path: Option<&str>,
+    dcd_client_path: Option<&str>,
+    core_only: bool,
+    use_bundled_dcd: Option<bool>,
+) -> Result<Value> {
+    // Plugin options forwarded into serve-d's `d` settings
+    let forwarded = [
+        ("dcdServerPath", dcd_server_path),
+        ("dcdClientPath", dcd_client_path),
+    ];
+
+    let mut d = Map::new();
+    for (option, path) in forwarded {
+        if let Some(path) = path {
+            if !Path::new(path).exists() {
+                return Err(Error::msg(format!("{} {} does not exist", option, path)));
+            }
+            d.insert(option.to_string(), Value::String(path.to_string()));
+        }
+    }
+
+    if use_bundled_dcd == Some(true) {
+        if let Some((option, _)) = forwarded.iter().find(|(_, path)| path.is_some()) {
+            return Err(Error::msg(format!(
+                "useBundledDcd is set, which conflicts with the external DCD at {}",
+                option
+            )));
+        }
+        if core_only {
+            return Err(Error::msg(
+                "useBundledDcd is set, but coreOnly leaves the bundled DCD out",
+            ));
+        }
+    }
+    if core_only || use_bundled_dcd == Some(false) {
+        for (option, program) in CORE_ONLY_TOOLS {
+            d.entry(option.to_string())
+                .or_insert_with(|| Value::from(*program));
+        }
+    }
+
+    let mut settings = Map::new();
+    if !d.is_empty() {
+        settings.insert("d".to_string(), Value::Object(d));
+    }
+    Ok(Value::Object(settings))
+}
+
+/// serve-d settings for editing standalone scripts with a dub single-file
+/// recipe, the `/+ dub.sdl: ... +/` header
+///
+/// serve-d resolves the dependencies of such a script through dub, which
+/// `d.neverUseDub` would turn off for every file, so it's forced back on.
+pub fn dub_single_file_settings(enabled: bool) -> Value {
+    let mut settings = Map::new();
+    if enabled {
+        let mut d = Map::new();
+        d.insert("neverUseDub".to_string(), Value::Bool(false));
+        settings.insert("d".to_string(), Value::Object(d));
+    }
+    Value::Object(settings)
+}
+
+/// Top-level initialization option keys holding the plugin's own config
+const PLUGIN_OPTION_KEYS: &[&str] = &["lsp"];
+
+/// Strip the plugin's own config from the initialization options, keeping
+/// serve-d's settings
+///
+/// Returns `None` when nothing is left to pass.
+pub fn sanitize_options_for_server(opts: Option<Value>) -> Option<Value> {
+    match opts? {
+        Value::Object(mut opts) => {
+            for key in PLUGIN_OPTION_KEYS {
+                opts.remove(*key);
+            }
+            if opts.is_empty() {
+                None
+            } else {
+                Some(Value::Object(opts))
+            }
+        }
+        opts => Some(opts),
+    }
+}
+
+/// A numeric serve-d setting exposed under `lsp.limits`
+struct Limit {
+    option: &'static str,
+    section: &'static str,
+    setting: &'static str,
+    min: u64,
+    max: u64,
+}
+
+const LIMITS: &[Limit] = &[
+    Limit {
+        option: "manyProjectsThreshold",
+        section: "d",
+        setting: "manyProjectsThreshold",
+        min: 1,
+        max: 1000,
+    },
+    Limit {
+        option: "softMaxLineLength",
+        section: "dfmt",
+        setting: "softMaxLineLength",
+        min: 1,
+        max: 1000,
+    },
+    Limit {
+        option: "maxLineLength",
+        section: "dfmt",
+        setting: "maxLineLength",
+        min: 1,
+        max: 1000,
+    },
+];
+
+/// serve-d settings from the `lsp.limits` table, checked against the range
+/// each setting accepts
+pub fn limit_settings(limits: &BTreeMap<String, u64>) -> Result<Value> {
+    let mut settings = Map::new();
+    for limit in LIMITS {
+        let value = match limits.get(limit.option) {
+            Some(value) => *value,
+            None => continue,
+        };
+        if !(limit.min..=limit.max).contains(&value) {
+            return Err(Error::msg(format!(
+                "limits.{} must be a number from {} to {}",
+                limit.option, limit.min, limit.max
+            )));
+        }
+
+        let section = settings
+            .entry(limit.section.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Some(section) = section.as_object_mut() {
+            section.insert(limit.setting.to_string(), Value::from(value));
+        }
+    }
+
+    Ok(Value::Object(settings))
+}
+
+/// Build the initialization options passed to serve-d
+///
+/// The `managed` settings, `lsp.serverSettings` with the settings derived
+/// from the plugin's options on top, are deep-merged over the options so
+/// settings the plugin doesn't model can still reach serve-d. The plugin's
+/// own `lsp` block is stripped, serve-d doesn't understand it.
+pub fn build_server_options(options: Option<Value>, managed: Value) -> Option<Value> {
+    let options = options.unwrap_or_else(|| Value::Object(Map::new()));
+    sanitize_options_for_server(Some(deep_merge(options, managed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{MockHost, TestDir};
+
+    #[test]
+    fn program_of_a_urn_is_percent_decoded() {
+        let server_path = Url::parse("urn:serve%20d").unwrap();
+        assert_eq!(program(&server_path).unwrap(), "serve d");
+        let server_path = Url::parse("urn:serve-d").unwrap();
+        assert_eq!(program(&server_path).unwrap(), "serve-d");
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%+1"), "%zz%+1");
+        assert_eq!(percent_decode("%C3%A9"), "\u{e9}");
+    }
+
+    #[test]
+    fn deep_merge_merges_objects_key_by_key() {
+        let base = json!({ "d": { "stdlibPath": ["/usr/include/d"], "neverUseDub": true } });
+        let overlay = json!({ "d": { "neverUseDub": false }, "dfmt": { "braceStyle": "otbs" } });
+        assert_eq!(
+            deep_merge(base, overlay),
+            json!({
+                "d": { "stdlibPath": ["/usr/include/d"], "neverUseDub": false },
+                "dfmt": { "braceStyle": "otbs" },
+            })
+        );
+    }
+
+    #[test]
+    fn deep_merge_replaces_other_values() {
+        assert_eq!(
+            deep_merge(json!({ "paths": ["a", "b"] }), json!({ "paths": ["c"] })),
+            json!({ "paths": ["c"] })
+        );
+        assert_eq!(deep_merge(json!({ "d": {} }), json!(null)), json!(null));
+        assert_eq!(deep_merge(json!(1), json!({ "d": 2 })), json!({ "d": 2 }));
+    }
+
+    #[test]
+    fn server_options_strip_the_plugin_options() {
+        let options = json!({ "lsp": { "serverPath": "serve-d" }, "d": { "a": 1 } });
+        assert_eq!(
+            build_server_options(Some(options), json!({ "d": { "b": 2 } })),
+            Some(json!({ "d": { "a": 1, "b": 2 } }))
+        );
+        assert_eq!(
+            build_server_options(Some(json!({ "lsp": {} })), json!({})),
+            None
+        );
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn requires_d_by_default() {
+        assert_eq!(
+            build_server_args(None, &BTreeMap::new(), &[], None),
+            strings(&["--require", "d"])
+        );
+    }
+
+    #[test]
+    fn required_features_replace_the_default() {
+        let features = strings(&["d", "dcd"]);
+        assert_eq!(
+            build_server_args(Some(&features), &BTreeMap::new(), &[], None),
+            strings(&["--require", "d", "--require", "dcd"])
+        );
+        assert_eq!(
+            build_server_args(
+                Some(&[]),
+                &BTreeMap::new(),
+                &strings(&["--lang", "en"]),
+                None
+            ),
+            strings(&["--lang", "en"])
+        );
+    }
+
+    #[test]
+    fn external_dcd_paths_are_forwarded_to_serve_d() {
+        let dir = TestDir::new("dcd-server");
+        let dcd_server = dir.join("dcd-server");
+        std::fs::write(&dcd_server, b"").unwrap();
+        let dcd_server = dcd_server.to_str().unwrap();
+
+        assert_eq!(
+            managed_settings(Some(dcd_server), None, false, None).unwrap(),
+            json!({ "d": { "dcdServerPath": dcd_server } })
+        );
+        assert_eq!(
+            managed_settings(None, None, false, None).unwrap(),
+            json!({})
+        );
+        std::fs::remove_file(dcd_server).unwrap();
+    }
+
+    #[test]
+    fn missing_dcd_paths_are_errors() {
+        let dir = TestDir::new("missing-dcd-client");
+        let missing = dir.join("dcd-client");
+        let missing = missing.to_str().unwrap();
+        let err = managed_settings(None, Some(missing), false, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("dcdClientPath {} does not exist", missing)
+        );
+    }
+
+    #[test]
+    fn sanitizing_strips_only_the_plugin_options() {
+        let options = json!({
+            "lsp": { "serverPath": "serve-d", "autoUpdate": false },
+            "d": { "dcdServerPath": "dcd-server" },
+            "dfmt": { "braceStyle": "otbs" },
+        });
+        assert_eq!(
+            sanitize_options_for_server(Some(options)),
+            Some(json!({
+                "d": { "dcdServerPath": "dcd-server" },
+                "dfmt": { "braceStyle": "otbs" },
+            }))
+        );
+        assert_eq!(
+            sanitize_options_for_server(Some(json!({ "lsp": {} }))),
+            None
+        );
+        assert_eq!(sanitize_options_for_server(None), None);
+        // Only the top-level plugin block is the plugin's
+        assert_eq!(
+            sanitize_options_for_server(Some(json!({ "d": { "lsp": true } }))),
+            Some(json!({ "d": { "lsp": true } }))
+        );
+    }
+
+    #[test]
+    fn dflags_option_overrides_the_inherited_environment() {
+        let dflags = |value: &str| vec![("DFLAGS".to_string(), value.to_string())];
+        assert_eq!(
+            dflags_env(Some("-preview=dip1000"), None),
+            dflags("-preview=dip1000")
+        );
+        assert_eq!(
+            dflags_env(Some("-preview=dip1000"), Some("-g".to_string())),
+            dflags("-preview=dip1000")
+        );
+        // serve-d inherits DFLAGS from Lapce already
+        assert!(dflags_env(None, Some("-g".to_string())).is_empty());
+        assert!(dflags_env(Some("-g"), Some("-g".to_string())).is_empty());
+    }
+
+    #[test]
+    fn environment_is_set_through_env() {
+        let server_path = Url::parse("file:///opt/serve-d/serve-d").unwrap();
+        let env = vec![("DFLAGS".to_string(), "-preview=dip1000 -g".to_string())];
+        assert_eq!(
+            env_command(&server_path, &strings(&["--require", "D"]), &env).unwrap(),
+            (
+                Url::parse("urn:env").unwrap(),
+                strings(&[
+                    "DFLAGS=-preview=dip1000 -g",
+                    "/opt/serve-d/serve-d",
+                    "--require",
+                    "D"
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn os_args_of_the_host_os_go_between_defaults_and_user_args() {
+        let os_args: BTreeMap<String, Vec<String>> = [
+            ("linux".to_string(), strings(&["--linux"])),
+            ("macos".to_string(), strings(&["--macos"])),
+            ("windows".to_string(), strings(&["--windows", "--crlf"])),
+        ]
+        .into();
+        let user_args = strings(&["--lang", "en"]);
+        let args = |os| build_server_args(Some(&[]), &os_args, &user_args, os);
+
+        assert_eq!(args(Some("linux")), strings(&["--linux", "--lang", "en"]));
+        assert_eq!(args(Some("macos")), strings(&["--macos", "--lang", "en"]));
+        assert_eq!(
+            args(Some("windows")),
+            strings(&["--windows", "--crlf", "--lang", "en"])
+        );
+        assert_eq!(args(Some("freebsd")), user_args);
+        assert_eq!(args(None), user_args);
+        assert_eq!(
+            build_server_args(None, &os_args, &[], Some("linux")),
+            strings(&["--require", "d", "--linux"])
+        );
+    }
+
+    #[test]
+    fn only_blocks_on_the_update_check_when_needed() {
+        assert_eq!(
+            StartupPolicy::parse("blocking").unwrap(),
+            StartupPolicy::Blocking
+        );
+        assert_eq!(
+            StartupPolicy::parse("startInstalledFirst").unwrap(),
+            StartupPolicy::StartInstalledFirst
+        );
+        assert!(StartupPolicy::parse("preferInstalled").is_err());
+
+        assert!(StartupPolicy::Blocking.blocks_on_update(true));
+        assert!(StartupPolicy::Blocking.blocks_on_update(false));
+        assert!(!StartupPolicy::StartInstalledFirst.blocks_on_update(true));
+        // Nothing to start before the first install
+        assert!(StartupPolicy::StartInstalledFirst.blocks_on_update(false));
+    }
+
+    #[test]
+    fn maps_limits_into_their_sections() {
+        let limits: BTreeMap<String, u64> = [
+            ("manyProjectsThreshold".to_string(), 6),
+            ("softMaxLineLength".to_string(), 80),
+            ("maxLineLength".to_string(), 120),
+        ]
+        .into();
+        assert_eq!(
+            limit_settings(&limits).unwrap(),
+            json!({
+                "d": { "manyProjectsThreshold": 6 },
+                "dfmt": { "softMaxLineLength": 80, "maxLineLength": 120 },
+            })
+        );
+        assert_eq!(limit_settings(&BTreeMap::new()).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn rejects_limits_out_of_range() {
+        for value in [0, 1001] {
+            let limits: BTreeMap<String, u64> = [("maxLineLength".to_string(), value)].into();
+            assert_eq!(
+                limit_settings(&limits).unwrap_err().to_string(),
+                "limits.maxLineLength must be a number from 1 to 1000"
+            );
+        }
+        let limits: BTreeMap<String, u64> = [("maxLineLength".to_string(), 1000)].into();
+        assert!(limit_settings(&limits).is_ok());
+    }
+
+    #[test]
+    fn rejects_delaying_the_update_check() {
+        assert!(check_update_check_delay(None).is_ok());
+        assert!(check_update_check_delay(Some(0)).is_ok());
+        assert_eq!(
+            check_update_check_delay(Some(30)).unwrap_err().to_string(),
+            "updateCheckDelaySecs is not supported, the plugin can't check for updates later"
+        );
+    }
+
+    #[test]
+    fn probes_the_installed_version() {
+        let binary = Path::new("/opt/serve-d/serve-d");
+        let host = MockHost {
+            programs: [(
+                "/opt/serve-d/serve-d --version".to_string(),
+                "serve-d standalone v0.7.4\n".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            probe_installed_version(&host, binary).unwrap(),
+            Version::new(0, 7, 4)
+        );
+
+        let host = MockHost {
+            programs: [(
+                "/opt/serve-d/serve-d --version".to_string(),
+                "serve-d nightly\n".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let err = probe_installed_version(&host, binary).unwrap_err();
+        assert!(err.to_string().contains("serve-d nightly"), "{}", err);
+
+        let host = MockHost {
+            failing: ["/opt/serve-d/serve-d --version".to_string()].into(),
+            ..Default::default()
+        };
+        assert!(probe_installed_version(&host, binary).is_err());
+    }
+
+    #[test]
+    fn detects_serve_d_exiting_right_after_starting() {
+        let server_path = Url::parse("urn:serve-d").unwrap();
+        let args = ["--loglevel".to_string(), "info".to_string()];
+
+        let host = MockHost {
+            programs: [("serve-d --loglevel info".to_string(), String::new())].into(),
+            ..Default::default()
+        };
+        early_exit_check(&host, &server_path, &args, false).unwrap();
+
+        let host = MockHost {
+            failing: ["serve-d --loglevel info".to_string()].into(),
+            ..Default::default()
+        };
+        let err = early_exit_check(&host, &server_path, &args, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "serve-d exited right after starting: serve-d failed"
+        );
+    }
+
+    #[test]
+    fn core_only_points_serve_d_at_external_dcd() {
+        let external =
+            json!({ "d": { "dcdServerPath": "dcd-server", "dcdClientPath": "dcd-client" } });
+        assert_eq!(managed_settings(None, None, true, None).unwrap(), external);
+        assert_eq!(
+            managed_settings(None, None, false, Some(false)).unwrap(),
+            external
+        );
+
+        let dir = TestDir::new("core-only-dcd-client");
+        let dcd_client = dir.join("dcd-client");
+        std::fs::write(&dcd_client, b"").unwrap();
+        let dcd_client = dcd_client.to_str().unwrap();
+        assert_eq!(
+            managed_settings(None, Some(dcd_client), true, None).unwrap(),
+            json!({ "d": { "dcdServerPath": "dcd-server", "dcdClientPath": dcd_client } })
+        );
+        std::fs::remove_file(dcd_client).unwrap();
+
+        let err = managed_settings(None, None, true, Some(true)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "useBundledDcd is set, but coreOnly leaves the bundled DCD out"
+        );
+    }
+
+    #[test]
+    fn dub_single_file_turns_dub_back_on() {
+        assert_eq!(
+            dub_single_file_settings(true),
+            json!({ "d": { "neverUseDub": false } })
+        );
+        assert_eq!(dub_single_file_settings(false), json!({}));
+
+        let user = json!({ "d": { "neverUseDub": true, "dubPath": "dub" } });
+        assert_eq!(
+            deep_merge(user.clone(), dub_single_file_settings(true)),
+            json!({ "d": { "neverUseDub": false, "dubPath": "dub" } })
+        );
+        assert_eq!(
+            deep_merge(user.clone(), dub_single_file_settings(false)),
+            user
+        );
+    }
+
+    #[test]
+    fn classifies_startup_output() {
+        assert_eq!(StartupOutput::classify(true, ""), StartupOutput::Clean);
+        assert_eq!(
+            StartupOutput::classify(true, "serve-d v0.7.6\n"),
+            StartupOutput::Clean
+        );
+        assert_eq!(
+            StartupOutput::classify(true, "Warning: dub not found\n\n"),
+            StartupOutput::Warnings(vec!["Warning: dub not found".to_string()])
+        );
+        // Warnings alone don't make a failed run fatal
+        assert_eq!(
+            StartupOutput::classify(false, "  WARN: option x is deprecated  "),
+            StartupOutput::Warnings(vec!["WARN: option x is deprecated".to_string()])
+        );
+        assert_eq!(
+            StartupOutput::classify(false, "Warning: dub not found\nSegmentation fault\n"),
+            StartupOutput::Fatal("Warning: dub not found\nSegmentation fault".to_string())
+        );
+        assert_eq!(
+            StartupOutput::classify(false, ""),
+            StartupOutput::Fatal(String::new())
+        );
+    }
+
+    #[test]
+    fn strict_startup_fails_on_warnings() {
+        let host = MockHost::default();
+        let warnings = || StartupOutput::Warnings(vec!["Warning: dub not found".to_string()]);
+
+        assert_eq!(warnings().check(&host, false), Ok(()));
+        assert_eq!(
+            *host.logs.borrow(),
+            vec!["serve-d warning: Warning: dub not found"]
+        );
+        assert_eq!(
+            warnings().check(&host, true),
+            Err("Warning: dub not found".to_string())
+        );
+        assert_eq!(StartupOutput::Clean.check(&host, true), Ok(()));
+        assert_eq!(
+            StartupOutput::Fatal("crashed".to_string()).check(&host, false),
+            Err("crashed".to_string())
+        );
+    }
+
+    #[test]
+    fn health_checks_fail_when_serve_d_does() {
+        let server_path = Url::parse("urn:serve-d").unwrap();
+        let host = MockHost {
+            programs: [(
+                "serve-d --version".to_string(),
+                "serve-d v0.7.6".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        health_check(&host, &server_path, true).unwrap();
+
+        let host = MockHost {
+            failing: ["serve-d --version".to_string()].into(),
+            ..Default::default()
+        };
+        let err = health_check(&host, &server_path, false).unwrap_err();
+        assert_eq!(err.to_string(), "serve-d --version failed: serve-d failed");
+    }
+
+    #[test]
+    fn bundled_dcd_is_chosen_consistently_with_external_paths() {
+        // Bundled, serve-d starts the DCD it ships with
+        assert_eq!(
+            managed_settings(None, None, false, Some(true)).unwrap(),
+            json!({})
+        );
+        assert_eq!(
+            managed_settings(None, None, false, Some(false)).unwrap(),
+            json!({ "d": { "dcdServerPath": "dcd-server", "dcdClientPath": "dcd-client" } })
+        );
+
+        let dir = TestDir::new("bundled-dcd-server");
+        let dcd_server = dir.join("dcd-server");
+        std::fs::write(&dcd_server, b"").unwrap();
+        let dcd_server = dcd_server.to_str().unwrap();
+        assert_eq!(
+            managed_settings(Some(dcd_server), None, false, Some(false)).unwrap(),
+            json!({ "d": { "dcdServerPath": dcd_server, "dcdClientPath": "dcd-client" } })
+        );
+        let err = managed_settings(Some(dcd_server), None, false, Some(true)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "useBundledDcd is set, which conflicts with the external DCD at dcdServerPath"
+        );
+        std::fs::remove_file(dcd_server).unwrap();
+    }
+
+    #[test]
+    fn connects_to_a_remote_serve_d_through_nc() {
+        let remote: RemoteServer =
+            serde_json::from_value(json!({ "host": " devcontainer ", "port": 8765 })).unwrap();
+        assert_eq!(
+            remote.command().unwrap(),
+            (
+                Url::parse("urn:nc").unwrap(),
+                vec!["devcontainer".to_string(), "8765".to_string()]
+            )
+        );
+
+        for remote in [
+            json!({ "host": "", "port": 8765 }),
+            json!({ "host": "devcontainer", "port": 0 }),
+        ] {
+            let remote: RemoteServer = serde_json::from_value(remote).unwrap();
+            assert!(remote.command().is_err());
+        }
+        assert!(serde_json::from_value::<RemoteServer>(json!({ "host": "devcontainer" })).is_err());
+        assert!(serde_json::from_value::<RemoteServer>(
+            json!({ "host": "devcontainer", "port": 70000 })
+        )
+        .is_err());
+    }
+}
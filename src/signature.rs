@@ -0,0 +1,177 @@
+use std::{fs, path::Path};
+
+use anyhow::{Error, Result};
+
+use crate::{host::Host, release::GHReleaseAsset};
+
+/// Detached signature published next to the release archive `archive_name`
+pub fn signature_asset<'a>(
+    assets: &'a [GHReleaseAsset],
+    archive_name: &str,
+) -> Option<&'a GHReleaseAsset> {
+    let name = format!("{}.asc", archive_name);
+    assets.iter().find(|candidate| candidate.name == name)
+}
+
+/// Whether an asset gets its signature checked, failing closed when a
+/// signing key is configured but the release has no signature for it
+pub fn needs_verification(
+    signing_key: Option<&Path>,
+    asset: &GHReleaseAsset,
+    signature: Option<&GHReleaseAsset>,
+) -> Result<bool> {
+    match (signing_key, signature) {
+        (None, _) => Ok(false),
+        (Some(_), Some(_)) => Ok(true),
+        (Some(_), None) => Err(Error::msg(format!(
+            "{} has no .asc signature but signingKey is set",
+            asset.name
+        ))),
+    }
+}
+
+/// Check the detached `signature` of `data` against the public key in
+/// `signing_key` with gpg
+///
+/// The plugin can't verify OpenPGP signatures itself, so the files are
+/// staged in `temp_dir` for gpg with a keyring holding only that key.
+pub fn verify(
+    host: &dyn Host,
+    signing_key: &Path,
+    data: &[u8],
+    signature: &[u8],
+    temp_dir: &Path,
+) -> Result<()> {
+    let dir = temp_dir.join("signature");
+    fs::create_dir_all(&dir)?;
+    let keyring = dir.join("signing.kbx");
+    let data_file = dir.join("asset");
+    let signature_file = dir.join("asset.asc");
+    let _ = fs::remove_file(&keyring);
+    fs::write(&data_file, data)?;
+    fs::write(&signature_file, signature)?;
+
+    let keyring_arg = keyring.display().to_string();
+    let gpg = |args: &[&str]| -> Result<()> {
+        let mut all = vec!["--batch", "--no-default-keyring", "--keyring", &keyring_arg];
+        all.extend_from_slice(args);
+        let output = host.execute("gpg", &all)?;
+        if output.success {
+            Ok(())
+        } else {
+            Err(Error::msg(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    };
+
+    let result = gpg(&["--import", &signing_key.display().to_string()])
+        .map_err(|err| Error::msg(format!("Importing signingKey failed: {}", err)))
+        .and_then(|()| {
+            gpg(&[
+                "--trust-model",
+                "always",
+                "--verify",
+                &signature_file.display().to_string(),
+                &data_file.display().to_string(),
+            ])
+            .map_err(|err| Error::msg(format!("Signature verification failed: {}", err)))
+        });
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockHost, TestDir};
+
+    fn asset(name: &str) -> GHReleaseAsset {
+        GHReleaseAsset {
+            id: 0,
+            name: name.to_string(),
+            size: 0,
+            download_count: 0,
+            browser_download_url: format!("https://example.com/{}", name),
+            created_at: String::new(),
+        }
+    }
+
+    const ARCHIVE: &str = "serve-d_0.8.0-x86_64-linux.tar.xz";
+
+    #[test]
+    fn verifies_signed_assets_only_with_a_signing_key() {
+        let assets = [asset(ARCHIVE), asset(&format!("{}.asc", ARCHIVE))];
+        let signature = signature_asset(&assets, ARCHIVE);
+        assert_eq!(
+            signature.map(|asset| asset.name.as_str()),
+            Some("serve-d_0.8.0-x86_64-linux.tar.xz.asc")
+        );
+        assert!(signature_asset(&assets, "serve-d_0.8.0-x86_64-windows.zip").is_none());
+
+        let key = Path::new("/home/user/serve-d.pub");
+        assert!(!needs_verification(None, &assets[0], signature).unwrap());
+        assert!(!needs_verification(None, &assets[0], None).unwrap());
+        assert!(needs_verification(Some(key), &assets[0], signature).unwrap());
+        let err = needs_verification(Some(key), &assets[0], None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "serve-d_0.8.0-x86_64-linux.tar.xz has no .asc signature but signingKey is set"
+        );
+    }
+
+    /// gpg command lines verifying a signature staged in `temp_dir`
+    fn gpg_commands(temp_dir: &Path, key: &Path) -> [String; 2] {
+        let dir = temp_dir.join("signature");
+        let gpg = format!(
+            "gpg --batch --no-default-keyring --keyring {}",
+            dir.join("signing.kbx").display()
+        );
+        [
+            format!("{} --import {}", gpg, key.display()),
+            format!(
+                "{} --trust-model always --verify {} {}",
+                gpg,
+                dir.join("asset.asc").display(),
+                dir.join("asset").display()
+            ),
+        ]
+    }
+
+    #[test]
+    fn checks_the_signature_with_gpg() {
+        let temp_dir = TestDir::new("signature");
+        let key = temp_dir.join("serve-d.pub");
+        let [import, verify_command] = gpg_commands(&temp_dir, &key);
+        let data = b"serve-d archive";
+        let signature = b"-----BEGIN PGP SIGNATURE-----\n\n-----END PGP SIGNATURE-----\n";
+
+        let host = MockHost {
+            programs: [
+                (import.clone(), String::new()),
+                (verify_command.clone(), String::new()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        verify(&host, &key, data, signature, &temp_dir).unwrap();
+        // The staged files are gone again
+        assert!(!temp_dir.join("signature").exists());
+
+        let host = MockHost {
+            programs: [(import.clone(), String::new())].into(),
+            failing: [verify_command].into(),
+            ..Default::default()
+        };
+        let err = verify(&host, &key, data, signature, &temp_dir).unwrap_err();
+        assert_eq!(err.to_string(), "Signature verification failed: gpg failed");
+
+        let host = MockHost {
+            failing: [import].into(),
+            ..Default::default()
+        };
+        let err = verify(&host, &key, data, signature, &temp_dir).unwrap_err();
+        assert_eq!(err.to_string(), "Importing signingKey failed: gpg failed");
+        assert!(!temp_dir.join("signature").exists());
+    }
+}
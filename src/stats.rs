@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::fs_retry;
+
+/// Local record of the updates the plugin performed
+///
+/// Never sent anywhere, it only helps to debug repeated downloads.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStats {
+    pub updates: u64,
+    /// Unix timestamp of the last update, in seconds
+    pub last_update: Option<u64>,
+    pub last_download_bytes: u64,
+    pub total_download_bytes: u64,
+}
+
+impl UpdateStats {
+    pub const FILE_NAME: &'static str = "stats.json";
+
+    fn path(install_dir: &Path) -> PathBuf {
+        install_dir.join(Self::FILE_NAME)
+    }
+
+    /// Read the stats of an install, starting fresh if there are none yet
+    pub fn read(install_dir: &Path) -> Result<Self> {
+        let path = Self::path(install_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn write(&self, install_dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs_retry::retry(|| fs::write(Self::path(install_dir), &data))?;
+        Ok(())
+    }
+
+    pub fn record_update(&mut self, download_bytes: u64, now: u64) {
+        self.updates += 1;
+        self.last_update = Some(now);
+        self.last_download_bytes = download_bytes;
+        self.total_download_bytes += download_bytes;
+    }
+}
+
+/// Current unix timestamp in seconds
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::TestDir;
+
+    #[test]
+    fn records_updates_across_reads() {
+        let dir = TestDir::new("stats");
+
+        let mut stats = UpdateStats::read(&dir).unwrap();
+        assert_eq!(stats.updates, 0);
+        assert_eq!(stats.last_update, None);
+        stats.record_update(1000, 1_700_000_000);
+        stats.write(&dir).unwrap();
+
+        let mut stats = UpdateStats::read(&dir).unwrap();
+        stats.record_update(500, 1_700_000_600);
+        stats.write(&dir).unwrap();
+
+        let stats = UpdateStats::read(&dir).unwrap();
+        assert_eq!(stats.updates, 2);
+        assert_eq!(stats.last_update, Some(1_700_000_600));
+        assert_eq!(stats.last_download_bytes, 500);
+        assert_eq!(stats.total_download_bytes, 1500);
+    }
+}
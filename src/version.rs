@@ -0,0 +1,693 @@
+use anyhow::{Error, Result};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+/// Parse a release tag or version file entry, which may carry a `v` prefix
+pub fn parse_tag(tag: &str) -> Result<Version> {
+    let tag = tag.trim();
+    Ok(Version::parse(tag.strip_prefix('v').unwrap_or(tag))?)
+}
+
+/// Find the version in serve-d's `--version` output
+///
+/// Releases print it differently, e.g. `serve-d standalone v0.7.4`,
+/// `v0.8.0-beta.15 (built with DMD)` or `serve-d 0.7.4+commit.12.gabc1234`,
+/// so the first word that parses as a version is taken.
+pub fn parse_version_output(output: &str) -> Option<Version> {
+    output
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| matches!(c, '(' | ')' | ',' | ':' | ';')))
+        .find_map(|word| parse_tag(word).ok())
+}
+
+/// Whether two release tags differ, for tags that aren't semver versions
+/// and can't be ordered
+pub fn tag_changed(installed: &str, latest: &str) -> bool {
+    let normalize = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix('v').unwrap_or(tag).to_string()
+    };
+    normalize(installed) != normalize(latest)
+}
+
+/// Whether a release tag matches a `skipVersions`/`allowedVersions` entry:
+/// an exact version like `0.7.5`, a requirement like `>=0.7, <0.8`, or a
+/// tag that isn't semver
+fn matches_entry(entry: &str, tag: &str) -> bool {
+    let version = parse_tag(tag).ok();
+    if let Ok(exact) = parse_tag(entry) {
+        return version == Some(exact);
+    }
+    match (VersionReq::parse(entry.trim()), version) {
+        (Ok(req), Some(version)) => req.matches(&version),
+        _ => !tag_changed(entry, tag),
+    }
+}
+
+/// Releases the user excluded because they're known to be broken, or
+/// restricted to the ones known to work
+#[derive(Default)]
+pub struct VersionFilter {
+    pub skip: Vec<String>,
+    /// Every release is allowed if empty
+    pub allowed: Vec<String>,
+}
+
+impl VersionFilter {
+    pub fn accepts(&self, tag: &str) -> bool {
+        !self.skip.iter().any(|entry| matches_entry(entry, tag))
+            && (self.allowed.is_empty()
+                || self.allowed.iter().any(|entry| matches_entry(entry, tag)))
+    }
+}
+
+/// Install subdirectory of a serve-d major version
+pub fn major_dir(major: u64) -> String {
+    format!("v{}", major)
+}
+
+/// Which serve-d releases to follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Prerelease,
+}
+
+impl Channel {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "stable" => Ok(Channel::Stable),
+            "prerelease" => Ok(Channel::Prerelease),
+            _ => Err(Error::msg(format!("Unknown release channel {}", name))),
+        }
+    }
+}
+
+/// How the newest release of the prerelease channel is picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrereleaseOrder {
+    /// Highest version by semver precedence, `0.8.0-beta.2` over `0.8.0-beta.1`
+    Semver,
+    /// Most recently published
+    Date,
+}
+
+impl PrereleaseOrder {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "semver" => Ok(PrereleaseOrder::Semver),
+            "date" => Ok(PrereleaseOrder::Date),
+            _ => Err(Error::msg(format!("Unknown prerelease order {}", name))),
+        }
+    }
+}
+
+/// What happens when the installed serve-d is newer than the latest
+/// release, e.g. after a manual install or a yanked release
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewerLocal {
+    /// Leave the newer version installed
+    Keep,
+    /// Go back to the latest published release
+    Downgrade,
+}
+
+impl NewerLocal {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "keep" => Ok(NewerLocal::Keep),
+            "downgrade" => Ok(NewerLocal::Downgrade),
+            _ => Err(Error::msg(format!("Unknown onNewerLocal policy {}", name))),
+        }
+    }
+}
+
+/// User preferences deciding when an installed serve-d gets replaced
+pub struct UpdatePolicy {
+    /// Update whenever a newer release is available
+    pub auto_update: bool,
+    /// Oldest acceptable installed version, enforced even without auto update
+    pub min_version: Option<Version>,
+    pub channel: Channel,
+    /// Replace an installed pre-release once its stable release is out
+    pub replace_prerelease: bool,
+    pub on_newer_local: NewerLocal,
+    /// Whether `allowedVersions` pins the releases to install, rather than
+    /// following the channel
+    pub pinned: bool,
+    /// Go back to an older release than installed, defaulting to on for
+    /// pins and off when following the channel
+    pub allow_downgrade: Option<bool>,
+    /// Internal testing aid overriding the version comparison
+    pub force_update: Option<bool>,
+}
+
+impl UpdatePolicy {
+    /// Whether an installed serve-d newer than the release to install goes
+    /// back to it
+    ///
+    /// A pin only downgrades as `allowDowngrade` says, since the pin names
+    /// the version wanted. Following the channel, `onNewerLocal` can ask for
+    /// it as well.
+    pub fn downgrades(&self) -> bool {
+        if self.pinned {
+            self.allow_downgrade.unwrap_or(true)
+        } else {
+            self.allow_downgrade.unwrap_or(false) || self.on_newer_local == NewerLocal::Downgrade
+        }
+    }
+}
+
+/// Whether `a` and `b` are the same release, ignoring pre-release tags
+fn same_release(a: &Version, b: &Version) -> bool {
+    (a.major, a.minor, a.patch) == (b.major, b.minor, b.patch)
+}
+
+/// Why serve-d was or wasn't updated, reported in the logs and by
+/// `dlang/exportState`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UpdateReason {
+    /// Nothing was installed yet
+    NotInstalled,
+    /// The install is there but its binary isn't
+    BinaryMissing,
+    /// The binary doesn't match its recorded checksum
+    Corrupted,
+    /// `internalForceUpdate` is set to true
+    Forced,
+    /// `internalForceUpdate` is set to false
+    ForcedOff,
+    /// The installed version is below `minVersion`
+    BelowMinimum,
+    /// A newer release is available
+    RemoteNewer,
+    /// The release tag changed, for tags that can't be ordered
+    TagChanged,
+    /// The stable release of the installed pre-release is out
+    PrereleaseReplaced,
+    /// The channel went back to stable from a pre-release
+    SwitchedToStable,
+    /// The installed version is newer and gets downgraded, see
+    /// [`UpdatePolicy::downgrades`]
+    Downgrade,
+    /// The installed version is the latest release
+    AlreadyLatest,
+    /// The installed version is newer and is kept
+    NewerLocal,
+    /// A newer release is available but `autoUpdate` is off
+    Pinned,
+    /// The latest release is a pre-release and the channel is stable
+    StableSkipsPrerelease,
+    /// The installed pre-release stays, `replacePrerelease` is off
+    PrereleaseKept,
+    /// A running serve-d holds the binary, the update waits for a restart
+    BinaryLocked,
+    /// The release failed to start before and got rolled back
+    RolledBack,
+    /// Another workspace sharing the install already installed it
+    InstalledElsewhere,
+    /// Another workspace sharing the install is still installing, waiting
+    /// for it timed out
+    InstallInProgress,
+}
+
+impl UpdateReason {
+    /// Whether the reason makes the plugin install the release
+    pub fn updates(self) -> bool {
+        matches!(
+            self,
+            UpdateReason::NotInstalled
+                | UpdateReason::BinaryMissing
+                | UpdateReason::Corrupted
+                | UpdateReason::Forced
+                | UpdateReason::BelowMinimum
+                | UpdateReason::RemoteNewer
+                | UpdateReason::TagChanged
+                | UpdateReason::PrereleaseReplaced
+                | UpdateReason::SwitchedToStable
+                | UpdateReason::Downgrade
+        )
+    }
+}
+
+/// Whether the installed serve-d should be replaced by `latest`, and why
+///
+/// Versions compare by semver precedence, so `0.8.0-beta.1` is older than
+/// `0.8.0`. Without an `installed` version there is nothing to compare, any
+/// acceptable release gets installed.
+pub fn needs_update(
+    installed: Option<&Version>,
+    latest: &Version,
+    policy: &UpdatePolicy,
+) -> Result<UpdateReason> {
+    match policy.force_update {
+        Some(true) => return Ok(UpdateReason::Forced),
+        Some(false) => return Ok(UpdateReason::ForcedOff),
+        None => {}
+    }
+
+    if let Some(min_version) = &policy.min_version {
+        let below_minimum = !matches!(installed, Some(installed) if installed >= min_version);
+        if below_minimum && latest < min_version {
+            return Err(Error::msg(format!(
+                "Latest serve-d {} is older than the required minimum {}",
+                latest, min_version
+            )));
+        }
+    }
+    let installed = match installed {
+        Some(installed) => installed,
+        None => return Ok(UpdateReason::NotInstalled),
+    };
+    if matches!(&policy.min_version, Some(min_version) if installed < min_version) {
+        return Ok(UpdateReason::BelowMinimum);
+    }
+
+    // Never move onto a pre-release when following stable
+    if policy.channel == Channel::Stable && !latest.pre.is_empty() {
+        return Ok(UpdateReason::StableSkipsPrerelease);
+    }
+
+    if installed > latest {
+        return Ok(if policy.downgrades() {
+            UpdateReason::Downgrade
+        } else {
+            UpdateReason::NewerLocal
+        });
+    }
+
+    if !installed.pre.is_empty() && latest.pre.is_empty() && same_release(installed, latest) {
+        return Ok(if !policy.auto_update {
+            UpdateReason::Pinned
+        } else if policy.replace_prerelease {
+            UpdateReason::PrereleaseReplaced
+        } else {
+            UpdateReason::PrereleaseKept
+        });
+    }
+
+    Ok(if latest == installed {
+        UpdateReason::AlreadyLatest
+    } else if policy.auto_update {
+        UpdateReason::RemoteNewer
+    } else {
+        UpdateReason::Pinned
+    })
+}
+
+/// Why tags that aren't semver versions do or don't get updated, they can
+/// only be told apart
+pub fn tag_update_reason(
+    installed: Option<&str>,
+    latest: &str,
+    policy: &UpdatePolicy,
+) -> UpdateReason {
+    match (policy.force_update, installed) {
+        (Some(true), _) => UpdateReason::Forced,
+        (Some(false), _) => UpdateReason::ForcedOff,
+        (None, Some(installed)) if !tag_changed(installed, latest) => UpdateReason::AlreadyLatest,
+        (None, _) if !policy.auto_update => UpdateReason::Pinned,
+        (None, None) => UpdateReason::NotInstalled,
+        (None, Some(_)) => UpdateReason::TagChanged,
+    }
+}
+
+/// Whether switching back from the prerelease to the stable channel
+/// requires installing the stable release, even if it's older than the
+/// installed pre-release
+pub fn switched_to_stable(
+    installed_channel: Option<Channel>,
+    installed: &Version,
+    latest: &Version,
+    policy: &UpdatePolicy,
+) -> bool {
+    policy.force_update.is_none()
+        && installed_channel == Some(Channel::Prerelease)
+        && policy.channel == Channel::Stable
+        && installed != latest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> UpdatePolicy {
+        UpdatePolicy {
+            auto_update: true,
+            min_version: None,
+            channel: Channel::Stable,
+            replace_prerelease: true,
+            on_newer_local: NewerLocal::Keep,
+            pinned: false,
+            allow_downgrade: None,
+            force_update: None,
+        }
+    }
+
+    fn reason(installed: Option<&str>, latest: &str, policy: &UpdatePolicy) -> UpdateReason {
+        let installed = installed.map(|tag| parse_tag(tag).unwrap());
+        needs_update(installed.as_ref(), &parse_tag(latest).unwrap(), policy).unwrap()
+    }
+
+    #[test]
+    fn installs_when_nothing_is_installed() {
+        assert_eq!(
+            reason(None, "v0.7.6", &policy()),
+            UpdateReason::NotInstalled
+        );
+    }
+
+    #[test]
+    fn updates_to_a_newer_release() {
+        assert_eq!(
+            reason(Some("v0.7.5"), "v0.7.6", &policy()),
+            UpdateReason::RemoteNewer
+        );
+        assert_eq!(
+            reason(Some("v0.7.6"), "v0.7.6", &policy()),
+            UpdateReason::AlreadyLatest
+        );
+        let policy = UpdatePolicy {
+            auto_update: false,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.7.5"), "v0.7.6", &policy),
+            UpdateReason::Pinned
+        );
+    }
+
+    #[test]
+    fn keeps_or_downgrades_a_newer_install() {
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.7.6", &policy()),
+            UpdateReason::NewerLocal
+        );
+        let policy = UpdatePolicy {
+            on_newer_local: NewerLocal::Downgrade,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.7.6", &policy),
+            UpdateReason::Downgrade
+        );
+    }
+
+    #[test]
+    fn pins_downgrade_unless_disallowed() {
+        let pinned = UpdatePolicy {
+            pinned: true,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.7.6", &pinned),
+            UpdateReason::Downgrade
+        );
+        let pinned = UpdatePolicy {
+            allow_downgrade: Some(false),
+            ..pinned
+        };
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.7.6", &pinned),
+            UpdateReason::NewerLocal
+        );
+    }
+
+    #[test]
+    fn enforces_the_minimum_version() {
+        let policy = UpdatePolicy {
+            auto_update: false,
+            min_version: Some(parse_tag("0.7.5").unwrap()),
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.7.4"), "v0.7.6", &policy),
+            UpdateReason::BelowMinimum
+        );
+        assert_eq!(
+            reason(Some("v0.7.5"), "v0.7.6", &policy),
+            UpdateReason::Pinned
+        );
+
+        let installed = parse_tag("v0.7.3").unwrap();
+        let latest = parse_tag("v0.7.4").unwrap();
+        assert!(needs_update(Some(&installed), &latest, &policy).is_err());
+    }
+
+    #[test]
+    fn stable_channel_skips_prereleases() {
+        assert_eq!(
+            reason(Some("v0.7.6"), "v0.8.0-beta.1", &policy()),
+            UpdateReason::StableSkipsPrerelease
+        );
+        let policy = UpdatePolicy {
+            channel: Channel::Prerelease,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.7.6"), "v0.8.0-beta.1", &policy),
+            UpdateReason::RemoteNewer
+        );
+    }
+
+    #[test]
+    fn replaces_a_prerelease_with_its_stable_release() {
+        assert_eq!(
+            reason(Some("v0.8.0-beta.3"), "v0.8.0", &policy()),
+            UpdateReason::PrereleaseReplaced
+        );
+        let policy = UpdatePolicy {
+            replace_prerelease: false,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.8.0-beta.3"), "v0.8.0", &policy),
+            UpdateReason::PrereleaseKept
+        );
+    }
+
+    #[test]
+    fn force_update_overrides_the_versions() {
+        let forced = UpdatePolicy {
+            force_update: Some(true),
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.7.6"), "v0.7.6", &forced),
+            UpdateReason::Forced
+        );
+        let forced_off = UpdatePolicy {
+            force_update: Some(false),
+            ..policy()
+        };
+        assert_eq!(reason(None, "v0.7.6", &forced_off), UpdateReason::ForcedOff);
+    }
+
+    #[test]
+    fn updates_tags_only_when_they_change() {
+        assert_eq!(
+            tag_update_reason(None, "nightly", &policy()),
+            UpdateReason::NotInstalled
+        );
+        assert_eq!(
+            tag_update_reason(Some("nightly"), "vnightly", &policy()),
+            UpdateReason::AlreadyLatest
+        );
+        assert_eq!(
+            tag_update_reason(Some("nightly-2024-01-01"), "nightly-2024-02-01", &policy()),
+            UpdateReason::TagChanged
+        );
+
+        let pinned = UpdatePolicy {
+            auto_update: false,
+            ..policy()
+        };
+        assert_eq!(
+            tag_update_reason(Some("nightly-2024-01-01"), "nightly-2024-02-01", &pinned),
+            UpdateReason::Pinned
+        );
+        assert_eq!(
+            tag_update_reason(None, "nightly", &pinned),
+            UpdateReason::Pinned
+        );
+
+        let forced = UpdatePolicy {
+            force_update: Some(true),
+            ..policy()
+        };
+        assert_eq!(
+            tag_update_reason(Some("nightly"), "nightly", &forced),
+            UpdateReason::Forced
+        );
+    }
+
+    #[test]
+    fn prereleases_sort_below_their_release_in_both_channels() {
+        assert!(parse_tag("0.8.0-beta.1").unwrap() < parse_tag("v0.8.0").unwrap());
+        for channel in [Channel::Stable, Channel::Prerelease] {
+            let policy = UpdatePolicy {
+                channel,
+                ..policy()
+            };
+            assert_eq!(
+                reason(Some("v0.8.0-beta.1"), "v0.8.0", &policy),
+                UpdateReason::PrereleaseReplaced
+            );
+        }
+
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.8.0-beta.1", &policy()),
+            UpdateReason::StableSkipsPrerelease
+        );
+        let prerelease = UpdatePolicy {
+            channel: Channel::Prerelease,
+            ..policy()
+        };
+        assert_eq!(
+            reason(Some("v0.8.0"), "v0.8.0-beta.1", &prerelease),
+            UpdateReason::NewerLocal
+        );
+    }
+
+    #[test]
+    fn switching_to_stable_replaces_a_newer_prerelease() {
+        let installed = parse_tag("v0.9.0-beta.2").unwrap();
+        let stable = parse_tag("v0.8.1").unwrap();
+        assert!(switched_to_stable(
+            Some(Channel::Prerelease),
+            &installed,
+            &stable,
+            &policy()
+        ));
+        // Without the switch, the newer pre-release is kept
+        assert_eq!(
+            reason(Some("v0.9.0-beta.2"), "v0.8.1", &policy()),
+            UpdateReason::NewerLocal
+        );
+
+        assert!(!switched_to_stable(
+            Some(Channel::Stable),
+            &installed,
+            &stable,
+            &policy()
+        ));
+        assert!(!switched_to_stable(None, &installed, &stable, &policy()));
+        assert!(!switched_to_stable(
+            Some(Channel::Prerelease),
+            &stable,
+            &stable,
+            &policy()
+        ));
+        let prerelease = UpdatePolicy {
+            channel: Channel::Prerelease,
+            ..policy()
+        };
+        assert!(!switched_to_stable(
+            Some(Channel::Prerelease),
+            &installed,
+            &stable,
+            &prerelease
+        ));
+        let forced_off = UpdatePolicy {
+            force_update: Some(false),
+            ..policy()
+        };
+        assert!(!switched_to_stable(
+            Some(Channel::Prerelease),
+            &installed,
+            &stable,
+            &forced_off
+        ));
+    }
+
+    #[test]
+    fn parses_version_output_samples() {
+        let parsed = |output: &str| parse_version_output(output).map(|version| version.to_string());
+        assert_eq!(
+            parsed("serve-d standalone v0.7.4\nBuilt with DMD v2.100.0\n").as_deref(),
+            Some("0.7.4")
+        );
+        assert_eq!(
+            parsed("v0.8.0-beta.15 (built with DMD)").as_deref(),
+            Some("0.8.0-beta.15")
+        );
+        assert_eq!(
+            parsed("serve-d 0.7.4+commit.12.gabc1234").as_deref(),
+            Some("0.7.4+commit.12.gabc1234")
+        );
+        assert_eq!(
+            parsed("serve-d (v0.8.0-beta.3), workspace-d: 3.8.0").as_deref(),
+            Some("0.8.0-beta.3")
+        );
+        assert_eq!(parsed("serve-d nightly"), None);
+        assert_eq!(parsed(""), None);
+    }
+
+    #[test]
+    fn parses_newer_local_policies() {
+        assert_eq!(NewerLocal::parse("keep").unwrap(), NewerLocal::Keep);
+        assert_eq!(
+            NewerLocal::parse("downgrade").unwrap(),
+            NewerLocal::Downgrade
+        );
+        let err = NewerLocal::parse("Downgrade").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown onNewerLocal policy Downgrade");
+    }
+
+    #[test]
+    fn filters_versions_by_exact_version_requirement_or_tag() {
+        let filter = |skip: &[&str], allowed: &[&str]| VersionFilter {
+            skip: skip.iter().map(|entry| entry.to_string()).collect(),
+            allowed: allowed.iter().map(|entry| entry.to_string()).collect(),
+        };
+
+        let all = filter(&[], &[]);
+        assert!(all.accepts("v0.7.5") && all.accepts("nightly"));
+
+        let skip = filter(&["0.7.5", "nightly"], &[]);
+        assert!(!skip.accepts("v0.7.5"));
+        assert!(skip.accepts("v0.7.6"));
+        assert!(!skip.accepts("vnightly"));
+
+        let allowed = filter(&["0.7.5"], &[">=0.7, <0.8"]);
+        assert!(allowed.accepts("v0.7.6"));
+        assert!(!allowed.accepts("v0.7.5"));
+        assert!(!allowed.accepts("v0.8.0"));
+        assert!(!allowed.accepts("nightly"));
+    }
+
+    #[test]
+    fn nothing_installed_never_compares_versions() {
+        // A v0.0.0 release is not already installed
+        assert_eq!(
+            reason(None, "v0.0.0", &policy()),
+            UpdateReason::NotInstalled
+        );
+        assert_eq!(
+            reason(Some("v0.0.0"), "v0.0.0", &policy()),
+            UpdateReason::AlreadyLatest
+        );
+        // Neither a pin nor the stable channel keep the first install out
+        let pinned = UpdatePolicy {
+            auto_update: false,
+            ..policy()
+        };
+        assert_eq!(reason(None, "v0.7.6", &pinned), UpdateReason::NotInstalled);
+        assert_eq!(
+            reason(Some("v0.7.5"), "v0.7.6", &pinned),
+            UpdateReason::Pinned
+        );
+        assert_eq!(
+            reason(None, "v0.8.0-beta.1", &policy()),
+            UpdateReason::NotInstalled
+        );
+
+        let minimum = UpdatePolicy {
+            min_version: Some(parse_tag("0.7.5").unwrap()),
+            ..policy()
+        };
+        assert_eq!(reason(None, "v0.7.6", &minimum), UpdateReason::NotInstalled);
+        assert!(needs_update(None, &parse_tag("v0.7.4").unwrap(), &minimum).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Error, Result};
+use lapce_plugin::psp_types::lsp_types::InitializeParams;
+use serde_json::{Map, Value};
+
+use crate::{archive, platform, server};
+
+/// Project config checked in at the workspace root
+pub const CONFIG_FILE: &str = ".lapce-dlang.json";
+
+/// Root directory of the workspace, the first workspace folder if Lapce
+/// didn't send a root URI
+pub fn workspace_root(params: &InitializeParams) -> Option<PathBuf> {
+    let root = params.root_uri.as_ref().or_else(|| {
+        params
+            .workspace_folders
+            .as_ref()?
+            .first()
+            .map(|folder| &folder.uri)
+    })?;
+    platform::uri_to_path(root.as_str()).ok()
+}
+
+/// Read the project config of the workspace at `root`, if there is one
+pub fn read_config(root: &Path) -> Result<Option<Value>> {
+    let path = root.join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// Keys of the `lsp` block a project config may set
+///
+/// The config comes with the checked out repository, so it only gets to
+/// shape how serve-d sees the project. Anything deciding what gets
+/// downloaded from where, or what runs, stays with the user's own options.
+pub const CONFIG_KEYS: &[&str] = &[
+    "serverSettings",
+    "importPaths",
+    "minVersion",
+    "allowedVersions",
+    "majorVersion",
+];
+
+/// serve-d settings a project config may set in `serverSettings`, either a
+/// whole section or a single `section.key`
+///
+/// Settings naming programs serve-d runs, like `d.dubPath` or
+/// `d.dcdServerPath`, stay with the user's own options as well.
+pub const SERVER_SETTINGS: &[&str] = &[
+    "d.projectImportPaths",
+    "d.dubConfiguration",
+    "d.dubArchType",
+    "d.dubBuildType",
+    "dfmt",
+    "dscanner.ignoredKeys",
+];
+
+/// Keep the [`SERVER_SETTINGS`] of the project `serverSettings`, also
+/// returning the names of the settings left out
+fn allowed_server_settings(settings: Value) -> (Map<String, Value>, Vec<String>) {
+    let mut allowed = Map::new();
+    let mut ignored = Vec::new();
+    let sections = match settings {
+        Value::Object(sections) => sections,
+        _ => return (allowed, vec!["serverSettings".to_string()]),
+    };
+
+    for (section, value) in sections {
+        if SERVER_SETTINGS.contains(&section.as_str()) {
+            allowed.insert(section, value);
+            continue;
+        }
+
+        let mut kept = Map::new();
+        match value {
+            Value::Object(keys) => {
+                for (key, value) in keys {
+                    let name = format!("{}.{}", section, key);
+                    if SERVER_SETTINGS.contains(&name.as_str()) {
+                        kept.insert(key, value);
+                    } else {
+                        ignored.push(format!("serverSettings.{}", name));
+                    }
+                }
+            }
+            _ => ignored.push(format!("serverSettings.{}", section)),
+        }
+        if !kept.is_empty() {
+            allowed.insert(section, Value::Object(kept));
+        }
+    }
+
+    (allowed, ignored)
+}
+
+/// Keep the [`CONFIG_KEYS`] of a project `config`, and of its
+/// `serverSettings` only the [`SERVER_SETTINGS`], also returning the names
+/// of the keys left out
+pub fn allowed_config(config: Value) -> Result<(Value, Vec<String>)> {
+    let config = match config {
+        Value::Object(config) => config,
+        _ => return Err(Error::msg("The project config must be an object")),
+    };
+
+    let (mut allowed, ignored): (Map<String, Value>, Map<String, Value>) = config
+        .into_iter()
+        .partition(|(key, _)| CONFIG_KEYS.contains(&key.as_str()));
+    let mut ignored: Vec<String> = ignored.into_iter().map(|(key, _)| key).collect();
+    if let Some(settings) = allowed.remove("serverSettings") {
+        let (settings, ignored_settings) = allowed_server_settings(settings);
+        allowed.insert("serverSettings".to_string(), Value::Object(settings));
+        ignored.extend(ignored_settings);
+    }
+    Ok((Value::Object(allowed), ignored))
+}
+
+/// Merge the project `config`, holding keys of the `lsp` block, under the
+/// initialization options, so options configured in Lapce win
+pub fn merge_config(options: Option<Value>, config: Value) -> Option<Value> {
+    let mut base = Map::new();
+    base.insert("lsp".to_string(), config);
+
+    Some(match options {
+        Some(options) => server::deep_merge(Value::Object(base), options),
+        None => Value::Object(base),
+    })
+}
+
+/// Whether a path component holds glob characters
+fn is_glob(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+/// Expand import path patterns against the workspace `root`
+///
+/// `*` and `?` match within a single path component, e.g.
+/// `subprojects/*/source`, and only existing directories are taken. Paths
+/// without glob characters are passed on as they are, as is the root of
+/// absolute patterns like `/opt/d/*/import` or `C:\d\*\import`.
+pub fn expand_import_paths(root: Option<&Path>, patterns: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        if !is_glob(pattern) {
+            paths.push(pattern.clone());
+            continue;
+        }
+
+        let mut start = match root {
+            Some(root) if Path::new(pattern).is_relative() => root.to_path_buf(),
+            Some(_) | None => PathBuf::new(),
+        };
+        // The root and a Windows prefix are kept as they are, only the
+        // components after them are matched
+        let mut components = Vec::new();
+        for component in Path::new(pattern).components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => start.push(component),
+                Component::CurDir => {}
+                component => components.push(component.as_os_str().to_string_lossy()),
+            }
+        }
+
+        let mut matches = vec![start];
+        for component in components
+            .iter()
+            .flat_map(|component| component.split('\\'))
+            .filter(|part| !part.is_empty())
+        {
+            matches = matches
+                .into_iter()
+                .flat_map(|dir| expand_component(&dir, component))
+                .collect();
+        }
+
+        let mut matches: Vec<String> = matches
+            .into_iter()
+            .filter(|path| path.is_dir())
+            .map(|path| path.display().to_string())
+            .collect();
+        matches.sort();
+        paths.extend(matches);
+    }
+
+    paths
+}
+
+/// Entries of `dir` matching one pattern component
+fn expand_component(dir: &Path, component: &str) -> Vec<PathBuf> {
+    if !is_glob(component) {
+        return vec![dir.join(component)];
+    }
+
+    let read_dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let entries = match fs::read_dir(read_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| archive::glob_match(component, &entry.file_name().to_string_lossy()))
+        .map(|entry| dir.join(entry.file_name()))
+        .collect()
+}
+
+/// serve-d settings for the expanded import paths
+pub fn import_path_settings(root: Option<&Path>, patterns: &[String]) -> Value {
+    let mut settings = Map::new();
+    if !patterns.is_empty() {
+        let paths = expand_import_paths(root, patterns);
+        let mut d = Map::new();
+        d.insert(
+            "projectImportPaths".to_string(),
+            Value::Array(paths.into_iter().map(Value::String).collect()),
+        );
+        settings.insert("d".to_string(), Value::Object(d));
+    }
+    Value::Object(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::TestDir;
+
+    /// Workspace with `subprojects/{a,b}/source` and a `subprojects/c`
+    /// without sources
+    fn workspace(name: &str) -> TestDir {
+        let root = TestDir::new(name);
+        for dir in ["a/source", "b/source", "c"] {
+            fs::create_dir_all(root.join("subprojects").join(dir)).unwrap();
+        }
+        root
+    }
+
+    fn source_dirs(root: &Path) -> Vec<String> {
+        ["a", "b"]
+            .iter()
+            .map(|dir| {
+                root.join("subprojects")
+                    .join(dir)
+                    .join("source")
+                    .display()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expands_relative_patterns_against_the_root() {
+        let root = workspace("relative-import-paths");
+        let patterns = vec!["subprojects/*/source".to_string(), "source".to_string()];
+        let mut expected = source_dirs(&root);
+        expected.push("source".to_string());
+        assert_eq!(expand_import_paths(Some(&root), &patterns), expected);
+    }
+
+    #[test]
+    fn keeps_the_root_of_absolute_patterns() {
+        let root = workspace("absolute-import-paths");
+        let pattern = root.join("subprojects").join("*").join("source");
+        let patterns = vec![pattern.display().to_string()];
+        assert_eq!(expand_import_paths(None, &patterns), source_dirs(&root));
+        let elsewhere = std::env::temp_dir();
+        assert_eq!(
+            expand_import_paths(Some(&elsewhere), &patterns),
+            source_dirs(&root)
+        );
+    }
+
+    #[test]
+    fn allowed_config_keeps_project_keys() {
+        let config = json!({
+            "importPaths": ["source"],
+            "minVersion": "0.7.4",
+            "serverPath": "/tmp/evil",
+            "postInstallCommand": ["sh", "-c", "true"],
+        });
+        let (config, mut ignored) = allowed_config(config).unwrap();
+        ignored.sort();
+        assert_eq!(
+            config,
+            json!({ "importPaths": ["source"], "minVersion": "0.7.4" })
+        );
+        assert_eq!(ignored, vec!["postInstallCommand", "serverPath"]);
+    }
+
+    #[test]
+    fn allowed_config_keeps_only_safe_server_settings() {
+        let config = json!({
+            "serverSettings": {
+                "d": {
+                    "projectImportPaths": ["source"],
+                    "dubPath": "/tmp/evil/dub",
+                    "dcdServerPath": "/tmp/evil/dcd-server",
+                },
+                "dfmt": { "braceStyle": "otbs" },
+                "editor": { "rulers": [120] },
+            },
+        });
+        let (config, mut ignored) = allowed_config(config).unwrap();
+        ignored.sort();
+        assert_eq!(
+            config,
+            json!({
+                "serverSettings": {
+                    "d": { "projectImportPaths": ["source"] },
+                    "dfmt": { "braceStyle": "otbs" },
+                },
+            })
+        );
+        assert_eq!(
+            ignored,
+            vec![
+                "serverSettings.d.dcdServerPath",
+                "serverSettings.d.dubPath",
+                "serverSettings.editor.rulers",
+            ]
+        );
+
+        let (config, ignored) = allowed_config(json!({ "serverSettings": "d" })).unwrap();
+        assert_eq!(config, json!({ "serverSettings": {} }));
+        assert_eq!(ignored, vec!["serverSettings"]);
+    }
+
+    #[test]
+    fn allowed_config_rejects_other_values() {
+        assert!(allowed_config(json!(["importPaths"])).is_err());
+    }
+
+    #[test]
+    fn user_options_override_the_project_config() {
+        let config = json!({ "importPaths": ["source"], "majorVersion": 0 });
+        let options = json!({ "lsp": { "majorVersion": 1 }, "d": {} });
+        assert_eq!(
+            merge_config(Some(options), config.clone()),
+            Some(json!({
+                "lsp": { "importPaths": ["source"], "majorVersion": 1 },
+                "d": {},
+            }))
+        );
+        assert_eq!(
+            merge_config(None, config.clone()),
+            Some(json!({ "lsp": config }))
+        );
+    }
+}